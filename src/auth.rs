@@ -0,0 +1,200 @@
+use deku::prelude::*;
+use subtle::ConstantTimeEq;
+
+use crate::consts::*;
+use crate::error::Error;
+use crate::keys::prf;
+use crate::sig::{SignatureAlgorithm, SigningKey, VerifyingKey};
+use crate::transform;
+use crate::types::IdPayload;
+
+/// Authentication payload body
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(endian = "big")]
+pub struct AuthPayload {
+    pub auth_method: AuthMethod,
+    pub reserved: [u8; 3],
+    #[deku(read_all)]
+    pub data: Vec<u8>,
+}
+
+/// The octets one side authenticates (RFC 7296 §2.15):
+/// its own IKE_SA_INIT message, the peer's nonce and prf(SK_p, RestOfIDPayload),
+/// where SK_p is SK_pi and ID is IDi for the initiator, SK_pr and IDr for the
+/// responder.
+pub fn signed_octets(
+    prf: &transform::PRF,
+    sk_p: &[u8],
+    message: &[u8],
+    peer_nonce: &[u8],
+    id: &IdPayload,
+) -> Result<Vec<u8>, Error> {
+    let mac = self::prf(prf, sk_p, &id.to_bytes()?)?;
+    Ok([message, peer_nonce, &mac].concat())
+}
+
+/// AUTH = prf( prf(Shared Secret, "Key Pad for IKEv2"), <SignedOctets>)
+pub fn psk_auth(
+    prf: &transform::PRF,
+    shared_secret: &[u8],
+    signed_octets: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let key = self::prf(prf, shared_secret, b"Key Pad for IKEv2")?;
+    self::prf(prf, &key, signed_octets)
+}
+
+impl AuthPayload {
+    /// A Shared Key Message Integrity Code over `signed_octets`.
+    pub fn psk(
+        prf: &transform::PRF,
+        shared_secret: &[u8],
+        signed_octets: &[u8],
+    ) -> Result<Self, Error> {
+        Ok(AuthPayload {
+            auth_method: AuthMethod::SHARED_KEY_MESSAGE_INTEGRITY_CODE,
+            reserved: [0; 3],
+            data: psk_auth(prf, shared_secret, signed_octets)?,
+        })
+    }
+
+    /// Checks a Shared Key Message Integrity Code, failing with
+    /// AUTHENTICATION_FAILED for any other method or a wrong value.
+    pub fn verify_psk(
+        &self,
+        prf: &transform::PRF,
+        shared_secret: &[u8],
+        signed_octets: &[u8],
+    ) -> Result<(), Error> {
+        if self.auth_method != AuthMethod::SHARED_KEY_MESSAGE_INTEGRITY_CODE {
+            return Err(Error::Notify(NotifyType::AUTHENTICATION_FAILED));
+        }
+        let expected = psk_auth(prf, shared_secret, signed_octets)?;
+        if !bool::from(expected.ct_eq(&self.data)) {
+            return Err(Error::Notify(NotifyType::AUTHENTICATION_FAILED));
+        }
+        Ok(())
+    }
+
+    /// A Digital Signature (RFC 7427) over `signed_octets`, prefixed with the
+    /// length and DER encoding of its AlgorithmIdentifier.
+    pub fn sign(
+        key: &SigningKey,
+        algorithm: &SignatureAlgorithm,
+        signed_octets: &[u8],
+    ) -> Result<Self, Error> {
+        let algorithm_identifier = algorithm.to_der()?;
+        let length = u8::try_from(algorithm_identifier.len())
+            .map_err(|_| Error::InvalidAlgorithmIdentifier)?;
+        let signature = key.sign(algorithm, signed_octets)?;
+        Ok(AuthPayload {
+            auth_method: AuthMethod::DIGITAL_SIGNATURE,
+            reserved: [0; 3],
+            data: [&[length][..], &algorithm_identifier, &signature].concat(),
+        })
+    }
+
+    /// Splits a Digital Signature into its algorithm and signature value.
+    pub fn signature(&self) -> Result<(SignatureAlgorithm, &[u8]), Error> {
+        let (&length, data) = self
+            .data
+            .split_first()
+            .ok_or(Error::InvalidAlgorithmIdentifier)?;
+        let (algorithm_identifier, signature) = data
+            .split_at_checked(usize::from(length))
+            .ok_or(Error::InvalidAlgorithmIdentifier)?;
+        Ok((
+            SignatureAlgorithm::from_der(algorithm_identifier)?,
+            signature,
+        ))
+    }
+
+    /// Checks a Digital Signature, failing with AUTHENTICATION_FAILED for any
+    /// other method, an unusable algorithm or a wrong signature.
+    pub fn verify(&self, key: &VerifyingKey, signed_octets: &[u8]) -> Result<(), Error> {
+        if self.auth_method != AuthMethod::DIGITAL_SIGNATURE {
+            return Err(Error::Notify(NotifyType::AUTHENTICATION_FAILED));
+        }
+        let (algorithm, signature) = self
+            .signature()
+            .map_err(|_| Error::Notify(NotifyType::AUTHENTICATION_FAILED))?;
+        key.verify(&algorithm, signed_octets, signature)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::hex;
+    use crate::types::Identity;
+
+    const IKE_SA_INIT: &[u8] = include_bytes!("testdata/ike_sa_init.bin");
+
+    #[test]
+    fn test_psk_auth() {
+        let prf = transform::PRF::PRF_HMAC_SHA2_256;
+        let nr: Vec<u8> = (0x20..0x40).collect();
+        let sk_pi: Vec<u8> = (0x60..0x80).collect();
+        let idi = IdPayload::from(Identity::Fqdn("initiator.example.com".to_string()));
+
+        let octets = signed_octets(&prf, &sk_pi, IKE_SA_INIT, &nr, &idi).unwrap();
+        assert_eq!(octets[..IKE_SA_INIT.len()], *IKE_SA_INIT);
+        assert_eq!(octets[IKE_SA_INIT.len()..][..32], nr);
+        // prf(SK_pi, IDi'), from Python's hmac module
+        assert_eq!(
+            octets[IKE_SA_INIT.len() + 32..],
+            hex("24f4bf85fa68e6c51752980ea5e9662cd73fba98bef5bcb41470cf69ef2b21c3")
+        );
+
+        // prf(prf("secret", "Key Pad for IKEv2"), octets), from Python's hmac module
+        let auth = AuthPayload::psk(&prf, b"secret", &octets).unwrap();
+        assert_eq!(
+            auth.data,
+            hex("630c2f8dd04c2ff225349c1061697c15b78573948f7addfbffbf5c59e975c41e")
+        );
+        let bytes = auth.to_bytes().unwrap();
+        assert_eq!(bytes[..4], [2, 0, 0, 0]);
+        assert_eq!(AuthPayload::try_from(&bytes[..]), Ok(auth.clone()));
+
+        assert_eq!(auth.verify_psk(&prf, b"secret", &octets), Ok(()));
+        assert_eq!(
+            auth.verify_psk(&prf, b"wrong", &octets),
+            Err(Error::Notify(NotifyType::AUTHENTICATION_FAILED))
+        );
+        assert_eq!(
+            auth.verify_psk(&prf, b"secret", &octets[1..]),
+            Err(Error::Notify(NotifyType::AUTHENTICATION_FAILED))
+        );
+        let mut rsa = auth;
+        rsa.auth_method = AuthMethod::RSA_DIGITAL_SIGNATURE;
+        assert_eq!(
+            rsa.verify_psk(&prf, b"secret", &octets),
+            Err(Error::Notify(NotifyType::AUTHENTICATION_FAILED))
+        );
+    }
+
+    #[test]
+    fn test_signature_auth() {
+        use rsa::pkcs8::DecodePrivateKey;
+
+        let key = SigningKey::from_pkcs8_der(include_bytes!("testdata/keys/p256.der")).unwrap();
+        let algorithm = SignatureAlgorithm::Ecdsa(HashAlgorithm::SHA2_256);
+        let auth = AuthPayload::sign(&key, &algorithm, IKE_SA_INIT).unwrap();
+        assert_eq!(auth.auth_method, AuthMethod::DIGITAL_SIGNATURE);
+        assert_eq!(auth.data[..13], hex("0c300a06082a8648ce3d040302"));
+        assert_eq!(auth.signature().unwrap().0, algorithm);
+        assert_eq!(auth.verify(&key.verifying_key(), IKE_SA_INIT), Ok(()));
+
+        let failed = Err(Error::Notify(NotifyType::AUTHENTICATION_FAILED));
+        assert_eq!(auth.verify(&key.verifying_key(), &IKE_SA_INIT[1..]), failed);
+        let mut truncated = auth.clone();
+        truncated.data.truncate(8);
+        assert_eq!(
+            truncated.signature(),
+            Err(Error::InvalidAlgorithmIdentifier)
+        );
+        assert_eq!(truncated.verify(&key.verifying_key(), IKE_SA_INIT), failed);
+        let mut psk = auth;
+        psk.auth_method = AuthMethod::SHARED_KEY_MESSAGE_INTEGRITY_CODE;
+        assert_eq!(psk.verify(&key.verifying_key(), IKE_SA_INIT), failed);
+    }
+}
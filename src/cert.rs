@@ -0,0 +1,209 @@
+use deku::prelude::*;
+use sha1::{Digest, Sha1};
+use x509_cert::Certificate;
+use x509_cert::der::{Decode, Encode};
+
+use crate::consts::*;
+use crate::error::Error;
+
+/// Length of a SHA-1 hash in CERTREQ and Hash and URL encodings.
+pub const HASH_LENGTH: usize = 20;
+
+/// Certificate payload body
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(endian = "big")]
+pub struct CertPayload {
+    pub cert_encoding: CertificateEncoding,
+    #[deku(read_all)]
+    pub data: Vec<u8>,
+}
+
+/// Certificate Request payload body
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(endian = "big")]
+pub struct CertReqPayload {
+    pub cert_encoding: CertificateEncoding,
+    #[deku(read_all)]
+    pub certification_authority: Vec<u8>,
+}
+
+/// SHA-1 hash of the certificate's DER encoded SubjectPublicKeyInfo, by
+/// which CERTREQ names a trusted CA.
+pub fn spki_hash(certificate: &Certificate) -> Result<[u8; HASH_LENGTH], Error> {
+    let spki = certificate
+        .tbs_certificate
+        .subject_public_key_info
+        .to_der()
+        .map_err(|_| Error::InvalidCertificate)?;
+    Ok(Sha1::digest(spki).into())
+}
+
+/// The first of the local CAs named by the peer's certificate requests,
+/// taking the requests and their hashes in order of preference.
+pub fn matching_ca<'a>(
+    requests: &[CertReqPayload],
+    cas: &'a [Certificate],
+) -> Result<Option<&'a Certificate>, Error> {
+    let hashes = cas.iter().map(spki_hash).collect::<Result<Vec<_>, _>>()?;
+    for request in requests {
+        for hash in request.ca_hashes()? {
+            if let Some(i) = hashes.iter().position(|h| *h == hash) {
+                return Ok(Some(&cas[i]));
+            }
+        }
+    }
+    Ok(None)
+}
+
+impl CertPayload {
+    /// An X.509 Certificate - Signature payload.
+    pub fn x509(certificate: &Certificate) -> Result<Self, Error> {
+        Ok(CertPayload {
+            cert_encoding: CertificateEncoding::X509_CERTIFICATE_SIGNATURE,
+            data: certificate
+                .to_der()
+                .map_err(|_| Error::InvalidCertificate)?,
+        })
+    }
+
+    /// Decodes an X.509 Certificate - Signature payload.
+    pub fn certificate(&self) -> Result<Certificate, Error> {
+        if self.cert_encoding != CertificateEncoding::X509_CERTIFICATE_SIGNATURE {
+            return Err(Error::InvalidCertificate);
+        }
+        Certificate::from_der(&self.data).map_err(|_| Error::InvalidCertificate)
+    }
+
+    /// Splits a Hash and URL payload into the SHA-1 hash of the certificate
+    /// or bundle and its URL.
+    pub fn hash_and_url(&self) -> Result<([u8; HASH_LENGTH], &str), Error> {
+        let invalid = || Error::Notify(NotifyType::INVALID_SYNTAX);
+        if !matches!(
+            self.cert_encoding,
+            CertificateEncoding::HASH_AND_URL_OF_X509_CERTIFICATE
+                | CertificateEncoding::HASH_AND_URL_OF_X509_BUNDLE
+        ) {
+            return Err(invalid());
+        }
+        let (hash, url) = self.data.split_first_chunk().ok_or_else(invalid)?;
+        Ok((*hash, std::str::from_utf8(url).map_err(|_| invalid())?))
+    }
+}
+
+impl CertReqPayload {
+    /// An X.509 Certificate - Signature request for any of the given CAs.
+    pub fn x509<'a>(cas: impl IntoIterator<Item = &'a Certificate>) -> Result<Self, Error> {
+        let mut certification_authority = vec![];
+        for ca in cas {
+            certification_authority.extend(spki_hash(ca)?);
+        }
+        Ok(CertReqPayload {
+            cert_encoding: CertificateEncoding::X509_CERTIFICATE_SIGNATURE,
+            certification_authority,
+        })
+    }
+
+    /// The SHA-1 SubjectPublicKeyInfo hashes of the CAs trusted by the peer.
+    /// Only the X.509 encodings list CAs this way; others yield none.
+    pub fn ca_hashes(&self) -> Result<Vec<[u8; HASH_LENGTH]>, Error> {
+        if !matches!(
+            self.cert_encoding,
+            CertificateEncoding::X509_CERTIFICATE_SIGNATURE
+                | CertificateEncoding::HASH_AND_URL_OF_X509_CERTIFICATE
+                | CertificateEncoding::HASH_AND_URL_OF_X509_BUNDLE
+        ) {
+            return Ok(vec![]);
+        }
+        let (hashes, rest) = self.certification_authority.as_chunks();
+        if !rest.is_empty() {
+            return Err(Error::Notify(NotifyType::INVALID_SYNTAX));
+        }
+        Ok(hashes.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::hex;
+
+    fn ca(der: &[u8]) -> Certificate {
+        Certificate::from_der(der).unwrap()
+    }
+
+    #[test]
+    fn test_cert_payload() {
+        let root = ca(include_bytes!("testdata/certs/root_ca.der"));
+        let cert = CertPayload::x509(&root).unwrap();
+        let bytes = cert.to_bytes().unwrap();
+        assert_eq!(bytes[0], 4);
+        assert_eq!(bytes[1..], include_bytes!("testdata/certs/root_ca.der")[..]);
+        assert_eq!(CertPayload::try_from(&bytes[..]), Ok(cert.clone()));
+        assert_eq!(cert.certificate(), Ok(root));
+        assert_eq!(
+            cert.hash_and_url(),
+            Err(Error::Notify(NotifyType::INVALID_SYNTAX))
+        );
+
+        let mut data = vec![0xab; 20];
+        data.extend(b"http://certs.example/vpn.der");
+        let hash_and_url = CertPayload::try_from(&[&[12][..], &data].concat()[..]).unwrap();
+        assert_eq!(
+            hash_and_url.cert_encoding,
+            CertificateEncoding::HASH_AND_URL_OF_X509_CERTIFICATE
+        );
+        assert_eq!(
+            hash_and_url.hash_and_url(),
+            Ok(([0xab; 20], "http://certs.example/vpn.der"))
+        );
+        assert_eq!(hash_and_url.certificate(), Err(Error::InvalidCertificate));
+    }
+
+    #[test]
+    fn test_cert_req_payload() {
+        // SHA-1 of each CA's SubjectPublicKeyInfo, from
+        // openssl x509 -pubkey | openssl pkey -pubin -outform DER | openssl sha1
+        let root = ca(include_bytes!("testdata/certs/root_ca.der"));
+        let other = ca(include_bytes!("testdata/certs/other_ca.der"));
+        let root_hash = hex("64cbd04890c5dde0ab904cf7edd1d687c52b0a5d");
+        let other_hash = hex("39dea0ff19cb6d7d94bb93da71eb7ebc27381410");
+        assert_eq!(spki_hash(&root).unwrap()[..], root_hash);
+        assert_eq!(spki_hash(&other).unwrap()[..], other_hash);
+
+        let request = CertReqPayload::x509([&other, &root]).unwrap();
+        let bytes = request.to_bytes().unwrap();
+        assert_eq!(bytes, [&[4][..], &other_hash, &root_hash].concat());
+        let request = CertReqPayload::try_from(&bytes[..]).unwrap();
+        assert_eq!(
+            request.ca_hashes(),
+            Ok(vec![spki_hash(&other).unwrap(), spki_hash(&root).unwrap()])
+        );
+
+        // the peer's preference decides between two matching CAs
+        let cas = [root.clone(), other.clone()];
+        assert_eq!(
+            matching_ca(std::slice::from_ref(&request), &cas),
+            Ok(Some(&other))
+        );
+        let root_only = CertReqPayload::x509([&root]).unwrap();
+        let requests = [CertReqPayload::x509([]).unwrap(), root_only.clone()];
+        assert_eq!(matching_ca(&requests, &cas), Ok(Some(&root)));
+        assert_eq!(matching_ca(&[root_only], &cas[1..]), Ok(None));
+
+        let raw = CertReqPayload {
+            cert_encoding: CertificateEncoding::RAW_PUBLIC_KEY,
+            certification_authority: vec![],
+        };
+        assert_eq!(raw.ca_hashes(), Ok(vec![]));
+        let mut truncated = request;
+        truncated.certification_authority.pop();
+        assert_eq!(
+            truncated.ca_hashes(),
+            Err(Error::Notify(NotifyType::INVALID_SYNTAX))
+        );
+        assert_eq!(
+            matching_ca(&[truncated], &cas),
+            Err(Error::Notify(NotifyType::INVALID_SYNTAX))
+        );
+    }
+}
@@ -0,0 +1,372 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use deku::prelude::*;
+
+use crate::consts::*;
+use crate::error::Error;
+
+/// Configuration payload body
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(endian = "big")]
+pub struct ConfigPayload {
+    pub cfg_type: CfgType,
+    pub reserved: [u8; 3],
+    #[deku(read_all)]
+    pub attributes: Vec<ConfigAttribute>,
+}
+
+/// Configuration Attribute
+#[deku_derive(DekuRead, DekuWrite)]
+#[derive(Clone, Debug, PartialEq)]
+#[deku(endian = "endian", ctx = "endian: deku::ctx::Endian")]
+pub struct ConfigAttribute {
+    #[deku(bits = 1)]
+    pub reserved: bool,
+    pub attribute_type: ConfigAttributeType,
+    #[deku(temp, temp_value = "u16::try_from(value.len())?")]
+    length: u16,
+    #[deku(count = "length")]
+    pub value: Vec<u8>,
+}
+
+/// Decoded Configuration Attribute value
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigValue {
+    /// No value, as in a CFG_REQUEST for any value or a CFG_ACK
+    Empty,
+    Ipv4(Ipv4Addr),
+    /// INTERNAL_IP4_SUBNET
+    Ipv4Subnet {
+        address: Ipv4Addr,
+        netmask: Ipv4Addr,
+    },
+    Ipv6(Ipv6Addr),
+    /// INTERNAL_IP6_ADDRESS, INTERNAL_IP6_SUBNET and INTERNAL_IP6_PREFIX
+    Ipv6Prefix {
+        address: Ipv6Addr,
+        prefix_len: u8,
+    },
+    /// APPLICATION_VERSION and INTERNAL_DNS_DOMAIN
+    Text(String),
+    SupportedAttributes(Vec<ConfigAttributeType>),
+    Other(Vec<u8>),
+}
+
+/// Attribute Type list entry of SUPPORTED_ATTRIBUTES
+#[derive(DekuRead, DekuWrite)]
+#[deku(endian = "big")]
+struct SupportedAttribute {
+    #[deku(bits = 1)]
+    reserved: bool,
+    attribute_type: ConfigAttributeType,
+}
+
+impl ConfigPayload {
+    pub fn new(cfg_type: CfgType, attributes: Vec<ConfigAttribute>) -> Self {
+        ConfigPayload {
+            cfg_type,
+            reserved: [0; 3],
+            attributes,
+        }
+    }
+
+    /// A CFG_REQUEST asking for any value of each attribute type.
+    pub fn request(attribute_types: impl IntoIterator<Item = ConfigAttributeType>) -> Self {
+        let attributes = attribute_types
+            .into_iter()
+            .map(|attribute_type| ConfigAttribute {
+                reserved: false,
+                attribute_type,
+                value: vec![],
+            })
+            .collect();
+        Self::new(CfgType::CFG_REQUEST, attributes)
+    }
+
+    /// The decoded values of every attribute of the type, in order, such as
+    /// all assigned DNS servers.
+    pub fn values(&self, attribute_type: &ConfigAttributeType) -> Result<Vec<ConfigValue>, Error> {
+        self.attributes
+            .iter()
+            .filter(|a| a.attribute_type == *attribute_type)
+            .map(ConfigAttribute::value)
+            .collect()
+    }
+}
+
+impl ConfigAttribute {
+    /// Encodes `value` as an attribute of the type, failing if the value
+    /// does not have the form the type requires.
+    pub fn new(attribute_type: ConfigAttributeType, value: ConfigValue) -> Result<Self, Error> {
+        let data = match &value {
+            ConfigValue::Empty => vec![],
+            ConfigValue::Ipv4(address) => address.octets().to_vec(),
+            ConfigValue::Ipv4Subnet { address, netmask } => {
+                [address.octets(), netmask.octets()].concat()
+            }
+            ConfigValue::Ipv6(address) => address.octets().to_vec(),
+            ConfigValue::Ipv6Prefix {
+                address,
+                prefix_len,
+            } => [&address.octets()[..], &[*prefix_len]].concat(),
+            ConfigValue::Text(text) => text.as_bytes().to_vec(),
+            ConfigValue::SupportedAttributes(types) => {
+                let mut data = vec![];
+                for attribute_type in types {
+                    let entry = SupportedAttribute {
+                        reserved: false,
+                        attribute_type: attribute_type.clone(),
+                    };
+                    data.extend(entry.to_bytes()?);
+                }
+                data
+            }
+            ConfigValue::Other(data) => data.clone(),
+        };
+        let attribute = ConfigAttribute {
+            reserved: false,
+            attribute_type,
+            value: data,
+        };
+        if attribute.value()? != value {
+            return Err(Error::InvalidConfigAttribute(attribute.attribute_type));
+        }
+        Ok(attribute)
+    }
+
+    /// Decodes the value according to the attribute type. Empty values are
+    /// valid for every type; types without a typed form are `Other`.
+    pub fn value(&self) -> Result<ConfigValue, Error> {
+        use ConfigAttributeType::*;
+
+        let invalid = || Error::InvalidConfigAttribute(self.attribute_type.clone());
+        let data = &self.value[..];
+        if data.is_empty() {
+            return Ok(ConfigValue::Empty);
+        }
+        Ok(match self.attribute_type {
+            INTERNAL_IP4_ADDRESS | INTERNAL_IP4_NETMASK | INTERNAL_IP4_DNS | INTERNAL_IP4_NBNS
+            | INTERNAL_IP4_DHCP | P_CSCF_IP4_ADDRESS => {
+                ConfigValue::Ipv4(<[u8; 4]>::try_from(data).map_err(|_| invalid())?.into())
+            }
+            INTERNAL_IP4_SUBNET => {
+                let [a, b, c, d, e, f, g, h] = <[u8; 8]>::try_from(data).map_err(|_| invalid())?;
+                ConfigValue::Ipv4Subnet {
+                    address: Ipv4Addr::new(a, b, c, d),
+                    netmask: Ipv4Addr::new(e, f, g, h),
+                }
+            }
+            INTERNAL_IP6_DNS | INTERNAL_IP6_DHCP | P_CSCF_IP6_ADDRESS => {
+                ConfigValue::Ipv6(<[u8; 16]>::try_from(data).map_err(|_| invalid())?.into())
+            }
+            INTERNAL_IP6_ADDRESS | INTERNAL_IP6_SUBNET | INTERNAL_IP6_PREFIX => {
+                let (address, &[prefix_len]) =
+                    data.split_first_chunk::<16>().ok_or_else(invalid)?
+                else {
+                    return Err(invalid());
+                };
+                if prefix_len > 128 {
+                    return Err(invalid());
+                }
+                ConfigValue::Ipv6Prefix {
+                    address: (*address).into(),
+                    prefix_len,
+                }
+            }
+            APPLICATION_VERSION | INTERNAL_DNS_DOMAIN => ConfigValue::Text(
+                std::str::from_utf8(data)
+                    .map_err(|_| invalid())?
+                    .to_string(),
+            ),
+            SUPPORTED_ATTRIBUTES => {
+                let (entries, rest) = data.as_chunks::<2>();
+                if !rest.is_empty() {
+                    return Err(invalid());
+                }
+                let types = entries
+                    .iter()
+                    .map(|entry| SupportedAttribute::try_from(&entry[..]).map(|e| e.attribute_type))
+                    .collect::<Result<_, _>>()?;
+                ConfigValue::SupportedAttributes(types)
+            }
+            _ => ConfigValue::Other(data.to_vec()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_config_reply() {
+        // CFG_REPLY with a virtual IPv4 address, netmask, two DNS servers,
+        // a virtual IPv6 address and a split DNS domain
+        let data = [
+            &[2, 0, 0, 0][..],
+            &[0, 1, 0, 4, 10, 0, 0, 7],
+            &[0, 2, 0, 4, 255, 255, 255, 0],
+            &[0, 3, 0, 4, 192, 0, 2, 53],
+            &[0, 3, 0, 4, 192, 0, 2, 54],
+            &[0, 8, 0, 17],
+            &"2001:db8::7".parse::<Ipv6Addr>().unwrap().octets(),
+            &[64],
+            &[0, 25, 0, 11],
+            b"example.com",
+        ]
+        .concat();
+        let cp = ConfigPayload::try_from(&data[..]).unwrap();
+        assert_eq!(cp.cfg_type, CfgType::CFG_REPLY);
+        assert_eq!(cp.attributes.len(), 6);
+        assert_eq!(
+            cp.values(&ConfigAttributeType::INTERNAL_IP4_ADDRESS),
+            Ok(vec![ConfigValue::Ipv4(Ipv4Addr::new(10, 0, 0, 7))])
+        );
+        assert_eq!(
+            cp.values(&ConfigAttributeType::INTERNAL_IP4_NETMASK),
+            Ok(vec![ConfigValue::Ipv4(Ipv4Addr::new(255, 255, 255, 0))])
+        );
+        assert_eq!(
+            cp.values(&ConfigAttributeType::INTERNAL_IP4_DNS),
+            Ok(vec![
+                ConfigValue::Ipv4(Ipv4Addr::new(192, 0, 2, 53)),
+                ConfigValue::Ipv4(Ipv4Addr::new(192, 0, 2, 54)),
+            ])
+        );
+        assert_eq!(
+            cp.values(&ConfigAttributeType::INTERNAL_IP6_ADDRESS),
+            Ok(vec![ConfigValue::Ipv6Prefix {
+                address: "2001:db8::7".parse().unwrap(),
+                prefix_len: 64,
+            }])
+        );
+        assert_eq!(
+            cp.values(&ConfigAttributeType::INTERNAL_DNS_DOMAIN),
+            Ok(vec![ConfigValue::Text("example.com".to_string())])
+        );
+        assert!(
+            cp.values(&ConfigAttributeType::INTERNAL_IP6_DNS)
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(cp.to_bytes().unwrap(), data);
+    }
+
+    #[test]
+    fn test_config_request() {
+        let cp = ConfigPayload::request([
+            ConfigAttributeType::INTERNAL_IP4_ADDRESS,
+            ConfigAttributeType::INTERNAL_IP6_DNS,
+            ConfigAttributeType::Private(0x4001),
+        ]);
+        let data = cp.to_bytes().unwrap();
+        assert_eq!(data, [1, 0, 0, 0, 0, 1, 0, 0, 0, 10, 0, 0, 0x40, 1, 0, 0]);
+        assert_eq!(ConfigPayload::try_from(&data[..]), Ok(cp.clone()));
+        for attribute in &cp.attributes {
+            assert_eq!(attribute.value(), Ok(ConfigValue::Empty));
+        }
+
+        // the reserved bit is not part of the attribute type
+        let cp = ConfigPayload::try_from(&[3, 0, 0, 0, 0x80, 7, 0, 1, b'x'][..]).unwrap();
+        assert!(cp.attributes[0].reserved);
+        assert_eq!(
+            cp.attributes[0].attribute_type,
+            ConfigAttributeType::APPLICATION_VERSION
+        );
+    }
+
+    #[test]
+    fn test_config_values() {
+        let cases = [
+            (
+                ConfigAttributeType::INTERNAL_IP4_SUBNET,
+                ConfigValue::Ipv4Subnet {
+                    address: Ipv4Addr::new(192, 0, 2, 0),
+                    netmask: Ipv4Addr::new(255, 255, 255, 0),
+                },
+                &[192, 0, 2, 0, 255, 255, 255, 0][..],
+            ),
+            (
+                ConfigAttributeType::INTERNAL_IP6_DNS,
+                ConfigValue::Ipv6(Ipv6Addr::LOCALHOST),
+                &Ipv6Addr::LOCALHOST.octets()[..],
+            ),
+            (
+                ConfigAttributeType::APPLICATION_VERSION,
+                ConfigValue::Text("ikev2 1.0".to_string()),
+                b"ikev2 1.0",
+            ),
+            (
+                ConfigAttributeType::SUPPORTED_ATTRIBUTES,
+                ConfigValue::SupportedAttributes(vec![
+                    ConfigAttributeType::INTERNAL_IP4_ADDRESS,
+                    ConfigAttributeType::INTERNAL_DNS_DOMAIN,
+                ]),
+                &[0, 1, 0, 25],
+            ),
+            (
+                ConfigAttributeType::TIMEOUT_PERIOD_FOR_LIVENESS_CHECK,
+                ConfigValue::Other(vec![0, 0, 0, 60]),
+                &[0, 0, 0, 60],
+            ),
+        ];
+        for (attribute_type, value, data) in cases {
+            let attribute = ConfigAttribute::new(attribute_type, value.clone()).unwrap();
+            assert_eq!(attribute.value, data);
+            assert_eq!(attribute.value(), Ok(value));
+        }
+
+        let invalid = [
+            (ConfigAttributeType::INTERNAL_IP4_ADDRESS, &[10, 0, 0][..]),
+            (ConfigAttributeType::INTERNAL_IP4_SUBNET, &[10, 0, 0, 0]),
+            (ConfigAttributeType::INTERNAL_IP6_DNS, &[0; 17]),
+            (ConfigAttributeType::INTERNAL_IP6_ADDRESS, &[0; 16]),
+            (ConfigAttributeType::INTERNAL_DNS_DOMAIN, &[0xff]),
+            (ConfigAttributeType::SUPPORTED_ATTRIBUTES, &[0, 1, 0]),
+        ];
+        for (attribute_type, data) in invalid {
+            let attribute = ConfigAttribute {
+                reserved: false,
+                attribute_type: attribute_type.clone(),
+                value: data.to_vec(),
+            };
+            assert_eq!(
+                attribute.value(),
+                Err(Error::InvalidConfigAttribute(attribute_type))
+            );
+        }
+        let mut too_long = [0; 17];
+        too_long[16] = 129;
+        let attribute = ConfigAttribute {
+            reserved: false,
+            attribute_type: ConfigAttributeType::INTERNAL_IP6_PREFIX,
+            value: too_long.to_vec(),
+        };
+        assert_eq!(
+            attribute.value(),
+            Err(Error::InvalidConfigAttribute(
+                ConfigAttributeType::INTERNAL_IP6_PREFIX
+            ))
+        );
+
+        // values that do not have the form of the type
+        assert_eq!(
+            ConfigAttribute::new(
+                ConfigAttributeType::INTERNAL_IP4_DNS,
+                ConfigValue::Ipv6(Ipv6Addr::LOCALHOST)
+            ),
+            Err(Error::InvalidConfigAttribute(
+                ConfigAttributeType::INTERNAL_IP4_DNS
+            ))
+        );
+        assert_eq!(
+            ConfigAttribute::new(
+                ConfigAttributeType::INTERNAL_IP4_ADDRESS,
+                ConfigValue::Other(vec![10, 0, 0, 1])
+            ),
+            Err(Error::InvalidConfigAttribute(
+                ConfigAttributeType::INTERNAL_IP4_ADDRESS
+            ))
+        );
+    }
+}
@@ -143,19 +143,19 @@ pub enum TransformType {
     #[deku(id = 5)]
     SN(u8, transform::SN),
     #[deku(id = 6)]
-    ADDKE1(u8, u16),
+    ADDKE1(u8, transform::KE),
     #[deku(id = 7)]
-    ADDKE2(u8, u16),
+    ADDKE2(u8, transform::KE),
     #[deku(id = 8)]
-    ADDKE3(u8, u16),
+    ADDKE3(u8, transform::KE),
     #[deku(id = 9)]
-    ADDKE4(u8, u16),
+    ADDKE4(u8, transform::KE),
     #[deku(id = 10)]
-    ADDKE5(u8, u16),
+    ADDKE5(u8, transform::KE),
     #[deku(id = 11)]
-    ADDKE6(u8, u16),
+    ADDKE6(u8, transform::KE),
     #[deku(id = 12)]
-    ADDKE7(u8, u16),
+    ADDKE7(u8, transform::KE),
     #[deku(id = 13)]
     KWA(u8, transform::KWA),
     #[deku(id = 14)]
@@ -166,6 +166,576 @@ pub enum TransformType {
     Private(u8, u8, u16),
 }
 
+impl TransformType {
+    /// The number n and method of an Additional Key Exchange n transform
+    /// (RFC 9370).
+    pub fn additional_key_exchange(&self) -> Option<(u8, &transform::KE)> {
+        match self {
+            TransformType::ADDKE1(_, ke) => Some((1, ke)),
+            TransformType::ADDKE2(_, ke) => Some((2, ke)),
+            TransformType::ADDKE3(_, ke) => Some((3, ke)),
+            TransformType::ADDKE4(_, ke) => Some((4, ke)),
+            TransformType::ADDKE5(_, ke) => Some((5, ke)),
+            TransformType::ADDKE6(_, ke) => Some((6, ke)),
+            TransformType::ADDKE7(_, ke) => Some((7, ke)),
+            _ => None,
+        }
+    }
+}
+
+/// IKEv2 Identification Payload ID Types
+/// Reference: https://www.iana.org/assignments/ikev2-parameters/ikev2-parameters.xhtml#ikev2-parameters-10
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, DekuRead, DekuWrite)]
+#[deku(id_type = "u8", endian = "endian", ctx = "endian: deku::ctx::Endian")]
+pub enum IdType {
+    #[deku(id = 0)]
+    Reserved,
+    #[deku(id = 1)]
+    ID_IPV4_ADDR,
+    #[deku(id = 2)]
+    ID_FQDN,
+    #[deku(id = 3)]
+    ID_RFC822_ADDR,
+    #[deku(id = 4)]
+    Unassigned0,
+    #[deku(id = 5)]
+    ID_IPV6_ADDR,
+    #[deku(id_pat = "6..=8")]
+    Unassigned1(u8),
+    #[deku(id = 9)]
+    ID_DER_ASN1_DN,
+    #[deku(id = 10)]
+    ID_DER_ASN1_GN,
+    #[deku(id = 11)]
+    ID_KEY_ID,
+    #[deku(id = 12)]
+    ID_FC_NAME,
+    #[deku(id = 13)]
+    ID_NULL,
+    #[deku(id_pat = "14..=200")]
+    Unassigned2(u8),
+    #[deku(id_pat = "201..=255")]
+    Private(u8),
+}
+
+/// IKEv2 Authentication Method
+/// Reference: https://www.iana.org/assignments/ikev2-parameters/ikev2-parameters.xhtml#ikev2-parameters-12
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(id_type = "u8", endian = "endian", ctx = "endian: deku::ctx::Endian")]
+pub enum AuthMethod {
+    #[deku(id = 0)]
+    Reserved,
+    #[deku(id = 1)]
+    RSA_DIGITAL_SIGNATURE,
+    #[deku(id = 2)]
+    SHARED_KEY_MESSAGE_INTEGRITY_CODE,
+    #[deku(id = 3)]
+    DSS_DIGITAL_SIGNATURE,
+    #[deku(id_pat = "4..=8")]
+    Unassigned0(u8),
+    #[deku(id = 9)]
+    ECDSA_SHA256_P256,
+    #[deku(id = 10)]
+    ECDSA_SHA384_P384,
+    #[deku(id = 11)]
+    ECDSA_SHA512_P521,
+    #[deku(id = 12)]
+    GENERIC_SECURE_PASSWORD_AUTHENTICATION_METHOD,
+    #[deku(id = 13)]
+    NULL_AUTHENTICATION,
+    #[deku(id = 14)]
+    DIGITAL_SIGNATURE,
+    #[deku(id_pat = "15..=200")]
+    Unassigned1(u8),
+    #[deku(id_pat = "201..=255")]
+    Private(u8),
+}
+
+/// IKEv2 Certificate Encodings
+/// Reference: https://www.iana.org/assignments/ikev2-parameters/ikev2-parameters.xhtml#ikev2-parameters-11
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(id_type = "u8", endian = "endian", ctx = "endian: deku::ctx::Endian")]
+pub enum CertificateEncoding {
+    #[deku(id = 0)]
+    Reserved0,
+    #[deku(id = 1)]
+    PKCS7_WRAPPED_X509_CERTIFICATE,
+    #[deku(id = 2)]
+    PGP_CERTIFICATE,
+    #[deku(id = 3)]
+    DNS_SIGNED_KEY,
+    #[deku(id = 4)]
+    X509_CERTIFICATE_SIGNATURE,
+    #[deku(id = 5)]
+    Reserved1,
+    #[deku(id = 6)]
+    KERBEROS_TOKEN,
+    #[deku(id = 7)]
+    CERTIFICATE_REVOCATION_LIST,
+    #[deku(id = 8)]
+    AUTHORITY_REVOCATION_LIST,
+    #[deku(id = 9)]
+    SPKI_CERTIFICATE,
+    #[deku(id = 10)]
+    X509_CERTIFICATE_ATTRIBUTE,
+    /// Deprecated
+    #[deku(id = 11)]
+    RAW_RSA_KEY,
+    #[deku(id = 12)]
+    HASH_AND_URL_OF_X509_CERTIFICATE,
+    #[deku(id = 13)]
+    HASH_AND_URL_OF_X509_BUNDLE,
+    #[deku(id = 14)]
+    OCSP_CONTENT,
+    #[deku(id = 15)]
+    RAW_PUBLIC_KEY,
+    #[deku(id_pat = "16..=200")]
+    Unassigned(u8),
+    #[deku(id_pat = "201..=255")]
+    Private(u8),
+}
+
+/// IKEv2 Configuration Payload CFG Types
+/// Reference: https://www.iana.org/assignments/ikev2-parameters/ikev2-parameters.xhtml#ikev2-parameters-20
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(id_type = "u8", endian = "endian", ctx = "endian: deku::ctx::Endian")]
+pub enum CfgType {
+    #[deku(id = 0)]
+    Reserved,
+    #[deku(id = 1)]
+    CFG_REQUEST,
+    #[deku(id = 2)]
+    CFG_REPLY,
+    #[deku(id = 3)]
+    CFG_SET,
+    #[deku(id = 4)]
+    CFG_ACK,
+    #[deku(id_pat = "5..=127")]
+    Unassigned(u8),
+    #[deku(id_pat = "128..=255")]
+    Private(u8),
+}
+
+/// IKEv2 Configuration Payload Attribute Types, the 15 bits following the
+/// reserved bit of a configuration attribute.
+/// Reference: https://www.iana.org/assignments/ikev2-parameters/ikev2-parameters.xhtml#ikev2-parameters-21
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(
+    id_type = "u16",
+    bits = 15,
+    id_endian = "endian",
+    endian = "endian",
+    ctx = "endian: deku::ctx::Endian"
+)]
+pub enum ConfigAttributeType {
+    #[deku(id_pat = "0 | 5 | 9 | 11")]
+    Reserved(u16),
+    #[deku(id = 1)]
+    INTERNAL_IP4_ADDRESS,
+    #[deku(id = 2)]
+    INTERNAL_IP4_NETMASK,
+    #[deku(id = 3)]
+    INTERNAL_IP4_DNS,
+    #[deku(id = 4)]
+    INTERNAL_IP4_NBNS,
+    #[deku(id = 6)]
+    INTERNAL_IP4_DHCP,
+    #[deku(id = 7)]
+    APPLICATION_VERSION,
+    #[deku(id = 8)]
+    INTERNAL_IP6_ADDRESS,
+    #[deku(id = 10)]
+    INTERNAL_IP6_DNS,
+    #[deku(id = 12)]
+    INTERNAL_IP6_DHCP,
+    #[deku(id = 13)]
+    INTERNAL_IP4_SUBNET,
+    #[deku(id = 14)]
+    SUPPORTED_ATTRIBUTES,
+    #[deku(id = 15)]
+    INTERNAL_IP6_SUBNET,
+    #[deku(id = 16)]
+    MIP6_HOME_PREFIX,
+    #[deku(id = 17)]
+    INTERNAL_IP6_LINK,
+    #[deku(id = 18)]
+    INTERNAL_IP6_PREFIX,
+    #[deku(id = 19)]
+    HOME_AGENT_ADDRESS,
+    #[deku(id = 20)]
+    P_CSCF_IP4_ADDRESS,
+    #[deku(id = 21)]
+    P_CSCF_IP6_ADDRESS,
+    #[deku(id = 22)]
+    FTT_KAT,
+    #[deku(id = 23)]
+    EXTERNAL_SOURCE_IP4_NAT_INFO,
+    #[deku(id = 24)]
+    TIMEOUT_PERIOD_FOR_LIVENESS_CHECK,
+    #[deku(id = 25)]
+    INTERNAL_DNS_DOMAIN,
+    #[deku(id = 26)]
+    INTERNAL_DNSSEC_TA,
+    #[deku(id = 27)]
+    ENCDNS_IP4,
+    #[deku(id = 28)]
+    ENCDNS_IP6,
+    #[deku(id = 29)]
+    ENCDNS_DIGEST_INFO,
+    #[deku(id_pat = "30..=16383")]
+    Unassigned(u16),
+    #[deku(id_pat = "16384..=32767")]
+    Private(u16),
+}
+
+/// EAP Codes
+/// Reference: https://www.iana.org/assignments/eap-numbers/eap-numbers.xhtml#eap-numbers-1
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(id_type = "u8", endian = "endian", ctx = "endian: deku::ctx::Endian")]
+pub enum EapCode {
+    #[deku(id = 0)]
+    Reserved,
+    #[deku(id = 1)]
+    Request,
+    #[deku(id = 2)]
+    Response,
+    #[deku(id = 3)]
+    Success,
+    #[deku(id = 4)]
+    Failure,
+    #[deku(id = 5)]
+    Initiate,
+    #[deku(id = 6)]
+    Finish,
+    #[deku(id_pat = "7..=255")]
+    Unassigned(u8),
+}
+
+/// EAP Method Types
+/// Reference: https://www.iana.org/assignments/eap-numbers/eap-numbers.xhtml#eap-numbers-4
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(
+    id_type = "u8",
+    endian = "endian",
+    ctx = "endian: deku::ctx::Endian",
+    ctx_default = "deku::ctx::Endian::Big"
+)]
+pub enum EapType {
+    #[deku(id = 1)]
+    IDENTITY,
+    #[deku(id = 2)]
+    NOTIFICATION,
+    /// Legacy Nak, in Responses only
+    #[deku(id = 3)]
+    NAK,
+    #[deku(id = 4)]
+    MD5_CHALLENGE,
+    #[deku(id = 5)]
+    ONE_TIME_PASSWORD,
+    #[deku(id = 6)]
+    GENERIC_TOKEN_CARD,
+    #[deku(id = 13)]
+    TLS,
+    #[deku(id = 18)]
+    SIM,
+    #[deku(id = 21)]
+    TTLS,
+    #[deku(id = 23)]
+    AKA,
+    #[deku(id = 25)]
+    PEAP,
+    #[deku(id = 26)]
+    MSCHAPV2,
+    #[deku(id = 43)]
+    FAST,
+    #[deku(id = 49)]
+    IKEV2,
+    #[deku(id = 50)]
+    AKA_PRIME,
+    #[deku(id = 52)]
+    PWD,
+    #[deku(id = 55)]
+    TEAP,
+    #[deku(id = 254)]
+    EXPANDED,
+    #[deku(id = 255)]
+    EXPERIMENTAL,
+    /// Reserved, unassigned and other assigned types
+    #[deku(id_pat = "_")]
+    Other(u8),
+}
+
+/// IKEv2 Hash Algorithms
+/// Reference: https://www.iana.org/assignments/ikev2-parameters/ikev2-parameters.xhtml#hash-algorithms
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(
+    id_type = "u16",
+    id_endian = "endian",
+    endian = "endian",
+    ctx = "endian: deku::ctx::Endian",
+    ctx_default = "deku::ctx::Endian::Big"
+)]
+pub enum HashAlgorithm {
+    #[deku(id = 0)]
+    Reserved,
+    #[deku(id = 1)]
+    SHA1,
+    #[deku(id = 2)]
+    SHA2_256,
+    #[deku(id = 3)]
+    SHA2_384,
+    #[deku(id = 4)]
+    SHA2_512,
+    #[deku(id = 5)]
+    Identity,
+    #[deku(id_pat = "6..=1023")]
+    Unassigned(u16),
+    #[deku(id_pat = "1024..=65535")]
+    Private(u16),
+}
+
+/// IKEv2 Notify Message Types
+/// Reference: https://www.iana.org/assignments/ikev2-parameters/ikev2-parameters.xhtml#ikev2-parameters-14
+/// Reference: https://www.iana.org/assignments/ikev2-parameters/ikev2-parameters.xhtml#ikev2-parameters-16
+#[allow(non_camel_case_types)]
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(
+    id_type = "u16",
+    id_endian = "endian",
+    endian = "endian",
+    ctx = "endian: deku::ctx::Endian"
+)]
+pub enum NotifyType {
+    #[deku(id_pat = "0 | 2..=3 | 6 | 8 | 10 | 12..=13 | 15..=16 | 18..=23 | 25..=33")]
+    Reserved(u16),
+    #[deku(id = 1)]
+    UNSUPPORTED_CRITICAL_PAYLOAD,
+    #[deku(id = 4)]
+    INVALID_IKE_SPI,
+    #[deku(id = 5)]
+    INVALID_MAJOR_VERSION,
+    #[deku(id = 7)]
+    INVALID_SYNTAX,
+    #[deku(id = 9)]
+    INVALID_MESSAGE_ID,
+    #[deku(id = 11)]
+    INVALID_SPI,
+    #[deku(id = 14)]
+    NO_PROPOSAL_CHOSEN,
+    #[deku(id = 17)]
+    INVALID_KE_PAYLOAD,
+    #[deku(id = 24)]
+    AUTHENTICATION_FAILED,
+    #[deku(id = 34)]
+    SINGLE_PAIR_REQUIRED,
+    #[deku(id = 35)]
+    NO_ADDITIONAL_SAS,
+    #[deku(id = 36)]
+    INTERNAL_ADDRESS_FAILURE,
+    #[deku(id = 37)]
+    FAILED_CP_REQUIRED,
+    #[deku(id = 38)]
+    TS_UNACCEPTABLE,
+    #[deku(id = 39)]
+    INVALID_SELECTORS,
+    #[deku(id = 40)]
+    UNACCEPTABLE_ADDRESSES,
+    #[deku(id = 41)]
+    UNEXPECTED_NAT_DETECTED,
+    #[deku(id = 42)]
+    USE_ASSIGNED_HoA,
+    #[deku(id = 43)]
+    TEMPORARY_FAILURE,
+    #[deku(id = 44)]
+    CHILD_SA_NOT_FOUND,
+    #[deku(id = 45)]
+    INVALID_GROUP_ID,
+    #[deku(id = 46)]
+    AUTHORIZATION_FAILED,
+    #[deku(id = 47)]
+    STATE_NOT_FOUND,
+    #[deku(id = 48)]
+    TS_MAX_QUEUE,
+    #[deku(id_pat = "49..=8191")]
+    Unassigned0(u16),
+    #[deku(id_pat = "8192..=16383")]
+    PrivateError(u16),
+    #[deku(id = 16384)]
+    INITIAL_CONTACT,
+    #[deku(id = 16385)]
+    SET_WINDOW_SIZE,
+    #[deku(id = 16386)]
+    ADDITIONAL_TS_POSSIBLE,
+    #[deku(id = 16387)]
+    IPCOMP_SUPPORTED,
+    #[deku(id = 16388)]
+    NAT_DETECTION_SOURCE_IP,
+    #[deku(id = 16389)]
+    NAT_DETECTION_DESTINATION_IP,
+    #[deku(id = 16390)]
+    COOKIE,
+    #[deku(id = 16391)]
+    USE_TRANSPORT_MODE,
+    #[deku(id = 16392)]
+    HTTP_CERT_LOOKUP_SUPPORTED,
+    #[deku(id = 16393)]
+    REKEY_SA,
+    #[deku(id = 16394)]
+    ESP_TFC_PADDING_NOT_SUPPORTED,
+    #[deku(id = 16395)]
+    NON_FIRST_FRAGMENTS_ALSO,
+    #[deku(id = 16396)]
+    MOBIKE_SUPPORTED,
+    #[deku(id = 16397)]
+    ADDITIONAL_IP4_ADDRESS,
+    #[deku(id = 16398)]
+    ADDITIONAL_IP6_ADDRESS,
+    #[deku(id = 16399)]
+    NO_ADDITIONAL_ADDRESSES,
+    #[deku(id = 16400)]
+    UPDATE_SA_ADDRESSES,
+    #[deku(id = 16401)]
+    COOKIE2,
+    #[deku(id = 16402)]
+    NO_NATS_ALLOWED,
+    #[deku(id = 16403)]
+    AUTH_LIFETIME,
+    #[deku(id = 16404)]
+    MULTIPLE_AUTH_SUPPORTED,
+    #[deku(id = 16405)]
+    ANOTHER_AUTH_FOLLOWS,
+    #[deku(id = 16406)]
+    REDIRECT_SUPPORTED,
+    #[deku(id = 16407)]
+    REDIRECT,
+    #[deku(id = 16408)]
+    REDIRECTED_FROM,
+    #[deku(id = 16409)]
+    TICKET_LT_OPAQUE,
+    #[deku(id = 16410)]
+    TICKET_REQUEST,
+    #[deku(id = 16411)]
+    TICKET_ACK,
+    #[deku(id = 16412)]
+    TICKET_NACK,
+    #[deku(id = 16413)]
+    TICKET_OPAQUE,
+    #[deku(id = 16414)]
+    LINK_ID,
+    #[deku(id = 16415)]
+    USE_WESP_MODE,
+    #[deku(id = 16416)]
+    ROHC_SUPPORTED,
+    #[deku(id = 16417)]
+    EAP_ONLY_AUTHENTICATION,
+    #[deku(id = 16418)]
+    CHILDLESS_IKEV2_SUPPORTED,
+    #[deku(id = 16419)]
+    QUICK_CRASH_DETECTION,
+    #[deku(id = 16420)]
+    IKEV2_MESSAGE_ID_SYNC_SUPPORTED,
+    #[deku(id = 16421)]
+    IPSEC_REPLAY_COUNTER_SYNC_SUPPORTED,
+    #[deku(id = 16422)]
+    IKEV2_MESSAGE_ID_SYNC,
+    #[deku(id = 16423)]
+    IPSEC_REPLAY_COUNTER_SYNC,
+    #[deku(id = 16424)]
+    SECURE_PASSWORD_METHODS,
+    #[deku(id = 16425)]
+    PSK_PERSIST,
+    #[deku(id = 16426)]
+    PSK_CONFIRM,
+    #[deku(id = 16427)]
+    ERX_SUPPORTED,
+    #[deku(id = 16428)]
+    IFOM_CAPABILITY,
+    #[deku(id = 16429)]
+    GROUP_SENDER,
+    #[deku(id = 16430)]
+    IKEV2_FRAGMENTATION_SUPPORTED,
+    #[deku(id = 16431)]
+    SIGNATURE_HASH_ALGORITHMS,
+    #[deku(id = 16432)]
+    CLONE_IKE_SA_SUPPORTED,
+    #[deku(id = 16433)]
+    CLONE_IKE_SA,
+    #[deku(id = 16434)]
+    PUZZLE,
+    #[deku(id = 16435)]
+    USE_PPK,
+    #[deku(id = 16436)]
+    PPK_IDENTITY,
+    #[deku(id = 16437)]
+    NO_PPK_AUTH,
+    #[deku(id = 16438)]
+    INTERMEDIATE_EXCHANGE_SUPPORTED,
+    #[deku(id = 16439)]
+    IP4_ALLOWED,
+    #[deku(id = 16440)]
+    IP6_ALLOWED,
+    #[deku(id = 16441)]
+    ADDITIONAL_KEY_EXCHANGE,
+    #[deku(id = 16442)]
+    USE_AGGFRAG,
+    #[deku(id = 16443)]
+    SUPPORTED_AUTH_METHODS,
+    #[deku(id = 16444)]
+    SA_RESOURCE_INFO,
+    #[deku(id = 16445)]
+    USE_PPK_INT,
+    #[deku(id = 16446)]
+    PPK_IDENTITY_KEY,
+    #[deku(id_pat = "16447..=40959")]
+    Unassigned1(u16),
+    #[deku(id_pat = "40960..=65535")]
+    PrivateStatus(u16),
+}
+
+impl NotifyType {
+    /// Error types occupy 0-16383; everything above is a status type.
+    pub fn is_error(&self) -> bool {
+        matches!(
+            self,
+            NotifyType::Reserved(_)
+                | NotifyType::UNSUPPORTED_CRITICAL_PAYLOAD
+                | NotifyType::INVALID_IKE_SPI
+                | NotifyType::INVALID_MAJOR_VERSION
+                | NotifyType::INVALID_SYNTAX
+                | NotifyType::INVALID_MESSAGE_ID
+                | NotifyType::INVALID_SPI
+                | NotifyType::NO_PROPOSAL_CHOSEN
+                | NotifyType::INVALID_KE_PAYLOAD
+                | NotifyType::AUTHENTICATION_FAILED
+                | NotifyType::SINGLE_PAIR_REQUIRED
+                | NotifyType::NO_ADDITIONAL_SAS
+                | NotifyType::INTERNAL_ADDRESS_FAILURE
+                | NotifyType::FAILED_CP_REQUIRED
+                | NotifyType::TS_UNACCEPTABLE
+                | NotifyType::INVALID_SELECTORS
+                | NotifyType::UNACCEPTABLE_ADDRESSES
+                | NotifyType::UNEXPECTED_NAT_DETECTED
+                | NotifyType::USE_ASSIGNED_HoA
+                | NotifyType::TEMPORARY_FAILURE
+                | NotifyType::CHILD_SA_NOT_FOUND
+                | NotifyType::INVALID_GROUP_ID
+                | NotifyType::AUTHORIZATION_FAILED
+                | NotifyType::STATE_NOT_FOUND
+                | NotifyType::TS_MAX_QUEUE
+                | NotifyType::Unassigned0(_)
+                | NotifyType::PrivateError(_)
+        )
+    }
+}
+
 #[allow(non_camel_case_types)]
 #[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
 #[deku(id_type = "u8", endian = "endian", ctx = "endian: deku::ctx::Endian")]
@@ -0,0 +1,953 @@
+use std::collections::VecDeque;
+
+use deku::prelude::*;
+use des::Des;
+use des::cipher::{BlockEncrypt, KeyInit};
+use md4::Md4;
+use md5::Md5;
+use rand_core::{OsRng, RngCore};
+use sha1::{Digest, Sha1};
+use subtle::ConstantTimeEq;
+
+use crate::auth::AuthPayload;
+use crate::consts::*;
+use crate::error::Error;
+use crate::transform;
+
+/// EAP payload body, a single EAP packet (RFC 3748 §4)
+#[deku_derive(DekuRead, DekuWrite)]
+#[derive(Clone, Debug, PartialEq)]
+#[deku(endian = "big")]
+pub struct EapPayload {
+    pub code: EapCode,
+    pub identifier: u8,
+    #[deku(
+        temp,
+        temp_value = "u16::try_from(4 + usize::from(eap_type.is_some()) + type_data.len())?",
+        map = "packet_length"
+    )]
+    length: u16,
+    #[deku(cond = "*length > 4")]
+    pub eap_type: Option<EapType>,
+    #[deku(count = "usize::from(*length).saturating_sub(5)")]
+    pub type_data: Vec<u8>,
+}
+
+impl EapPayload {
+    pub fn request(identifier: u8, eap_type: EapType, type_data: Vec<u8>) -> Self {
+        EapPayload {
+            code: EapCode::Request,
+            identifier,
+            eap_type: Some(eap_type),
+            type_data,
+        }
+    }
+
+    pub fn response(identifier: u8, eap_type: EapType, type_data: Vec<u8>) -> Self {
+        EapPayload {
+            code: EapCode::Response,
+            identifier,
+            eap_type: Some(eap_type),
+            type_data,
+        }
+    }
+
+    pub fn success(identifier: u8) -> Self {
+        EapPayload {
+            code: EapCode::Success,
+            identifier,
+            eap_type: None,
+            type_data: vec![],
+        }
+    }
+
+    pub fn failure(identifier: u8) -> Self {
+        EapPayload {
+            code: EapCode::Failure,
+            identifier,
+            eap_type: None,
+            type_data: vec![],
+        }
+    }
+
+    /// Requests and Responses carry a Type, Success and Failure nothing
+    /// beyond the header.
+    pub fn validate(&self) -> Result<(), Error> {
+        let valid = match self.code {
+            EapCode::Request | EapCode::Response => self.eap_type.is_some(),
+            EapCode::Success | EapCode::Failure => {
+                self.eap_type.is_none() && self.type_data.is_empty()
+            }
+            _ => true,
+        };
+        if !valid {
+            return Err(Error::InvalidEapPacket);
+        }
+        Ok(())
+    }
+}
+
+fn packet_length(length: u16) -> Result<u16, DekuError> {
+    if length < 4 {
+        return Err(DekuError::Assertion("EAP length below 4".into()));
+    }
+    Ok(length)
+}
+
+/// One EAP method, in the role of either the peer or the server.
+pub trait EapMethod {
+    fn eap_type(&self) -> EapType;
+
+    /// The Type-Data of the server's first Request, sent with `identifier`.
+    fn initiate(&mut self, identifier: u8) -> Result<Vec<u8>, Error>;
+
+    /// Processes the Type-Data of a Request (peer) or Response (server) and
+    /// returns the Type-Data to answer with, or `None` once the server side
+    /// has finished. A failed authentication is AUTHENTICATION_FAILED.
+    fn process(&mut self, identifier: u8, type_data: &[u8]) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Whether the method has completed successfully.
+    fn is_complete(&self) -> bool;
+
+    /// Master Session Key of a completed key-generating method.
+    fn msk(&self) -> Option<Vec<u8>>;
+}
+
+/// The EAP peer, answering the server's Requests with the first method of
+/// each Type and Nak-ing Types it has no method for.
+pub struct EapPeer {
+    methods: Vec<Box<dyn EapMethod>>,
+    current: Option<usize>,
+    msk: Option<Vec<u8>>,
+}
+
+impl EapPeer {
+    pub fn new(methods: Vec<Box<dyn EapMethod>>) -> Self {
+        EapPeer {
+            methods,
+            current: None,
+            msk: None,
+        }
+    }
+
+    /// Answers a Request, or returns `None` for an EAP-Success following a
+    /// completed method. An EAP-Failure or a premature EAP-Success is
+    /// AUTHENTICATION_FAILED.
+    pub fn process(&mut self, request: &EapPayload) -> Result<Option<EapPayload>, Error> {
+        request.validate()?;
+        let failed = || Error::Notify(NotifyType::AUTHENTICATION_FAILED);
+        match request.code {
+            EapCode::Request => {}
+            EapCode::Success => {
+                let method = self.current.map(|i| &self.methods[i]);
+                if !method.is_some_and(|m| m.is_complete()) {
+                    return Err(failed());
+                }
+                self.msk = method.and_then(|m| m.msk());
+                return Ok(None);
+            }
+            EapCode::Failure => return Err(failed()),
+            _ => return Err(Error::InvalidEapPacket),
+        }
+
+        let identifier = request.identifier;
+        let eap_type = request.eap_type.clone().ok_or(Error::InvalidEapPacket)?;
+        if eap_type == EapType::NOTIFICATION {
+            return Ok(Some(EapPayload::response(identifier, eap_type, vec![])));
+        }
+        let Some(i) = self.methods.iter().position(|m| m.eap_type() == eap_type) else {
+            let mut desired = vec![];
+            for method in &self.methods {
+                if !matches!(method.eap_type(), EapType::IDENTITY | EapType::NOTIFICATION) {
+                    desired.extend(method.eap_type().to_bytes()?);
+                }
+            }
+            if desired.is_empty() {
+                desired.push(0);
+            }
+            return Ok(Some(EapPayload::response(
+                identifier,
+                EapType::NAK,
+                desired,
+            )));
+        };
+        self.current = Some(i);
+        let type_data = self.methods[i]
+            .process(identifier, &request.type_data)?
+            .ok_or(Error::InvalidEapPacket)?;
+        Ok(Some(EapPayload::response(identifier, eap_type, type_data)))
+    }
+
+    /// Master Session Key once EAP has succeeded.
+    pub fn msk(&self) -> Option<&[u8]> {
+        self.msk.as_deref()
+    }
+}
+
+/// The EAP server, running its methods in order and concluding with an
+/// EAP-Success once all of them have completed.
+pub struct EapServer {
+    methods: VecDeque<Box<dyn EapMethod>>,
+    identifier: u8,
+    success: Option<bool>,
+    msk: Option<Vec<u8>>,
+}
+
+impl EapServer {
+    pub fn new(methods: Vec<Box<dyn EapMethod>>) -> Self {
+        EapServer {
+            methods: methods.into(),
+            identifier: (OsRng.next_u32() & 0xff) as u8,
+            success: None,
+            msk: None,
+        }
+    }
+
+    /// The first Request.
+    pub fn start(&mut self) -> Result<EapPayload, Error> {
+        let method = self.methods.front_mut().ok_or(Error::InvalidEapPacket)?;
+        let type_data = method.initiate(self.identifier)?;
+        Ok(EapPayload::request(
+            self.identifier,
+            method.eap_type(),
+            type_data,
+        ))
+    }
+
+    /// Processes a Response and returns the next Request, or the concluding
+    /// EAP-Success or EAP-Failure. Responses that do not answer the last
+    /// Request fail with `InvalidEapPacket` and are to be discarded.
+    pub fn process(&mut self, response: &EapPayload) -> Result<EapPayload, Error> {
+        response.validate()?;
+        let identifier = response.identifier;
+        if self.success.is_some()
+            || response.code != EapCode::Response
+            || identifier != self.identifier
+        {
+            return Err(Error::InvalidEapPacket);
+        }
+        let method = self.methods.front_mut().ok_or(Error::InvalidEapPacket)?;
+        let eap_type = response.eap_type.clone().ok_or(Error::InvalidEapPacket)?;
+        if eap_type == EapType::NAK {
+            return Ok(self.conclude(false, identifier));
+        }
+        if eap_type != method.eap_type() {
+            return Err(Error::InvalidEapPacket);
+        }
+
+        let next = match method.process(identifier, &response.type_data) {
+            Ok(next) => next,
+            Err(_) => return Ok(self.conclude(false, identifier)),
+        };
+        self.identifier = identifier.wrapping_add(1);
+        if let Some(type_data) = next {
+            return Ok(EapPayload::request(self.identifier, eap_type, type_data));
+        }
+        if !method.is_complete() {
+            return Ok(self.conclude(false, identifier));
+        }
+        if let Some(msk) = method.msk() {
+            self.msk = Some(msk);
+        }
+        self.methods.pop_front();
+        let Some(method) = self.methods.front_mut() else {
+            return Ok(self.conclude(true, identifier));
+        };
+        match method.initiate(self.identifier) {
+            Ok(type_data) => Ok(EapPayload::request(
+                self.identifier,
+                method.eap_type(),
+                type_data,
+            )),
+            Err(_) => Ok(self.conclude(false, identifier)),
+        }
+    }
+
+    /// Success and Failure carry the Identifier of the Response they answer.
+    fn conclude(&mut self, success: bool, identifier: u8) -> EapPayload {
+        self.success = Some(success);
+        if success {
+            EapPayload::success(identifier)
+        } else {
+            EapPayload::failure(identifier)
+        }
+    }
+
+    pub fn is_successful(&self) -> bool {
+        self.success == Some(true)
+    }
+
+    /// Master Session Key once EAP has succeeded.
+    pub fn msk(&self) -> Option<&[u8]> {
+        self.msk.as_deref().filter(|_| self.is_successful())
+    }
+}
+
+/// The AUTH payload concluding IKE_AUTH after EAP (RFC 7296 §2.16): a shared
+/// key MIC keyed with the MSK, or with SK_pi or SK_pr if the method
+/// establishes none.
+pub fn auth(
+    prf: &transform::PRF,
+    msk: Option<&[u8]>,
+    sk_p: &[u8],
+    signed_octets: &[u8],
+) -> Result<AuthPayload, Error> {
+    AuthPayload::psk(prf, msk.unwrap_or(sk_p), signed_octets)
+}
+
+/// Checks the AUTH payload concluding IKE_AUTH after EAP.
+pub fn verify_auth(
+    auth: &AuthPayload,
+    prf: &transform::PRF,
+    msk: Option<&[u8]>,
+    sk_p: &[u8],
+    signed_octets: &[u8],
+) -> Result<(), Error> {
+    auth.verify_psk(prf, msk.unwrap_or(sk_p), signed_octets)
+}
+
+/// EAP-Identity (RFC 3748 §5.1)
+pub struct EapIdentity {
+    identity: Option<Vec<u8>>,
+    server: bool,
+}
+
+impl EapIdentity {
+    pub fn peer(identity: &[u8]) -> Self {
+        EapIdentity {
+            identity: Some(identity.to_vec()),
+            server: false,
+        }
+    }
+
+    pub fn server() -> Self {
+        EapIdentity {
+            identity: None,
+            server: true,
+        }
+    }
+
+    /// The peer's identity, once received by the server.
+    pub fn identity(&self) -> Option<&[u8]> {
+        self.identity.as_deref()
+    }
+}
+
+impl EapMethod for EapIdentity {
+    fn eap_type(&self) -> EapType {
+        EapType::IDENTITY
+    }
+
+    fn initiate(&mut self, _identifier: u8) -> Result<Vec<u8>, Error> {
+        Ok(vec![])
+    }
+
+    fn process(&mut self, _identifier: u8, type_data: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        if self.server {
+            self.identity = Some(type_data.to_vec());
+            return Ok(None);
+        }
+        Ok(self.identity.clone())
+    }
+
+    fn is_complete(&self) -> bool {
+        self.identity.is_some()
+    }
+
+    fn msk(&self) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Length of the MD5-Challenge and MS-CHAPv2 challenges.
+const CHALLENGE_LENGTH: usize = 16;
+
+/// EAP-MD5-Challenge (RFC 3748 §5.4), which authenticates the peer only
+/// and establishes no MSK.
+pub struct EapMd5 {
+    password: Vec<u8>,
+    /// Identifier and value of the server's challenge
+    challenge: Option<(u8, [u8; CHALLENGE_LENGTH])>,
+    server: bool,
+    complete: bool,
+}
+
+impl EapMd5 {
+    pub fn peer(password: &[u8]) -> Self {
+        Self::new(password, false)
+    }
+
+    pub fn server(password: &[u8]) -> Self {
+        Self::new(password, true)
+    }
+
+    fn new(password: &[u8], server: bool) -> Self {
+        EapMd5 {
+            password: password.to_vec(),
+            challenge: None,
+            server,
+            complete: false,
+        }
+    }
+
+    /// MD5(Identifier | secret | Challenge), after CHAP (RFC 1994 §4.1)
+    fn response(&self, identifier: u8, challenge: &[u8]) -> [u8; 16] {
+        Md5::new()
+            .chain_update([identifier])
+            .chain_update(&self.password)
+            .chain_update(challenge)
+            .finalize()
+            .into()
+    }
+}
+
+/// Splits a Value-Size prefixed value from the Name that follows it.
+fn sized_value(data: &[u8]) -> Result<(&[u8], &[u8]), Error> {
+    let (&size, rest) = data.split_first().ok_or(Error::InvalidEapPacket)?;
+    if rest.len() < usize::from(size) {
+        return Err(Error::InvalidEapPacket);
+    }
+    Ok(rest.split_at(usize::from(size)))
+}
+
+impl EapMethod for EapMd5 {
+    fn eap_type(&self) -> EapType {
+        EapType::MD5_CHALLENGE
+    }
+
+    fn initiate(&mut self, identifier: u8) -> Result<Vec<u8>, Error> {
+        let mut challenge = [0; CHALLENGE_LENGTH];
+        OsRng.fill_bytes(&mut challenge);
+        self.challenge = Some((identifier, challenge));
+        Ok([&[CHALLENGE_LENGTH as u8][..], &challenge].concat())
+    }
+
+    fn process(&mut self, identifier: u8, type_data: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let (value, _name) = sized_value(type_data)?;
+        if !self.server {
+            let response = self.response(identifier, value);
+            self.complete = true;
+            return Ok(Some([&[16][..], &response].concat()));
+        }
+        let (identifier, challenge) = self.challenge.ok_or(Error::InvalidEapPacket)?;
+        if !bool::from(self.response(identifier, &challenge).ct_eq(value)) {
+            return Err(Error::Notify(NotifyType::AUTHENTICATION_FAILED));
+        }
+        self.complete = true;
+        Ok(None)
+    }
+
+    fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    fn msk(&self) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// MS-CHAPv2 OpCodes
+const MSCHAPV2_CHALLENGE: u8 = 1;
+const MSCHAPV2_RESPONSE: u8 = 2;
+const MSCHAPV2_SUCCESS: u8 = 3;
+const MSCHAPV2_FAILURE: u8 = 4;
+
+/// Peer-Challenge, Reserved, NT-Response and Flags
+const MSCHAPV2_RESPONSE_LENGTH: usize = 49;
+
+/// EAP-MSCHAPv2 (draft-kamath-pppext-eap-mschapv2) with MS-CHAPv2 (RFC 2759)
+/// authenticating both sides, and the MSK derived as in RFC 3079.
+pub struct EapMsChapV2 {
+    username: Vec<u8>,
+    password_hash: [u8; 16],
+    state: MsChapV2State,
+    server: bool,
+}
+
+enum MsChapV2State {
+    Initial,
+    /// The server sent its challenge.
+    Challenged([u8; CHALLENGE_LENGTH]),
+    /// The peer answered the challenge, or the server accepted the answer.
+    Responded {
+        authenticator_response: Vec<u8>,
+        msk: Vec<u8>,
+    },
+    /// The server rejected the answer.
+    Failed,
+    Complete {
+        msk: Vec<u8>,
+    },
+}
+
+impl EapMsChapV2 {
+    pub fn peer(username: &[u8], password: &str) -> Self {
+        Self::new(username, password, false)
+    }
+
+    /// A server authenticating the user with the password.
+    pub fn server(username: &[u8], password: &str) -> Self {
+        Self::new(username, password, true)
+    }
+
+    fn new(username: &[u8], password: &str, server: bool) -> Self {
+        EapMsChapV2 {
+            username: username.to_vec(),
+            password_hash: nt_password_hash(password),
+            state: MsChapV2State::Initial,
+            server,
+        }
+    }
+
+    /// The NT-Response to the challenges, the authenticator response
+    /// proving the server knows the password, and the state of having sent
+    /// or accepted the NT-Response.
+    fn respond(
+        &self,
+        challenge: &[u8],
+        peer_challenge: &[u8],
+    ) -> ([u8; 24], Vec<u8>, MsChapV2State) {
+        let challenge_hash = challenge_hash(peer_challenge, challenge, &self.username);
+        let nt_response = challenge_response(&challenge_hash, &self.password_hash);
+        let password_hash_hash: [u8; 16] = Md4::digest(self.password_hash).into();
+        let authenticator_response =
+            authenticator_response(&password_hash_hash, &nt_response, &challenge_hash);
+        let state = MsChapV2State::Responded {
+            authenticator_response: authenticator_response.clone(),
+            msk: msk(&password_hash_hash, &nt_response),
+        };
+        (nt_response, authenticator_response, state)
+    }
+
+    fn process_peer(&mut self, type_data: &[u8]) -> Result<Vec<u8>, Error> {
+        let (opcode, ms_id, data) = mschapv2_packet(type_data)?;
+        match (opcode, &self.state) {
+            (MSCHAPV2_CHALLENGE, MsChapV2State::Initial) => {
+                let (challenge, _name) = sized_value(data)?;
+                if challenge.len() != CHALLENGE_LENGTH {
+                    return Err(Error::InvalidEapPacket);
+                }
+                let mut peer_challenge = [0; CHALLENGE_LENGTH];
+                OsRng.fill_bytes(&mut peer_challenge);
+                let (nt_response, _, state) = self.respond(challenge, &peer_challenge);
+                self.state = state;
+                let data = [
+                    &[MSCHAPV2_RESPONSE_LENGTH as u8][..],
+                    &peer_challenge,
+                    &[0; 8],
+                    &nt_response,
+                    &[0],
+                    &self.username,
+                ]
+                .concat();
+                mschapv2_packet_bytes(MSCHAPV2_RESPONSE, ms_id, &data)
+            }
+            (
+                MSCHAPV2_SUCCESS,
+                MsChapV2State::Responded {
+                    authenticator_response,
+                    msk,
+                },
+            ) => {
+                let received = data.get(..authenticator_response.len());
+                if !received.is_some_and(|r| bool::from(r.ct_eq(authenticator_response))) {
+                    return Err(Error::Notify(NotifyType::AUTHENTICATION_FAILED));
+                }
+                self.state = MsChapV2State::Complete { msk: msk.clone() };
+                Ok(vec![MSCHAPV2_SUCCESS])
+            }
+            (MSCHAPV2_FAILURE, MsChapV2State::Responded { .. }) => {
+                self.state = MsChapV2State::Failed;
+                Ok(vec![MSCHAPV2_FAILURE])
+            }
+            _ => Err(Error::InvalidEapPacket),
+        }
+    }
+
+    fn process_server(
+        &mut self,
+        identifier: u8,
+        type_data: &[u8],
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let challenge = match (&self.state, type_data) {
+            (MsChapV2State::Responded { msk, .. }, [MSCHAPV2_SUCCESS]) => {
+                self.state = MsChapV2State::Complete { msk: msk.clone() };
+                return Ok(None);
+            }
+            (MsChapV2State::Failed, [MSCHAPV2_FAILURE]) => {
+                return Err(Error::Notify(NotifyType::AUTHENTICATION_FAILED));
+            }
+            (MsChapV2State::Challenged(challenge), _) => *challenge,
+            _ => return Err(Error::InvalidEapPacket),
+        };
+        let (opcode, ms_id, data) = mschapv2_packet(type_data)?;
+        if opcode != MSCHAPV2_RESPONSE || ms_id != identifier {
+            return Err(Error::InvalidEapPacket);
+        }
+        let (response, name) = sized_value(data)?;
+        if response.len() != MSCHAPV2_RESPONSE_LENGTH {
+            return Err(Error::InvalidEapPacket);
+        }
+        let (peer_challenge, rest) = response.split_at(CHALLENGE_LENGTH);
+        let (expected, authenticator_response, state) = self.respond(&challenge, peer_challenge);
+        if name != self.username || !bool::from(expected.ct_eq(&rest[8..32])) {
+            self.state = MsChapV2State::Failed;
+            let message = format!(
+                "E=691 R=0 C={} V=3 M=Authentication failed",
+                hex_upper(&challenge)
+            );
+            let data = mschapv2_packet_bytes(MSCHAPV2_FAILURE, ms_id, message.as_bytes())?;
+            return Ok(Some(data));
+        }
+        let message = [&authenticator_response[..], b" M=OK"].concat();
+        self.state = state;
+        mschapv2_packet_bytes(MSCHAPV2_SUCCESS, ms_id, &message).map(Some)
+    }
+}
+
+impl EapMethod for EapMsChapV2 {
+    fn eap_type(&self) -> EapType {
+        EapType::MSCHAPV2
+    }
+
+    fn initiate(&mut self, identifier: u8) -> Result<Vec<u8>, Error> {
+        let mut challenge = [0; CHALLENGE_LENGTH];
+        OsRng.fill_bytes(&mut challenge);
+        self.state = MsChapV2State::Challenged(challenge);
+        let data = [&[CHALLENGE_LENGTH as u8][..], &challenge].concat();
+        mschapv2_packet_bytes(MSCHAPV2_CHALLENGE, identifier, &data)
+    }
+
+    fn process(&mut self, identifier: u8, type_data: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        if self.server {
+            self.process_server(identifier, type_data)
+        } else {
+            self.process_peer(type_data).map(Some)
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        matches!(self.state, MsChapV2State::Complete { .. })
+    }
+
+    fn msk(&self) -> Option<Vec<u8>> {
+        match &self.state {
+            MsChapV2State::Complete { msk } => Some(msk.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Splits an MS-CHAPv2 packet into its OpCode, MS-CHAPv2-ID and data,
+/// checking MS-Length.
+fn mschapv2_packet(type_data: &[u8]) -> Result<(u8, u8, &[u8]), Error> {
+    let Some((&[opcode, ms_id, length_0, length_1], data)) = type_data.split_first_chunk() else {
+        return Err(Error::InvalidEapPacket);
+    };
+    if usize::from(u16::from_be_bytes([length_0, length_1])) != type_data.len() {
+        return Err(Error::InvalidEapPacket);
+    }
+    Ok((opcode, ms_id, data))
+}
+
+/// MS-Length counts the whole packet from the OpCode on.
+fn mschapv2_packet_bytes(opcode: u8, ms_id: u8, data: &[u8]) -> Result<Vec<u8>, Error> {
+    let length = u16::try_from(4 + data.len()).map_err(|_| Error::InvalidEapPacket)?;
+    Ok([&[opcode, ms_id][..], &length.to_be_bytes(), data].concat())
+}
+
+fn hex_upper(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02X}")).collect()
+}
+
+/// NtPasswordHash: MD4 of the UTF-16LE password (RFC 2759 §8.3)
+fn nt_password_hash(password: &str) -> [u8; 16] {
+    let unicode: Vec<u8> = password.encode_utf16().flat_map(u16::to_le_bytes).collect();
+    Md4::digest(unicode).into()
+}
+
+/// ChallengeHash (RFC 2759 §8.2)
+fn challenge_hash(peer_challenge: &[u8], challenge: &[u8], username: &[u8]) -> [u8; 8] {
+    let digest = Sha1::new()
+        .chain_update(peer_challenge)
+        .chain_update(challenge)
+        .chain_update(username)
+        .finalize();
+    digest[..8]
+        .try_into()
+        .expect("SHA-1 is longer than 8 bytes")
+}
+
+/// ChallengeResponse: the challenge DES encrypted under each 7-byte third
+/// of the zero-padded password hash (RFC 2759 §8.5)
+fn challenge_response(challenge: &[u8; 8], password_hash: &[u8; 16]) -> [u8; 24] {
+    let mut z_password_hash = [0; 21];
+    z_password_hash[..16].copy_from_slice(password_hash);
+    let mut response = [0; 24];
+    for (key, block) in z_password_hash.chunks(7).zip(response.chunks_mut(8)) {
+        // spread the 56 key bits over 8 bytes, leaving the parity bits clear
+        let bits = key.iter().fold(0u64, |bits, b| bits << 8 | u64::from(*b));
+        let key: [u8; 8] = std::array::from_fn(|i| ((bits >> (49 - 7 * i)) as u8) << 1);
+        block.copy_from_slice(challenge);
+        Des::new(&key.into()).encrypt_block(block.into());
+    }
+    response
+}
+
+/// GenerateAuthenticatorResponse, as "S=" and 40 upper case hex digits
+/// (RFC 2759 §8.7)
+fn authenticator_response(
+    password_hash_hash: &[u8; 16],
+    nt_response: &[u8],
+    challenge_hash: &[u8; 8],
+) -> Vec<u8> {
+    const MAGIC1: &[u8] = b"Magic server to client signing constant";
+    const MAGIC2: &[u8] = b"Pad to make it do more than one iteration";
+    let digest = Sha1::new()
+        .chain_update(password_hash_hash)
+        .chain_update(nt_response)
+        .chain_update(MAGIC1)
+        .finalize();
+    let digest = Sha1::new()
+        .chain_update(digest)
+        .chain_update(challenge_hash)
+        .chain_update(MAGIC2)
+        .finalize();
+    format!("S={}", hex_upper(&digest)).into_bytes()
+}
+
+/// MSK: the authenticator's MS-MPPE-Recv-Key followed by its
+/// MS-MPPE-Send-Key, 128-bit keys derived as in RFC 3079 §3.
+fn msk(password_hash_hash: &[u8; 16], nt_response: &[u8]) -> Vec<u8> {
+    const MAGIC1: &[u8] = b"This is the MPPE Master Key";
+    const MAGIC2: &[u8] = b"On the client side, this is the send key; \
+        on the server side, it is the receive key.";
+    const MAGIC3: &[u8] = b"On the client side, this is the receive key; \
+        on the server side, it is the send key.";
+    let master_key = &Sha1::new()
+        .chain_update(password_hash_hash)
+        .chain_update(nt_response)
+        .chain_update(MAGIC1)
+        .finalize()[..16];
+    let start_key = |magic| {
+        Sha1::new()
+            .chain_update(master_key)
+            .chain_update([0; 40])
+            .chain_update(magic)
+            .chain_update([0xf2; 40])
+            .finalize()[..16]
+            .to_vec()
+    };
+    [start_key(MAGIC2), start_key(MAGIC3)].concat()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::hex;
+
+    /// Runs a conversation, passing every packet through its encoding.
+    fn run(server: &mut EapServer, peer: &mut EapPeer) -> Result<(), Error> {
+        let wire = |packet: EapPayload| EapPayload::try_from(&packet.to_bytes().unwrap()[..]);
+        let mut request = wire(server.start()?)?;
+        loop {
+            match peer.process(&request)? {
+                Some(response) => request = wire(server.process(&wire(response)?)?)?,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_eap_payload() {
+        let identity = EapPayload::try_from(&[1, 7, 0, 5, 1][..]).unwrap();
+        assert_eq!(identity, EapPayload::request(7, EapType::IDENTITY, vec![]));
+        assert_eq!(identity.validate(), Ok(()));
+        let response = EapPayload::response(7, EapType::IDENTITY, b"alice".to_vec());
+        let data = response.to_bytes().unwrap();
+        assert_eq!(data, [&[2, 7, 0, 10, 1][..], b"alice"].concat());
+        assert_eq!(EapPayload::try_from(&data[..]), Ok(response));
+        let success = EapPayload::try_from(&[3, 7, 0, 4][..]).unwrap();
+        assert_eq!(success, EapPayload::success(7));
+        assert_eq!(success.validate(), Ok(()));
+
+        assert!(EapPayload::try_from(&[3, 7, 0, 3][..]).is_err());
+        assert!(EapPayload::try_from(&[2, 7, 0, 10, 1][..]).is_err());
+        assert!(EapPayload::try_from(&[2, 7, 0, 5, 1, 0][..]).is_err());
+        for data in [&[1, 7, 0, 4][..], &[4, 7, 0, 5, 1]] {
+            let packet = EapPayload::try_from(data).unwrap();
+            assert_eq!(packet.validate(), Err(Error::InvalidEapPacket));
+        }
+    }
+
+    #[test]
+    fn test_md5() {
+        // MD5(0x07 | "password" | 0x00..0x0f), computed with Python's hashlib
+        let challenge: Vec<u8> = (0..16).collect();
+        let mut peer = EapMd5::peer(b"password");
+        assert_eq!(
+            peer.process(7, &[&[16][..], &challenge, b"server"].concat()),
+            Ok(Some(
+                [&[16][..], &hex("23ede83231c0bc7b7f00c30fc578cadc")].concat()
+            ))
+        );
+        assert!(peer.is_complete());
+        assert_eq!(peer.msk(), None);
+        assert_eq!(peer.process(7, &[17]), Err(Error::InvalidEapPacket));
+
+        let mut server = EapServer::new(vec![
+            Box::new(EapIdentity::server()),
+            Box::new(EapMd5::server(b"password")),
+        ]);
+        let mut peer = EapPeer::new(vec![
+            Box::new(EapIdentity::peer(b"alice")),
+            Box::new(EapMd5::peer(b"password")),
+        ]);
+        assert_eq!(run(&mut server, &mut peer), Ok(()));
+        assert!(server.is_successful());
+        assert_eq!((server.msk(), peer.msk()), (None, None));
+
+        let mut server = EapServer::new(vec![Box::new(EapMd5::server(b"password"))]);
+        let mut peer = EapPeer::new(vec![Box::new(EapMd5::peer(b"wrong"))]);
+        assert_eq!(
+            run(&mut server, &mut peer),
+            Err(Error::Notify(NotifyType::AUTHENTICATION_FAILED))
+        );
+        assert!(!server.is_successful());
+    }
+
+    #[test]
+    fn test_mschapv2_vectors() {
+        // RFC 2759 §9.2, and the keys of RFC 3079 §3.5.3 for the same response
+        let username = b"User";
+        let challenge = hex("5B5D7C7D7B3F2F3E3C2C602132262628");
+        let peer_challenge = hex("21402324255E262A28295F2B3A337C7E");
+        let password_hash = nt_password_hash("clientPass");
+        assert_eq!(password_hash[..], hex("44EBBA8D5312B8D611474411F56989AE"));
+        let challenge_hash = challenge_hash(&peer_challenge, &challenge, username);
+        assert_eq!(challenge_hash[..], hex("D02E4386BCE91226"));
+        let nt_response = challenge_response(&challenge_hash, &password_hash);
+        assert_eq!(
+            nt_response[..],
+            hex("82309ECD8D708B5EA08FAA3981CD83544233114A3D85D6DF")
+        );
+        let password_hash_hash: [u8; 16] = Md4::digest(password_hash).into();
+        assert_eq!(
+            password_hash_hash[..],
+            hex("41C00C584BD2D91C4017A2A12FA59F3F")
+        );
+        assert_eq!(
+            authenticator_response(&password_hash_hash, &nt_response, &challenge_hash),
+            b"S=407A5589115FD0D6209F510FE9C04566932CDA56"
+        );
+        assert_eq!(
+            msk(&password_hash_hash, &nt_response),
+            hex("D5F0E9521E3EA9589645E86051C822268B7CDC149B993A1BA118CB153F56DCCB")
+        );
+    }
+
+    #[test]
+    fn test_mschapv2() {
+        let server = || {
+            EapServer::new(vec![
+                Box::new(EapIdentity::server()),
+                Box::new(EapMsChapV2::server(b"User", "clientPass")),
+            ])
+        };
+        let peer = |password| {
+            EapPeer::new(vec![
+                Box::new(EapIdentity::peer(b"User")),
+                Box::new(EapMd5::peer(b"unused")),
+                Box::new(EapMsChapV2::peer(b"User", password)),
+            ])
+        };
+        let (mut server_0, mut peer_0) = (server(), peer("clientPass"));
+        assert_eq!(run(&mut server_0, &mut peer_0), Ok(()));
+        assert!(server_0.is_successful());
+        let msk = server_0.msk().unwrap();
+        assert_eq!(msk.len(), 32);
+        assert_eq!(peer_0.msk(), Some(msk));
+
+        // the MSK keys AUTH of both sides
+        let prf = transform::PRF::PRF_HMAC_SHA2_256;
+        let auth = auth(&prf, peer_0.msk(), b"SK_pi", b"signed octets").unwrap();
+        assert_eq!(
+            verify_auth(&auth, &prf, Some(msk), b"SK_pi", b"signed octets"),
+            Ok(())
+        );
+        assert_eq!(
+            verify_auth(&auth, &prf, None, b"SK_pi", b"signed octets"),
+            Err(Error::Notify(NotifyType::AUTHENTICATION_FAILED))
+        );
+        let auth = self::auth(&prf, None, b"SK_pi", b"signed octets").unwrap();
+        assert_eq!(
+            auth,
+            AuthPayload::psk(&prf, b"SK_pi", b"signed octets").unwrap()
+        );
+
+        let (mut server_1, mut peer_1) = (server(), peer("wrong"));
+        assert_eq!(
+            run(&mut server_1, &mut peer_1),
+            Err(Error::Notify(NotifyType::AUTHENTICATION_FAILED))
+        );
+        assert!(!server_1.is_successful());
+        assert_eq!((server_1.msk(), peer_1.msk()), (None, None));
+    }
+
+    #[test]
+    fn test_conversation_errors() {
+        // the peer Naks a method it does not have, proposing its own
+        let mut server = EapServer::new(vec![Box::new(EapMd5::server(b"password"))]);
+        let mut peer = EapPeer::new(vec![Box::new(EapMsChapV2::peer(b"User", "clientPass"))]);
+        let request = server.start().unwrap();
+        let nak = peer.process(&request).unwrap().unwrap();
+        assert_eq!(nak.eap_type, Some(EapType::NAK));
+        assert_eq!(nak.type_data, [26]);
+        assert_eq!(
+            server.process(&nak),
+            Ok(EapPayload::failure(request.identifier))
+        );
+        assert_eq!(server.process(&nak), Err(Error::InvalidEapPacket));
+
+        // Responses must answer the outstanding Request
+        let mut server = EapServer::new(vec![Box::new(EapIdentity::server())]);
+        let request = server.start().unwrap();
+        let wrong_identifier = request.identifier.wrapping_add(1);
+        for response in [
+            EapPayload::response(wrong_identifier, EapType::IDENTITY, vec![]),
+            EapPayload::response(request.identifier, EapType::MD5_CHALLENGE, vec![]),
+            EapPayload::request(request.identifier, EapType::IDENTITY, vec![]),
+        ] {
+            assert_eq!(server.process(&response), Err(Error::InvalidEapPacket));
+        }
+        let response = EapPayload::response(request.identifier, EapType::IDENTITY, vec![]);
+        assert_eq!(
+            server.process(&response),
+            Ok(EapPayload::success(request.identifier))
+        );
+
+        // a Success before the method has completed is not accepted
+        let mut peer = EapPeer::new(vec![Box::new(EapMsChapV2::peer(b"User", "clientPass"))]);
+        let failed = Err(Error::Notify(NotifyType::AUTHENTICATION_FAILED));
+        assert_eq!(peer.process(&EapPayload::success(1)), failed);
+        let mut server = EapServer::new(vec![Box::new(EapMsChapV2::server(b"User", "clientPass"))]);
+        let challenge = server.start().unwrap();
+        assert!(peer.process(&challenge).unwrap().is_some());
+        assert_eq!(peer.process(&EapPayload::success(1)), failed);
+        assert_eq!(peer.process(&EapPayload::failure(1)), failed);
+
+        // a Response whose MS-Length would overflow is refused
+        let mut long_name = EapMsChapV2::peer(&[b'u'; 0x10000], "clientPass");
+        assert_eq!(
+            long_name.process(challenge.identifier, &challenge.type_data),
+            Err(Error::InvalidEapPacket)
+        );
+
+        // Notifications are acknowledged
+        let notification = EapPayload::request(3, EapType::NOTIFICATION, b"hello".to_vec());
+        assert_eq!(
+            peer.process(&notification),
+            Ok(Some(EapPayload::response(3, EapType::NOTIFICATION, vec![])))
+        );
+    }
+}
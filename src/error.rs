@@ -0,0 +1,180 @@
+use std::fmt;
+
+use deku::DekuError;
+
+use crate::consts::{
+    ConfigAttributeType, ExchangeType, IdType, LastSubstructure, NotifyType, PayloadType,
+    ProtocolIdentifier, TransformType,
+};
+use crate::transform;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// A fixed-layout structure failed to decode or encode.
+    Deku(DekuError),
+    /// The datagram is shorter than the IKE header.
+    TruncatedHeader(usize),
+    /// `IKEHeader::length` disagrees with the size of the datagram.
+    LengthMismatch { header: u32, actual: usize },
+    /// Fewer than four bytes remain where a generic payload header is expected.
+    TruncatedPayload(PayloadType),
+    /// A payload length is smaller than its generic header.
+    InvalidPayloadLength(PayloadType, u16),
+    /// A payload extends past the end of the datagram.
+    PayloadOverrun {
+        payload_type: PayloadType,
+        length: u16,
+        remaining: usize,
+    },
+    /// Bytes remain after the last payload in the chain.
+    TrailingData(usize),
+    /// An encrypted payload is followed by another payload.
+    PayloadAfterEncrypted(PayloadType),
+    /// A payload body does not fit the 16-bit payload length.
+    PayloadTooLong(PayloadType, usize),
+    /// A message does not fit the 32-bit message length.
+    MessageTooLong(usize),
+    /// Proposals are not numbered consecutively from one.
+    ProposalNumber { expected: u8, actual: u8 },
+    /// A proposal length disagrees with the size of its contents.
+    SubstructureLength { declared: u16, actual: usize },
+    /// A `LastSubstructure` marker disagrees with its position in the list.
+    SubstructureMarker {
+        expected: LastSubstructure,
+        actual: LastSubstructure,
+    },
+    /// Key Exchange Data does not have the size required by its method.
+    KeyExchangeLength {
+        group: transform::KE,
+        expected: usize,
+        actual: usize,
+    },
+    /// Key Exchange Data fails the public value checks of RFC 6989.
+    InvalidPublicValue(transform::KE),
+    /// Nonce Data is shorter than 16 or longer than 256 bytes.
+    InvalidNonceLength(usize),
+    /// The SPI size of a Delete payload does not fit its protocol, or an
+    /// IKE SA is deleted with SPIs.
+    InvalidSpiSize(ProtocolIdentifier, u8),
+    /// Identification Data is not valid for its ID Type.
+    InvalidIdentity(IdType),
+    /// An address prefix is longer than its address.
+    InvalidPrefixLength(u8),
+    /// A configuration attribute value is not valid for its type.
+    InvalidConfigAttribute(ConfigAttributeType),
+    /// The exchange failed in a way reported to the peer with this notify.
+    Notify(NotifyType),
+    /// The transform is not implemented by this crate.
+    UnsupportedTransform(TransformType),
+    /// A key does not have the size required by its transform.
+    InvalidKeyLength(usize),
+    /// An IV does not have the size required by its transform.
+    InvalidIvLength(usize),
+    /// The message does not end with an Encrypted payload.
+    MissingEncryptedPayload,
+    /// The Encrypted payload is too short, misaligned or badly padded.
+    InvalidEncryptedPayload,
+    /// The ICV of a protected message does not verify.
+    IntegrityCheckFailed,
+    /// A proposal lacks a transform of the named type.
+    MissingTransform(&'static str),
+    /// A proposal string contains a keyword naming no known algorithm.
+    UnknownKeyword(String),
+    /// The proposal is for a protocol that cannot be used here.
+    UnexpectedProtocol(ProtocolIdentifier),
+    /// An AlgorithmIdentifier is malformed or names an unsupported algorithm.
+    InvalidAlgorithmIdentifier,
+    /// The key cannot produce a signature with the requested algorithm.
+    KeyMismatch,
+    /// More key material is requested than prf+ can produce.
+    KeyMaterialTooLong(usize),
+    /// The message does not fit the exchange or its current state.
+    UnexpectedMessage(ExchangeType),
+    /// A certificate is not valid DER or cannot be re-encoded.
+    InvalidCertificate,
+    /// An EAP packet is malformed or does not fit the conversation.
+    InvalidEapPacket,
+    /// A request was retransmitted without ever being answered.
+    Timeout(ExchangeType),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Deku(e) => write!(f, "{e}"),
+            Error::TruncatedHeader(len) => write!(f, "datagram of {len} bytes has no IKE header"),
+            Error::LengthMismatch { header, actual } => {
+                write!(f, "header length {header} but datagram is {actual} bytes")
+            }
+            Error::TruncatedPayload(t) => write!(f, "truncated {t:?} payload header"),
+            Error::InvalidPayloadLength(t, len) => {
+                write!(f, "{t:?} payload length {len} is shorter than its header")
+            }
+            Error::PayloadOverrun {
+                payload_type,
+                length,
+                remaining,
+            } => write!(
+                f,
+                "{payload_type:?} payload length {length} exceeds remaining {remaining} bytes"
+            ),
+            Error::TrailingData(len) => write!(f, "{len} bytes after the last payload"),
+            Error::PayloadAfterEncrypted(t) => write!(f, "{t:?} payload must be the last"),
+            Error::PayloadTooLong(t, len) => write!(f, "{t:?} payload of {len} bytes is too long"),
+            Error::MessageTooLong(len) => write!(f, "message of {len} bytes is too long"),
+            Error::ProposalNumber { expected, actual } => {
+                write!(f, "proposal number {actual}, expected {expected}")
+            }
+            Error::SubstructureLength { declared, actual } => {
+                write!(
+                    f,
+                    "substructure length {declared}, contents are {actual} bytes"
+                )
+            }
+            Error::SubstructureMarker { expected, actual } => {
+                write!(f, "substructure marker {actual:?}, expected {expected:?}")
+            }
+            Error::KeyExchangeLength {
+                group,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{group:?} key exchange data of {actual} bytes, expected {expected}"
+            ),
+            Error::InvalidPublicValue(group) => write!(f, "invalid {group:?} public value"),
+            Error::InvalidNonceLength(len) => write!(f, "invalid nonce length {len}"),
+            Error::InvalidSpiSize(p, size) => write!(f, "invalid {p:?} SPI size {size}"),
+            Error::InvalidIdentity(t) => write!(f, "invalid {t:?} identification data"),
+            Error::InvalidPrefixLength(len) => write!(f, "invalid prefix length {len}"),
+            Error::InvalidConfigAttribute(t) => write!(f, "invalid {t:?} attribute value"),
+            Error::Notify(t) => write!(f, "{t:?}"),
+            Error::UnsupportedTransform(t) => write!(f, "unsupported transform {t:?}"),
+            Error::InvalidKeyLength(len) => write!(f, "invalid key length {len}"),
+            Error::InvalidIvLength(len) => write!(f, "invalid IV length {len}"),
+            Error::MissingEncryptedPayload => write!(f, "no Encrypted payload"),
+            Error::InvalidEncryptedPayload => write!(f, "malformed Encrypted payload"),
+            Error::IntegrityCheckFailed => write!(f, "integrity check failed"),
+            Error::MissingTransform(t) => write!(f, "proposal has no {t} transform"),
+            Error::UnknownKeyword(k) => write!(f, "unknown algorithm keyword {k:?}"),
+            Error::UnexpectedProtocol(p) => write!(f, "unexpected protocol {p:?}"),
+            Error::InvalidAlgorithmIdentifier => write!(f, "invalid AlgorithmIdentifier"),
+            Error::KeyMismatch => write!(f, "key does not match the signature algorithm"),
+            Error::KeyMaterialTooLong(len) => {
+                write!(f, "{len} bytes of key material exceed prf+ output")
+            }
+            Error::UnexpectedMessage(t) => write!(f, "unexpected {t:?} message"),
+            Error::InvalidCertificate => write!(f, "invalid certificate"),
+            Error::InvalidEapPacket => write!(f, "unexpected EAP packet"),
+            Error::Timeout(t) => write!(f, "no response to {t:?} request"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<DekuError> for Error {
+    fn from(e: DekuError) -> Self {
+        Error::Deku(e)
+    }
+}
@@ -0,0 +1,1853 @@
+use std::collections::VecDeque;
+use std::mem::discriminant;
+use std::num::NonZeroU64;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use deku::prelude::*;
+use rand_core::{OsRng, RngCore};
+
+use crate::auth::AuthPayload;
+use crate::consts::*;
+use crate::error::Error;
+use crate::intermediate::{AdditionalKeyExchanges, encrypt};
+use crate::ke::{self, KeyExchange};
+use crate::keys::{ChildSaKeys, IkeSaKeys};
+use crate::message::*;
+use crate::proposal::{Policy, negotiate};
+use crate::sk::SkCipher;
+use crate::transform;
+use crate::ts::{TrafficSelector, TsPayload, narrow};
+use crate::types::*;
+
+/// Length of the nonces sent, enough for PRFs with 256-bit keys.
+const NONCE_LENGTH: usize = 32;
+
+/// Default time to wait for the first response to a request.
+pub const RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(1);
+/// Default number of retransmissions, each waiting twice as long as the one
+/// before, after which the peer is considered dead.
+pub const RETRANSMIT_TRIES: u32 = 5;
+
+/// Local policy of an IKE SA.
+#[derive(Clone, Debug)]
+pub struct IkeSaConfig {
+    /// Sent in IDi or IDr.
+    pub identity: Identity,
+    /// The shared secret both ends authenticate with.
+    pub psk: Vec<u8>,
+    /// IKE proposals offered as initiator, or accepted as responder.
+    pub ike_proposals: Vec<Proposal>,
+    /// ESP or AH proposals for Child SAs, offered or accepted likewise. A KE
+    /// transform in the first one requests a key exchange in
+    /// CREATE_CHILD_SA.
+    pub child_proposals: Vec<Proposal>,
+    /// Traffic selectors of this end, proposed or narrowed down to.
+    pub local_ts: Vec<TrafficSelector>,
+    /// Traffic selectors of the peer, likewise.
+    pub remote_ts: Vec<TrafficSelector>,
+    pub retransmit_timeout: Duration,
+    pub retransmit_tries: u32,
+}
+
+impl IkeSaConfig {
+    pub fn new(
+        identity: Identity,
+        psk: &[u8],
+        ike_proposals: Vec<Proposal>,
+        child_proposals: Vec<Proposal>,
+        local_ts: Vec<TrafficSelector>,
+        remote_ts: Vec<TrafficSelector>,
+    ) -> Self {
+        IkeSaConfig {
+            identity,
+            psk: psk.to_vec(),
+            ike_proposals,
+            child_proposals,
+            local_ts,
+            remote_ts,
+            retransmit_timeout: RETRANSMIT_TIMEOUT,
+            retransmit_tries: RETRANSMIT_TRIES,
+        }
+    }
+}
+
+/// A Child SA negotiated over the IKE SA, for the caller to install.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChildSa {
+    /// The accepted proposal with one transform of each type and the
+    /// responder's SPI.
+    pub proposal: Proposal,
+    /// SPI of the SA carrying traffic to this end, chosen by this end.
+    pub inbound_spi: [u8; 4],
+    /// SPI of the SA carrying traffic to the peer, chosen by the peer.
+    pub outbound_spi: [u8; 4],
+    /// Whether this end initiated the exchange creating the SA, which makes
+    /// `keys.encr_i` and `keys.integ_i` the keys of its outbound SA.
+    pub initiator: bool,
+    pub keys: ChildSaKeys,
+    pub local_ts: Vec<TrafficSelector>,
+    pub remote_ts: Vec<TrafficSelector>,
+}
+
+/// What an `IkeSa` asks of its caller.
+#[derive(Debug, PartialEq)]
+pub enum Output {
+    /// A datagram for the peer.
+    Send(Vec<u8>),
+    /// IKE_AUTH completed and the peer is authenticated.
+    Established,
+    ChildSaCreated(ChildSa),
+    /// The Child SA was deleted by either end.
+    ChildSaDeleted(ChildSa),
+    /// A Child SA could not be negotiated. The IKE SA is not affected.
+    ChildSaFailed(Error),
+    /// The IKE SA and all its Child SAs are gone, either deleted or ended
+    /// by the error.
+    Closed(Option<Error>),
+}
+
+/// One end of an IKE SA (RFC 7296), driven entirely by its caller: received
+/// datagrams go to `handle`, the deadline of `timeout` to `handle_timeout`,
+/// and every call returns the datagrams to send and the changes to the SA.
+/// Nothing here reads a clock or touches a socket.
+///
+/// IKE_SA_INIT is followed by the IKE_INTERMEDIATE exchanges of any
+/// additional key exchanges and IKE_AUTH with a pre-shared key, which also
+/// creates the first Child SA. Once established, either end can create and
+/// delete Child SAs, check liveness and delete the IKE SA. Requests are sent
+/// one at a time and queued until the previous one is answered. Rekeying
+/// is not supported.
+pub struct IkeSa {
+    config: IkeSaConfig,
+    initiator: bool,
+    state: State,
+    spi_i: NonZeroU64,
+    /// Zero until the initiator receives the IKE_SA_INIT response.
+    spi_r: u64,
+    proposal: Option<Proposal>,
+    keys: Option<IkeSaKeys>,
+    ni: Vec<u8>,
+    nr: Vec<u8>,
+    /// Message ID of the next request sent.
+    next_message_id: u32,
+    /// Message ID of the next request expected from the peer.
+    peer_message_id: u32,
+    outstanding: Option<Outstanding>,
+    /// The response to the peer's last request, sent again if the request is
+    /// retransmitted.
+    last_response: Option<Vec<u8>>,
+    queue: VecDeque<Request>,
+    child_sas: Vec<ChildSa>,
+    peer_identity: Option<Identity>,
+}
+
+enum State {
+    Initial,
+    /// Between IKE_SA_INIT and the end of IKE_AUTH.
+    Handshake {
+        exchanges: Box<AdditionalKeyExchanges>,
+        /// The IKE_SA_INIT request and response, signed by the initiator
+        /// and the responder.
+        init_i: Vec<u8>,
+        init_r: Vec<u8>,
+    },
+    Established,
+    Closed,
+}
+
+/// A request sent and not yet answered.
+struct Outstanding {
+    message_id: u32,
+    exchange_type: ExchangeType,
+    data: Vec<u8>,
+    pending: Pending,
+    retransmit_at: Instant,
+    retransmits: u32,
+}
+
+/// What is needed to process the response to a request.
+enum Pending {
+    SaInit {
+        /// Shared with a retry carrying a COOKIE, which repeats the KE
+        /// payload unchanged.
+        key_exchange: Rc<dyn KeyExchange>,
+        cookie: Option<Vec<u8>>,
+    },
+    Intermediate,
+    Auth(ChildRequest),
+    CreateChildSa(ChildRequest),
+    DeleteChildSa([u8; 4]),
+    Delete,
+    Liveness,
+}
+
+/// The initiator's side of a requested Child SA.
+struct ChildRequest {
+    spi: [u8; 4],
+    /// Ni of CREATE_CHILD_SA, empty in IKE_AUTH where the nonces of
+    /// IKE_SA_INIT are used.
+    nonce: Vec<u8>,
+    key_exchange: Option<Box<dyn KeyExchange>>,
+}
+
+/// A request waiting for the previous one to be answered.
+enum Request {
+    CreateChildSa,
+    DeleteChildSa([u8; 4]),
+    Delete,
+    Liveness,
+}
+
+impl IkeSa {
+    /// Starts an IKE SA with a fresh SPI and returns its IKE_SA_INIT
+    /// request, using the key exchange method of the first proposal.
+    pub fn initiator(config: IkeSaConfig, now: Instant) -> Result<(Self, Vec<Output>), Error> {
+        let spi_i = loop {
+            if let Some(spi) = NonZeroU64::new(OsRng.next_u64()) {
+                break spi;
+            }
+        };
+        let method = config
+            .ike_proposals
+            .first()
+            .and_then(Proposal::ke)
+            .ok_or(Error::MissingTransform("KE"))?
+            .clone();
+        let mut sa = IkeSa::new(config, true, spi_i);
+        sa.ni = NoncePayload::random(NONCE_LENGTH)?.nonce;
+        let key_exchange = ke::generate(&method)?;
+        let output = sa.send_sa_init(now, key_exchange.into(), None)?;
+        Ok((sa, vec![output]))
+    }
+
+    /// Creates the responder's end of an IKE SA from the IKE_SA_INIT request
+    /// in `data` and returns its response.
+    ///
+    /// An unacceptable request is answered with an error notify. The SA then
+    /// either stays ready for the initiator's retry with another key
+    /// exchange method, or is closed.
+    pub fn responder(
+        config: IkeSaConfig,
+        now: Instant,
+        data: &[u8],
+    ) -> Result<(Self, Vec<Output>), Error> {
+        if data.len() < IKE_HEADER_LENGTH {
+            return Err(Error::TruncatedHeader(data.len()));
+        }
+        let header = IKEHeader::try_from(&data[..IKE_HEADER_LENGTH])?;
+        let mut sa = IkeSa::new(config, false, header.initiator_spi);
+        sa.spi_r = loop {
+            let spi = OsRng.next_u64();
+            if spi != 0 {
+                break spi;
+            }
+        };
+        let outputs = sa.handle(now, data)?;
+        Ok((sa, outputs))
+    }
+
+    fn new(config: IkeSaConfig, initiator: bool, spi_i: NonZeroU64) -> Self {
+        IkeSa {
+            config,
+            initiator,
+            state: State::Initial,
+            spi_i,
+            spi_r: 0,
+            proposal: None,
+            keys: None,
+            ni: vec![],
+            nr: vec![],
+            next_message_id: 0,
+            peer_message_id: 0,
+            outstanding: None,
+            last_response: None,
+            queue: VecDeque::new(),
+            child_sas: vec![],
+            peer_identity: None,
+        }
+    }
+
+    pub fn is_initiator(&self) -> bool {
+        self.initiator
+    }
+
+    pub fn is_established(&self) -> bool {
+        matches!(self.state, State::Established)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        matches!(self.state, State::Closed)
+    }
+
+    /// The initiator's and the responder's SPI.
+    pub fn spis(&self) -> (NonZeroU64, u64) {
+        (self.spi_i, self.spi_r)
+    }
+
+    /// The negotiated IKE proposal, once IKE_SA_INIT is done.
+    pub fn proposal(&self) -> Option<&Proposal> {
+        self.proposal.as_ref()
+    }
+
+    /// The current keys, once IKE_SA_INIT is done.
+    pub fn keys(&self) -> Option<&IkeSaKeys> {
+        self.keys.as_ref()
+    }
+
+    /// The identity the peer authenticated with.
+    pub fn peer_identity(&self) -> Option<&Identity> {
+        self.peer_identity.as_ref()
+    }
+
+    pub fn child_sas(&self) -> &[ChildSa] {
+        &self.child_sas
+    }
+
+    /// The message IDs of the next request sent and of the next request
+    /// expected from the peer.
+    pub fn message_ids(&self) -> (u32, u32) {
+        (self.next_message_id, self.peer_message_id)
+    }
+
+    /// When `handle_timeout` is due next.
+    pub fn timeout(&self) -> Option<Instant> {
+        self.outstanding.as_ref().map(|o| o.retransmit_at)
+    }
+
+    /// Retransmits the outstanding request if it is due, or closes the SA
+    /// once all retransmissions went unanswered.
+    pub fn handle_timeout(&mut self, now: Instant) -> Vec<Output> {
+        let Some(outstanding) = &mut self.outstanding else {
+            return vec![];
+        };
+        if now < outstanding.retransmit_at {
+            return vec![];
+        }
+        if outstanding.retransmits >= self.config.retransmit_tries {
+            let exchange_type = outstanding.exchange_type.clone();
+            return vec![self.close(Some(Error::Timeout(exchange_type)))];
+        }
+        outstanding.retransmits += 1;
+        let timeout = self
+            .config
+            .retransmit_timeout
+            .saturating_mul(1 << outstanding.retransmits.min(16));
+        outstanding.retransmit_at = now + timeout;
+        vec![Output::Send(outstanding.data.clone())]
+    }
+
+    /// Requests another Child SA with the configured proposals and traffic
+    /// selectors.
+    pub fn create_child_sa(&mut self, now: Instant) -> Result<Vec<Output>, Error> {
+        self.request(now, Request::CreateChildSa)
+    }
+
+    /// Deletes the Child SA with the given inbound SPI.
+    pub fn delete_child_sa(&mut self, now: Instant, spi: [u8; 4]) -> Result<Vec<Output>, Error> {
+        if !self.child_sas.iter().any(|c| c.inbound_spi == spi) {
+            return Err(Error::Notify(NotifyType::CHILD_SA_NOT_FOUND));
+        }
+        self.request(now, Request::DeleteChildSa(spi))
+    }
+
+    /// Deletes the IKE SA along with its Child SAs.
+    pub fn delete(&mut self, now: Instant) -> Result<Vec<Output>, Error> {
+        self.request(now, Request::Delete)
+    }
+
+    /// Sends an empty INFORMATIONAL request to check that the peer is
+    /// alive (RFC 7296 §2.4).
+    pub fn liveness(&mut self, now: Instant) -> Result<Vec<Output>, Error> {
+        self.request(now, Request::Liveness)
+    }
+
+    /// Processes a datagram received from the peer.
+    ///
+    /// Fails without changing the SA if the datagram is malformed, does not
+    /// belong to the SA or does not fit its state; such datagrams are to be
+    /// dropped. A retransmitted request is answered with the response sent
+    /// before.
+    pub fn handle(&mut self, now: Instant, data: &[u8]) -> Result<Vec<Output>, Error> {
+        if data.len() < IKE_HEADER_LENGTH {
+            return Err(Error::TruncatedHeader(data.len()));
+        }
+        let header = IKEHeader::try_from(&data[..IKE_HEADER_LENGTH])?;
+        if header.major_version != 2 {
+            return Err(Error::Notify(NotifyType::INVALID_MAJOR_VERSION));
+        }
+        if header.initiator_spi != self.spi_i
+            || (header.exchange_type != ExchangeType::IKE_SA_INIT
+                && header.responder_spi != self.spi_r)
+        {
+            return Err(Error::Notify(NotifyType::INVALID_IKE_SPI));
+        }
+        if header.flags.initiator == self.initiator || self.is_closed() {
+            return Err(Error::UnexpectedMessage(header.exchange_type));
+        }
+        if header.flags.response {
+            self.handle_response(now, &header, data)
+        } else {
+            self.handle_request(&header, data)
+        }
+    }
+
+    fn handle_request(&mut self, header: &IKEHeader, data: &[u8]) -> Result<Vec<Output>, Error> {
+        if header.message_id.wrapping_add(1) == self.peer_message_id
+            && let Some(response) = &self.last_response
+        {
+            return Ok(vec![Output::Send(response.clone())]);
+        }
+        if header.message_id != self.peer_message_id {
+            return Err(Error::Notify(NotifyType::INVALID_MESSAGE_ID));
+        }
+        let exchange_type = &header.exchange_type;
+        match (exchange_type, &self.state) {
+            (ExchangeType::IKE_SA_INIT, State::Initial) if !self.initiator => {
+                self.respond_sa_init(data)
+            }
+            (ExchangeType::IKE_INTERMEDIATE, State::Handshake { .. }) if !self.initiator => {
+                self.respond_intermediate(data)
+            }
+            (ExchangeType::IKE_AUTH, State::Handshake { exchanges, .. })
+                if !self.initiator && exchanges.is_complete() =>
+            {
+                self.respond_auth(data)
+            }
+            (ExchangeType::CREATE_CHILD_SA, State::Established) => {
+                self.respond_create_child_sa(data)
+            }
+            (ExchangeType::INFORMATIONAL, State::Established) => self.respond_informational(data),
+            _ => Err(Error::UnexpectedMessage(exchange_type.clone())),
+        }
+    }
+
+    fn handle_response(
+        &mut self,
+        now: Instant,
+        header: &IKEHeader,
+        data: &[u8],
+    ) -> Result<Vec<Output>, Error> {
+        let Some(outstanding) = self.outstanding.take_if(|o| {
+            o.message_id == header.message_id && o.exchange_type == header.exchange_type
+        }) else {
+            return Err(Error::UnexpectedMessage(header.exchange_type.clone()));
+        };
+        let result = match &outstanding.pending {
+            Pending::SaInit {
+                key_exchange,
+                cookie,
+            } => self.handle_sa_init(now, header, data, &outstanding.data, key_exchange, cookie),
+            Pending::Intermediate => self.handle_intermediate(now, data),
+            Pending::Auth(child) => self.handle_auth(data, outstanding.message_id, child),
+            Pending::CreateChildSa(child) => self.handle_create_child_sa(data, child),
+            Pending::DeleteChildSa(spi) => self.handle_delete_child_sa(data, *spi),
+            Pending::Delete => self.unprotect(data).map(|_| vec![self.close(None)]),
+            Pending::Liveness => self.unprotect(data).map(|_| vec![]),
+        };
+        let mut outputs = match result {
+            Ok(outputs) => outputs,
+            Err(e) => {
+                self.outstanding = Some(outstanding);
+                return Err(e);
+            }
+        };
+        if self.outstanding.is_none() {
+            outputs.extend(self.send_queued(now)?);
+        }
+        Ok(outputs)
+    }
+
+    fn request(&mut self, now: Instant, request: Request) -> Result<Vec<Output>, Error> {
+        if !self.is_established() {
+            let exchange_type = match request {
+                Request::CreateChildSa => ExchangeType::CREATE_CHILD_SA,
+                _ => ExchangeType::INFORMATIONAL,
+            };
+            return Err(Error::UnexpectedMessage(exchange_type));
+        }
+        self.queue.push_back(request);
+        if self.outstanding.is_some() {
+            return Ok(vec![]);
+        }
+        self.send_queued(now)
+    }
+
+    /// Sends the next queued request of an established SA.
+    fn send_queued(&mut self, now: Instant) -> Result<Vec<Output>, Error> {
+        if !self.is_established() {
+            return Ok(vec![]);
+        }
+        let Some(request) = self.queue.pop_front() else {
+            return Ok(vec![]);
+        };
+        let message_id = self.next_message_id;
+        let (exchange_type, payloads, pending) = match request {
+            Request::CreateChildSa => {
+                let spi = child_spi();
+                let nonce = NoncePayload::random(NONCE_LENGTH)?;
+                let method = self
+                    .config
+                    .child_proposals
+                    .first()
+                    .and_then(Proposal::ke)
+                    .filter(|ke| **ke != transform::KE::NONE);
+                let key_exchange = method.map(ke::generate).transpose()?;
+                let mut payloads = vec![
+                    payload(
+                        PayloadType::SA,
+                        PayloadBody::SA(SaPayload {
+                            proposals: numbered(&self.config.child_proposals, &spi),
+                        }),
+                    ),
+                    payload(PayloadType::Nonce, PayloadBody::Nonce(nonce.clone())),
+                ];
+                if let Some(key_exchange) = &key_exchange {
+                    payloads.push(ke_payload(
+                        key_exchange.method(),
+                        key_exchange.public_value(),
+                    ));
+                }
+                payloads.extend(self.ts_payloads());
+                let child = ChildRequest {
+                    spi,
+                    nonce: nonce.nonce,
+                    key_exchange,
+                };
+                (
+                    ExchangeType::CREATE_CHILD_SA,
+                    payloads,
+                    Pending::CreateChildSa(child),
+                )
+            }
+            Request::DeleteChildSa(spi) => {
+                let Some(child) = self.child_sas.iter().find(|c| c.inbound_spi == spi) else {
+                    return self.send_queued(now);
+                };
+                let delete = DeletePayload::child_sas(child.proposal.protocol_id.clone(), &[spi])?;
+                (
+                    ExchangeType::INFORMATIONAL,
+                    vec![payload(PayloadType::D, PayloadBody::D(delete))],
+                    Pending::DeleteChildSa(spi),
+                )
+            }
+            Request::Delete => (
+                ExchangeType::INFORMATIONAL,
+                vec![payload(
+                    PayloadType::D,
+                    PayloadBody::D(DeletePayload::ike()),
+                )],
+                Pending::Delete,
+            ),
+            Request::Liveness => (ExchangeType::INFORMATIONAL, vec![], Pending::Liveness),
+        };
+        let message = IkeMessage {
+            header: self.header(exchange_type.clone(), false, message_id),
+            payloads,
+        };
+        let data = self.protect(&message)?;
+        Ok(vec![self.send(
+            now,
+            message_id,
+            exchange_type,
+            data,
+            pending,
+        )])
+    }
+
+    /// Sends a request and waits for its response.
+    fn send(
+        &mut self,
+        now: Instant,
+        message_id: u32,
+        exchange_type: ExchangeType,
+        data: Vec<u8>,
+        pending: Pending,
+    ) -> Output {
+        self.next_message_id = message_id.wrapping_add(1);
+        self.outstanding = Some(Outstanding {
+            message_id,
+            exchange_type,
+            data: data.clone(),
+            pending,
+            retransmit_at: now + self.config.retransmit_timeout,
+            retransmits: 0,
+        });
+        Output::Send(data)
+    }
+
+    /// Answers the peer's current request.
+    fn reply(&mut self, data: Vec<u8>) -> Output {
+        self.peer_message_id = self.peer_message_id.wrapping_add(1);
+        self.last_response = Some(data.clone());
+        Output::Send(data)
+    }
+
+    fn close(&mut self, error: Option<Error>) -> Output {
+        self.state = State::Closed;
+        self.outstanding = None;
+        self.queue.clear();
+        self.child_sas.clear();
+        Output::Closed(error)
+    }
+
+    fn send_sa_init(
+        &mut self,
+        now: Instant,
+        key_exchange: Rc<dyn KeyExchange>,
+        cookie: Option<Vec<u8>>,
+    ) -> Result<Output, Error> {
+        let mut payloads = vec![];
+        if let Some(cookie) = &cookie {
+            payloads.push(notify(NotifyType::COOKIE, cookie.clone()));
+        }
+        payloads.extend([
+            payload(
+                PayloadType::SA,
+                PayloadBody::SA(SaPayload {
+                    proposals: numbered(&self.config.ike_proposals, &[]),
+                }),
+            ),
+            ke_payload(key_exchange.method(), key_exchange.public_value()),
+            payload(
+                PayloadType::Nonce,
+                PayloadBody::Nonce(NoncePayload {
+                    nonce: self.ni.clone(),
+                }),
+            ),
+        ]);
+        if self
+            .config
+            .ike_proposals
+            .iter()
+            .any(|p| !p.additional_key_exchanges().is_empty())
+        {
+            payloads.push(notify(NotifyType::INTERMEDIATE_EXCHANGE_SUPPORTED, vec![]));
+        }
+        let message = IkeMessage {
+            header: self.header(ExchangeType::IKE_SA_INIT, false, 0),
+            payloads,
+        };
+        let data = message.to_bytes()?;
+        let pending = Pending::SaInit {
+            key_exchange,
+            cookie,
+        };
+        Ok(self.send(now, 0, ExchangeType::IKE_SA_INIT, data, pending))
+    }
+
+    fn respond_sa_init(&mut self, data: &[u8]) -> Result<Vec<Output>, Error> {
+        let request = IkeMessage::parse(data)?;
+        let (Some(PayloadBody::SA(sa)), Some(PayloadBody::KE(ke)), Some(PayloadBody::Nonce(nonce))) = (
+            body(&request, PayloadType::SA),
+            body(&request, PayloadType::KE),
+            body(&request, PayloadType::Nonce),
+        ) else {
+            return Err(Error::Notify(NotifyType::INVALID_SYNTAX));
+        };
+        let proposal = match select(&sa.proposals, &self.config.ike_proposals) {
+            Ok(proposal) => proposal,
+            Err(e) => {
+                let response = self.sa_init_error(NotifyType::NO_PROPOSAL_CHOSEN, vec![])?;
+                return Ok(vec![Output::Send(response), self.close(Some(e))]);
+            }
+        };
+        let method = proposal.ke().ok_or(Error::MissingTransform("KE"))?;
+        if ke.dh_group != *method {
+            // the initiator retries with the same SPI, so nothing is kept
+            let response =
+                self.sa_init_error(NotifyType::INVALID_KE_PAYLOAD, method.to_bytes()?)?;
+            return Ok(vec![Output::Send(response)]);
+        }
+        let (public_value, shared_secret) = ke::respond(method, &ke.data)?;
+
+        self.ni = nonce.nonce.clone();
+        self.nr = NoncePayload::random(NONCE_LENGTH)?.nonce;
+        let mut payloads = vec![
+            payload(
+                PayloadType::SA,
+                PayloadBody::SA(SaPayload {
+                    proposals: vec![proposal.clone()],
+                }),
+            ),
+            ke_payload(method.clone(), public_value),
+            payload(
+                PayloadType::Nonce,
+                PayloadBody::Nonce(NoncePayload {
+                    nonce: self.nr.clone(),
+                }),
+            ),
+        ];
+        if find_notify(&request, NotifyType::INTERMEDIATE_EXCHANGE_SUPPORTED).is_some() {
+            payloads.push(notify(NotifyType::INTERMEDIATE_EXCHANGE_SUPPORTED, vec![]));
+        }
+        let response = IkeMessage {
+            header: self.header(ExchangeType::IKE_SA_INIT, true, 0),
+            payloads,
+        }
+        .to_bytes()?;
+        self.start_handshake(proposal, &shared_secret, data.to_vec(), response.clone())?;
+        Ok(vec![self.reply(response)])
+    }
+
+    /// An unprotected IKE_SA_INIT response that creates no IKE SA.
+    fn sa_init_error(&self, notify_type: NotifyType, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+        let mut header = self.header(ExchangeType::IKE_SA_INIT, true, 0);
+        header.responder_spi = 0;
+        IkeMessage {
+            header,
+            payloads: vec![notify(notify_type, data)],
+        }
+        .to_bytes()
+    }
+
+    fn handle_sa_init(
+        &mut self,
+        now: Instant,
+        header: &IKEHeader,
+        data: &[u8],
+        request: &[u8],
+        key_exchange: &Rc<dyn KeyExchange>,
+        cookie: &Option<Vec<u8>>,
+    ) -> Result<Vec<Output>, Error> {
+        let response = IkeMessage::parse(data)?;
+        if let Some(n) = find_notify(&response, NotifyType::COOKIE) {
+            // RFC 7296 §2.6: all other payloads stay unchanged
+            let key_exchange = Rc::clone(key_exchange);
+            let output = self.send_sa_init(now, key_exchange, Some(n.data.clone()))?;
+            return Ok(vec![output]);
+        }
+        if let Some(n) = find_notify(&response, NotifyType::INVALID_KE_PAYLOAD) {
+            let offered = |method: &transform::KE| {
+                self.config.ike_proposals.iter().any(|p| {
+                    p.transforms
+                        .iter()
+                        .any(|t| t.transform_type == TransformType::KE(0, method.clone()))
+                })
+            };
+            return Ok(match transform::KE::from_bytes((&n.data[..], 0)) {
+                Ok((_, method)) if offered(&method) && method != key_exchange.method() => {
+                    let key_exchange = ke::generate(&method)?;
+                    vec![self.send_sa_init(now, key_exchange.into(), cookie.clone())?]
+                }
+                _ => vec![self.close(Some(Error::Notify(NotifyType::INVALID_KE_PAYLOAD)))],
+            });
+        }
+        if let Some(notify_type) = error_notify(&response) {
+            return Ok(vec![self.close(Some(Error::Notify(notify_type)))]);
+        }
+
+        let syntax = || Error::Notify(NotifyType::INVALID_SYNTAX);
+        let (Some(PayloadBody::SA(sa)), Some(PayloadBody::KE(ke)), Some(PayloadBody::Nonce(nonce))) = (
+            body(&response, PayloadType::SA),
+            body(&response, PayloadType::KE),
+            body(&response, PayloadType::Nonce),
+        ) else {
+            return Err(syntax());
+        };
+        let [proposal] = &sa.proposals[..] else {
+            return Err(syntax());
+        };
+        if header.responder_spi == 0
+            || !is_chosen_from(proposal, &self.config.ike_proposals)
+            || proposal.ke() != Some(&key_exchange.method())
+            || ke.dh_group != key_exchange.method()
+        {
+            return Err(syntax());
+        }
+        let shared_secret = key_exchange.shared_secret(&ke.data)?;
+
+        self.spi_r = header.responder_spi;
+        self.nr = nonce.nonce.clone();
+        self.start_handshake(
+            proposal.clone(),
+            &shared_secret,
+            request.to_vec(),
+            data.to_vec(),
+        )?;
+        self.continue_handshake(now)
+    }
+
+    /// Derives the keys from IKE_SA_INIT and prepares the IKE_INTERMEDIATE
+    /// exchanges that may follow it.
+    fn start_handshake(
+        &mut self,
+        proposal: Proposal,
+        shared_secret: &[u8],
+        init_i: Vec<u8>,
+        init_r: Vec<u8>,
+    ) -> Result<(), Error> {
+        let header = self.header(ExchangeType::IKE_SA_INIT, !self.initiator, 0);
+        let keys = IkeSaKeys::derive(&proposal, &header, &self.ni, &self.nr, shared_secret)?;
+        let exchanges = Box::new(AdditionalKeyExchanges::new(
+            self.initiator,
+            &proposal,
+            &header,
+            &self.ni,
+            &self.nr,
+            keys.clone(),
+        ));
+        self.proposal = Some(proposal);
+        self.keys = Some(keys);
+        self.state = State::Handshake {
+            exchanges,
+            init_i,
+            init_r,
+        };
+        Ok(())
+    }
+
+    /// Sends the initiator's next IKE_INTERMEDIATE request, or its IKE_AUTH
+    /// request once there are none left.
+    fn continue_handshake(&mut self, now: Instant) -> Result<Vec<Output>, Error> {
+        let message_id = self.next_message_id;
+        let State::Handshake {
+            exchanges, init_i, ..
+        } = &mut self.state
+        else {
+            return Err(Error::UnexpectedMessage(ExchangeType::IKE_AUTH));
+        };
+        if let Some(data) = exchanges.request(message_id)? {
+            let exchange_type = ExchangeType::IKE_INTERMEDIATE;
+            return Ok(vec![self.send(
+                now,
+                message_id,
+                exchange_type,
+                data,
+                Pending::Intermediate,
+            )]);
+        }
+
+        let id = IdPayload::from(self.config.identity.clone());
+        let octets = exchanges.signed_octets(true, init_i, &id, message_id)?;
+        let auth = AuthPayload::psk(self.prf()?, &self.config.psk, &octets)?;
+        let spi = child_spi();
+        let mut payloads = vec![
+            payload(PayloadType::IDi, PayloadBody::ID(id)),
+            payload(PayloadType::AUTH, PayloadBody::AUTH(auth)),
+            payload(
+                PayloadType::SA,
+                PayloadBody::SA(SaPayload {
+                    proposals: numbered(&self.config.child_proposals, &spi),
+                }),
+            ),
+        ];
+        payloads.extend(self.ts_payloads());
+        let message = IkeMessage {
+            header: self.header(ExchangeType::IKE_AUTH, false, message_id),
+            payloads,
+        };
+        let data = self.protect(&message)?;
+        let child = ChildRequest {
+            spi,
+            nonce: vec![],
+            key_exchange: None,
+        };
+        let pending = Pending::Auth(child);
+        Ok(vec![self.send(
+            now,
+            message_id,
+            ExchangeType::IKE_AUTH,
+            data,
+            pending,
+        )])
+    }
+
+    fn respond_intermediate(&mut self, data: &[u8]) -> Result<Vec<Output>, Error> {
+        let State::Handshake { exchanges, .. } = &mut self.state else {
+            return Err(Error::UnexpectedMessage(ExchangeType::IKE_INTERMEDIATE));
+        };
+        let response = exchanges.respond(data)?;
+        self.keys = Some(exchanges.keys().clone());
+        Ok(vec![self.reply(response)])
+    }
+
+    fn handle_intermediate(&mut self, now: Instant, data: &[u8]) -> Result<Vec<Output>, Error> {
+        let State::Handshake { exchanges, .. } = &mut self.state else {
+            return Err(Error::UnexpectedMessage(ExchangeType::IKE_INTERMEDIATE));
+        };
+        exchanges.handle_response(data)?;
+        self.keys = Some(exchanges.keys().clone());
+        self.continue_handshake(now)
+    }
+
+    fn respond_auth(&mut self, data: &[u8]) -> Result<Vec<Output>, Error> {
+        let request = self.unprotect(data)?;
+        let (Some(PayloadBody::ID(idi)), Some(PayloadBody::AUTH(auth))) = (
+            body(&request, PayloadType::IDi),
+            body(&request, PayloadType::AUTH),
+        ) else {
+            return Err(Error::Notify(NotifyType::INVALID_SYNTAX));
+        };
+        let peer_identity = idi.identity()?;
+        let State::Handshake {
+            exchanges,
+            init_i,
+            init_r,
+        } = &self.state
+        else {
+            return Err(Error::UnexpectedMessage(ExchangeType::IKE_AUTH));
+        };
+        let message_id = request.header.message_id;
+        let prf = self.prf()?;
+        let octets = exchanges.signed_octets(true, init_i, idi, message_id)?;
+        if let Err(e) = auth.verify_psk(prf, &self.config.psk, &octets) {
+            let response = self.protect(&IkeMessage {
+                header: self.header(ExchangeType::IKE_AUTH, true, message_id),
+                payloads: vec![notify(NotifyType::AUTHENTICATION_FAILED, vec![])],
+            })?;
+            return Ok(vec![self.reply(response), self.close(Some(e))]);
+        }
+
+        let idr = IdPayload::from(self.config.identity.clone());
+        let octets = exchanges.signed_octets(false, init_r, &idr, message_id)?;
+        let mut payloads = vec![
+            payload(PayloadType::IDr, PayloadBody::ID(idr)),
+            payload(
+                PayloadType::AUTH,
+                PayloadBody::AUTH(AuthPayload::psk(prf, &self.config.psk, &octets)?),
+            ),
+        ];
+        let mut outputs = vec![Output::Established];
+        if body(&request, PayloadType::SA).is_some() {
+            match self.accept_child_sa(&request) {
+                Ok((child, child_payloads)) => {
+                    payloads.extend(child_payloads);
+                    outputs.push(Output::ChildSaCreated(child));
+                }
+                Err(e) => {
+                    payloads.push(self.child_sa_error(&request, &e)?);
+                    outputs.push(Output::ChildSaFailed(e));
+                }
+            }
+        }
+        let response = self.protect(&IkeMessage {
+            header: self.header(ExchangeType::IKE_AUTH, true, message_id),
+            payloads,
+        })?;
+        self.peer_identity = Some(peer_identity);
+        self.state = State::Established;
+        for output in &outputs {
+            if let Output::ChildSaCreated(child) = output {
+                self.child_sas.push(child.clone());
+            }
+        }
+        outputs.insert(0, self.reply(response));
+        Ok(outputs)
+    }
+
+    fn handle_auth(
+        &mut self,
+        data: &[u8],
+        message_id: u32,
+        child: &ChildRequest,
+    ) -> Result<Vec<Output>, Error> {
+        let response = self.unprotect(data)?;
+        let (Some(PayloadBody::ID(idr)), Some(PayloadBody::AUTH(auth))) = (
+            body(&response, PayloadType::IDr),
+            body(&response, PayloadType::AUTH),
+        ) else {
+            let notify_type = error_notify(&response).unwrap_or(NotifyType::INVALID_SYNTAX);
+            return Ok(vec![self.close(Some(Error::Notify(notify_type)))]);
+        };
+        let State::Handshake {
+            exchanges, init_r, ..
+        } = &self.state
+        else {
+            return Err(Error::UnexpectedMessage(ExchangeType::IKE_AUTH));
+        };
+        let octets = exchanges.signed_octets(false, init_r, idr, message_id)?;
+        let verified = auth.verify_psk(self.prf()?, &self.config.psk, &octets);
+        let peer_identity = match verified.and_then(|_| idr.identity()) {
+            Ok(identity) => identity,
+            Err(e) => return Ok(vec![self.close(Some(e))]),
+        };
+
+        self.peer_identity = Some(peer_identity);
+        self.state = State::Established;
+        let mut outputs = vec![Output::Established];
+        outputs.push(match self.complete_child_sa(&response, child) {
+            Ok(child) => {
+                self.child_sas.push(child.clone());
+                Output::ChildSaCreated(child)
+            }
+            Err(e) => Output::ChildSaFailed(e),
+        });
+        Ok(outputs)
+    }
+
+    fn respond_create_child_sa(&mut self, data: &[u8]) -> Result<Vec<Output>, Error> {
+        let request = self.unprotect(data)?;
+        let (payloads, output) = match self.accept_child_sa(&request) {
+            Ok((child, payloads)) => {
+                self.child_sas.push(child.clone());
+                (payloads, Output::ChildSaCreated(child))
+            }
+            Err(e) => (
+                vec![self.child_sa_error(&request, &e)?],
+                Output::ChildSaFailed(e),
+            ),
+        };
+        let response = self.protect(&IkeMessage {
+            header: self.header(
+                ExchangeType::CREATE_CHILD_SA,
+                true,
+                request.header.message_id,
+            ),
+            payloads,
+        })?;
+        Ok(vec![self.reply(response), output])
+    }
+
+    fn handle_create_child_sa(
+        &mut self,
+        data: &[u8],
+        child: &ChildRequest,
+    ) -> Result<Vec<Output>, Error> {
+        let response = self.unprotect(data)?;
+        Ok(vec![match self.complete_child_sa(&response, child) {
+            Ok(child) => {
+                self.child_sas.push(child.clone());
+                Output::ChildSaCreated(child)
+            }
+            Err(e) => Output::ChildSaFailed(e),
+        }])
+    }
+
+    /// The responder's side of a Child SA requested in IKE_AUTH or
+    /// CREATE_CHILD_SA, along with the payloads of the response creating it.
+    fn accept_child_sa(&self, request: &IkeMessage) -> Result<(ChildSa, Vec<Payload>), Error> {
+        let syntax = || Error::Notify(NotifyType::INVALID_SYNTAX);
+        let (Some(PayloadBody::SA(sa)), Some(PayloadBody::TS(tsi)), Some(PayloadBody::TS(tsr))) = (
+            body(request, PayloadType::SA),
+            body(request, PayloadType::TSi),
+            body(request, PayloadType::TSr),
+        ) else {
+            return Err(syntax());
+        };
+        let mut proposal = select(&sa.proposals, &self.config.child_proposals)?;
+        let outbound_spi = <[u8; 4]>::try_from(&proposal.spi[..]).map_err(|_| syntax())?;
+        let remote_ts = narrow(&tsi.traffic_selectors, &self.config.remote_ts)?;
+        let local_ts = narrow(&tsr.traffic_selectors, &self.config.local_ts)?;
+        let inbound_spi = child_spi();
+        proposal.spi = inbound_spi.to_vec();
+
+        let mut payloads = vec![payload(
+            PayloadType::SA,
+            PayloadBody::SA(SaPayload {
+                proposals: vec![proposal.clone()],
+            }),
+        )];
+        let (ni, nr, shared_secret) = if request.header.exchange_type == ExchangeType::IKE_AUTH {
+            (self.ni.clone(), self.nr.clone(), None)
+        } else {
+            let Some(PayloadBody::Nonce(ni)) = body(request, PayloadType::Nonce) else {
+                return Err(syntax());
+            };
+            let nr = NoncePayload::random(NONCE_LENGTH)?;
+            payloads.push(payload(PayloadType::Nonce, PayloadBody::Nonce(nr.clone())));
+            let shared_secret = match (proposal.ke(), body(request, PayloadType::KE)) {
+                (None | Some(transform::KE::NONE), _) => None,
+                (Some(method), Some(PayloadBody::KE(ke))) if ke.dh_group == *method => {
+                    let (public_value, shared_secret) = ke::respond(method, &ke.data)?;
+                    payloads.push(ke_payload(method.clone(), public_value));
+                    Some(shared_secret)
+                }
+                _ => return Err(Error::Notify(NotifyType::INVALID_KE_PAYLOAD)),
+            };
+            (ni.nonce.clone(), nr.nonce, shared_secret)
+        };
+        payloads.extend([
+            ts_payload(PayloadType::TSi, &remote_ts),
+            ts_payload(PayloadType::TSr, &local_ts),
+        ]);
+
+        let keys = ChildSaKeys::derive(
+            self.prf()?,
+            &self.sk_d()?,
+            &proposal,
+            shared_secret.as_deref(),
+            &ni,
+            &nr,
+        )?;
+        let child = ChildSa {
+            proposal,
+            inbound_spi,
+            outbound_spi,
+            initiator: false,
+            keys,
+            local_ts,
+            remote_ts,
+        };
+        Ok((child, payloads))
+    }
+
+    /// The notify refusing a Child SA, which for INVALID_KE_PAYLOAD names
+    /// the key exchange method of the proposal that would be accepted.
+    fn child_sa_error(&self, request: &IkeMessage, error: &Error) -> Result<Payload, Error> {
+        let notify_type = match error {
+            Error::Notify(notify_type) => notify_type.clone(),
+            _ => NotifyType::NO_PROPOSAL_CHOSEN,
+        };
+        let mut data = vec![];
+        if notify_type == NotifyType::INVALID_KE_PAYLOAD
+            && let Some(PayloadBody::SA(sa)) = body(request, PayloadType::SA)
+            && let Ok(proposal) = select(&sa.proposals, &self.config.child_proposals)
+            && let Some(method) = proposal.ke()
+        {
+            data = method.to_bytes()?;
+        }
+        Ok(notify(notify_type, data))
+    }
+
+    /// The initiator's side of a Child SA from the response to its request.
+    fn complete_child_sa(
+        &self,
+        response: &IkeMessage,
+        request: &ChildRequest,
+    ) -> Result<ChildSa, Error> {
+        if let Some(notify_type) = error_notify(response) {
+            return Err(Error::Notify(notify_type));
+        }
+        let syntax = || Error::Notify(NotifyType::INVALID_SYNTAX);
+        let (Some(PayloadBody::SA(sa)), Some(PayloadBody::TS(tsi)), Some(PayloadBody::TS(tsr))) = (
+            body(response, PayloadType::SA),
+            body(response, PayloadType::TSi),
+            body(response, PayloadType::TSr),
+        ) else {
+            return Err(syntax());
+        };
+        let [proposal] = &sa.proposals[..] else {
+            return Err(syntax());
+        };
+        if !is_chosen_from(proposal, &self.config.child_proposals) {
+            return Err(syntax());
+        }
+        let outbound_spi = <[u8; 4]>::try_from(&proposal.spi[..]).map_err(|_| syntax())?;
+        let within = |selectors: &[TrafficSelector], policy: &[TrafficSelector]| {
+            selectors
+                .iter()
+                .all(|s| policy.iter().any(|p| s.is_subset_of(p)))
+        };
+        if !within(&tsi.traffic_selectors, &self.config.local_ts)
+            || !within(&tsr.traffic_selectors, &self.config.remote_ts)
+        {
+            return Err(Error::Notify(NotifyType::TS_UNACCEPTABLE));
+        }
+
+        let (ni, nr, shared_secret) = if request.nonce.is_empty() {
+            (&self.ni, self.nr.clone(), None)
+        } else {
+            let Some(PayloadBody::Nonce(nr)) = body(response, PayloadType::Nonce) else {
+                return Err(syntax());
+            };
+            let shared_secret = match (proposal.ke(), &request.key_exchange) {
+                (None | Some(transform::KE::NONE), _) => None,
+                (Some(method), Some(key_exchange)) if *method == key_exchange.method() => {
+                    let Some(PayloadBody::KE(ke)) = body(response, PayloadType::KE) else {
+                        return Err(syntax());
+                    };
+                    if ke.dh_group != *method {
+                        return Err(syntax());
+                    }
+                    Some(key_exchange.shared_secret(&ke.data)?)
+                }
+                _ => return Err(syntax()),
+            };
+            (&request.nonce, nr.nonce.clone(), shared_secret)
+        };
+        let keys = ChildSaKeys::derive(
+            self.prf()?,
+            &self.sk_d()?,
+            proposal,
+            shared_secret.as_deref(),
+            ni,
+            &nr,
+        )?;
+        Ok(ChildSa {
+            proposal: proposal.clone(),
+            inbound_spi: request.spi,
+            outbound_spi,
+            initiator: true,
+            keys,
+            local_ts: tsi.traffic_selectors.clone(),
+            remote_ts: tsr.traffic_selectors.clone(),
+        })
+    }
+
+    fn respond_informational(&mut self, data: &[u8]) -> Result<Vec<Output>, Error> {
+        let request = self.unprotect(data)?;
+        let message_id = request.header.message_id;
+        let deletes = request.payloads.iter().filter_map(|p| match &p.body {
+            PayloadBody::D(delete) => Some(delete),
+            _ => None,
+        });
+        let mut payloads = vec![];
+        let mut outputs = vec![];
+        let mut closed = false;
+        for delete in deletes {
+            if delete.protocol_id == ProtocolIdentifier::IKE {
+                closed = true;
+                continue;
+            }
+            let mut spis = vec![];
+            for spi in &delete.spis {
+                let Some(i) = self.child_sas.iter().position(|c| {
+                    c.proposal.protocol_id == delete.protocol_id && c.outbound_spi[..] == spi[..]
+                }) else {
+                    continue;
+                };
+                let child = self.child_sas.remove(i);
+                spis.push(child.inbound_spi);
+                outputs.push(Output::ChildSaDeleted(child));
+            }
+            if !spis.is_empty() {
+                let delete = DeletePayload::child_sas(delete.protocol_id.clone(), &spis)?;
+                payloads.push(payload(PayloadType::D, PayloadBody::D(delete)));
+            }
+        }
+        if closed {
+            payloads.clear();
+            outputs.clear();
+        }
+        let response = self.protect(&IkeMessage {
+            header: self.header(ExchangeType::INFORMATIONAL, true, message_id),
+            payloads,
+        })?;
+        outputs.insert(0, self.reply(response));
+        if closed {
+            outputs.push(self.close(None));
+        }
+        Ok(outputs)
+    }
+
+    fn handle_delete_child_sa(&mut self, data: &[u8], spi: [u8; 4]) -> Result<Vec<Output>, Error> {
+        self.unprotect(data)?;
+        let Some(i) = self.child_sas.iter().position(|c| c.inbound_spi == spi) else {
+            return Ok(vec![]);
+        };
+        Ok(vec![Output::ChildSaDeleted(self.child_sas.remove(i))])
+    }
+
+    /// A header of this end with both SPIs.
+    fn header(&self, exchange_type: ExchangeType, response: bool, message_id: u32) -> IKEHeader {
+        IKEHeader {
+            initiator_spi: self.spi_i,
+            responder_spi: self.spi_r,
+            next_payload: PayloadType::NoNextPayload,
+            major_version: 2,
+            minor_version: 0,
+            exchange_type,
+            flags: Flags {
+                unused_0: false,
+                unused_1: false,
+                response,
+                version: false,
+                initiator: self.initiator,
+                unused_2: false,
+                unused_3: false,
+                unused_4: false,
+            },
+            message_id,
+            length: 0,
+        }
+    }
+
+    /// The TSi and TSr payloads of a request.
+    fn ts_payloads(&self) -> [Payload; 2] {
+        [
+            ts_payload(PayloadType::TSi, &self.config.local_ts),
+            ts_payload(PayloadType::TSr, &self.config.remote_ts),
+        ]
+    }
+
+    /// Protects messages sent by the initiator (or the responder).
+    fn cipher(&self, initiator: bool) -> Result<SkCipher, Error> {
+        let (Some(proposal), Some(keys)) = (&self.proposal, &self.keys) else {
+            return Err(Error::UnexpectedMessage(ExchangeType::IKE_SA_INIT));
+        };
+        SkCipher::from_keys(proposal, keys, initiator)
+    }
+
+    fn protect(&self, message: &IkeMessage) -> Result<Vec<u8>, Error> {
+        encrypt(&self.cipher(self.initiator)?, message)
+    }
+
+    fn unprotect(&self, data: &[u8]) -> Result<IkeMessage, Error> {
+        self.cipher(!self.initiator)?.decrypt(data)
+    }
+
+    fn prf(&self) -> Result<&transform::PRF, Error> {
+        self.proposal
+            .as_ref()
+            .and_then(Proposal::prf)
+            .ok_or(Error::MissingTransform("PRF"))
+    }
+
+    fn sk_d(&self) -> Result<Vec<u8>, Error> {
+        self.keys
+            .as_ref()
+            .map(|keys| keys.sk_d.clone())
+            .ok_or(Error::UnexpectedMessage(ExchangeType::IKE_SA_INIT))
+    }
+}
+
+/// Negotiates one of the peer's proposals with the configured ones as
+/// policies.
+fn select(received: &[Proposal], configured: &[Proposal]) -> Result<Proposal, Error> {
+    let policies: Vec<Policy> = configured.iter().map(Policy::from).collect();
+    negotiate(received, &policies)
+}
+
+/// Whether the responder's proposal takes one transform of each type from
+/// the offered proposal it names.
+fn is_chosen_from(chosen: &Proposal, offered: &[Proposal]) -> bool {
+    let Some(offer) = usize::from(chosen.proposal_num)
+        .checked_sub(1)
+        .and_then(|i| offered.get(i))
+    else {
+        return false;
+    };
+    let kinds = |p: &Proposal| {
+        let mut kinds = vec![];
+        for kind in p.transforms.iter().map(|t| discriminant(&t.transform_type)) {
+            if !kinds.contains(&kind) {
+                kinds.push(kind);
+            }
+        }
+        kinds
+    };
+    chosen.protocol_id == offer.protocol_id
+        && kinds(chosen).len() == chosen.transforms.len()
+        && kinds(chosen).len() == kinds(offer).len()
+        && chosen.transforms.iter().all(|t| {
+            offer
+                .transforms
+                .iter()
+                .any(|o| o.transform_type == t.transform_type && o.key_length() == t.key_length())
+        })
+}
+
+/// Copies of the proposals numbered from one, all with the given SPI.
+fn numbered(proposals: &[Proposal], spi: &[u8]) -> Vec<Proposal> {
+    proposals
+        .iter()
+        .zip(1..)
+        .map(|(proposal, proposal_num)| Proposal {
+            proposal_num,
+            spi: spi.to_vec(),
+            ..proposal.clone()
+        })
+        .collect()
+}
+
+/// A random SPI for an inbound Child SA, outside the reserved values 0-255.
+fn child_spi() -> [u8; 4] {
+    loop {
+        let spi = OsRng.next_u32();
+        if spi > 255 {
+            return spi.to_be_bytes();
+        }
+    }
+}
+
+fn payload(payload_type: PayloadType, body: PayloadBody) -> Payload {
+    Payload {
+        payload_type,
+        critical: false,
+        body,
+    }
+}
+
+fn notify(notify_type: NotifyType, data: Vec<u8>) -> Payload {
+    payload(
+        PayloadType::N,
+        PayloadBody::N(NotifyPayload {
+            protocol_id: ProtocolIdentifier::Reserved,
+            spi_size: 0,
+            notify_type,
+            spi: vec![],
+            data,
+        }),
+    )
+}
+
+fn ke_payload(dh_group: transform::KE, data: Vec<u8>) -> Payload {
+    payload(
+        PayloadType::KE,
+        PayloadBody::KE(KePayload {
+            dh_group,
+            reserved: 0,
+            data,
+        }),
+    )
+}
+
+fn ts_payload(payload_type: PayloadType, traffic_selectors: &[TrafficSelector]) -> Payload {
+    payload(
+        payload_type,
+        PayloadBody::TS(TsPayload {
+            number_of_ts: 0,
+            reserved: [0; 3],
+            traffic_selectors: traffic_selectors.to_vec(),
+        }),
+    )
+}
+
+/// The body of the first payload of the given type.
+fn body(message: &IkeMessage, payload_type: PayloadType) -> Option<&PayloadBody> {
+    message
+        .payloads
+        .iter()
+        .find(|p| p.payload_type == payload_type)
+        .map(|p| &p.body)
+}
+
+fn find_notify(message: &IkeMessage, notify_type: NotifyType) -> Option<&NotifyPayload> {
+    message.payloads.iter().find_map(|p| match &p.body {
+        PayloadBody::N(n) if n.notify_type == notify_type => Some(n),
+        _ => None,
+    })
+}
+
+/// The type of the first error notify.
+fn error_notify(message: &IkeMessage) -> Option<NotifyType> {
+    message.payloads.iter().find_map(|p| match &p.body {
+        PayloadBody::N(n) if n.notify_type.is_error() => Some(n.notify_type.clone()),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::IpAddr;
+
+    use super::*;
+    use crate::test_util::{proposal, transform};
+
+    fn ike_proposal(methods: &[transform::KE], additional: Option<transform::KE>) -> Proposal {
+        let mut transforms = vec![
+            transform(
+                TransformType::ENCR(0, transform::ENCR::ENCR_AES_GCM_16),
+                Some(256),
+            ),
+            transform(
+                TransformType::PRF(0, transform::PRF::PRF_HMAC_SHA2_256),
+                None,
+            ),
+        ];
+        for method in methods {
+            transforms.push(transform(TransformType::KE(0, method.clone()), None));
+        }
+        if let Some(method) = additional {
+            transforms.push(transform(TransformType::ADDKE1(0, method), None));
+        }
+        proposal(ProtocolIdentifier::IKE, transforms)
+    }
+
+    fn esp_proposal(method: Option<transform::KE>) -> Proposal {
+        let mut transforms = vec![
+            transform(
+                TransformType::ENCR(0, transform::ENCR::ENCR_AES_GCM_16),
+                Some(128),
+            ),
+            transform(TransformType::SN(0, transform::SN::Sequential_32), None),
+        ];
+        if let Some(method) = method {
+            transforms.push(transform(TransformType::KE(0, method), None));
+        }
+        proposal(ProtocolIdentifier::ESP, transforms)
+    }
+
+    fn prefix(address: &str, prefix_len: u8) -> TrafficSelector {
+        TrafficSelector::from_prefix(address.parse::<IpAddr>().unwrap(), prefix_len).unwrap()
+    }
+
+    fn configs(ike_proposals: Vec<Proposal>) -> (IkeSaConfig, IkeSaConfig) {
+        let initiator = IkeSaConfig::new(
+            Identity::Fqdn("moon.example.com".to_string()),
+            b"shared secret",
+            ike_proposals.clone(),
+            vec![esp_proposal(None)],
+            vec![prefix("10.1.0.0", 16)],
+            vec![prefix("10.2.0.0", 16)],
+        );
+        let responder = IkeSaConfig::new(
+            Identity::Fqdn("sun.example.com".to_string()),
+            b"shared secret",
+            ike_proposals,
+            vec![esp_proposal(None)],
+            vec![prefix("10.2.0.0", 16)],
+            vec![prefix("10.0.0.0", 8)],
+        );
+        (initiator, responder)
+    }
+
+    /// The only datagram among the outputs.
+    fn sent(outputs: &[Output]) -> Vec<u8> {
+        let mut sent = outputs.iter().filter_map(|output| match output {
+            Output::Send(data) => Some(data.clone()),
+            _ => None,
+        });
+        let data = sent.next().expect("nothing sent");
+        assert!(sent.next().is_none());
+        data
+    }
+
+    /// The outputs other than datagrams.
+    fn events(outputs: &[Output]) -> Vec<&Output> {
+        outputs
+            .iter()
+            .filter(|output| !matches!(output, Output::Send(_)))
+            .collect()
+    }
+
+    fn created(outputs: &[Output]) -> ChildSa {
+        match outputs
+            .iter()
+            .find(|o| matches!(o, Output::ChildSaCreated(_)))
+        {
+            Some(Output::ChildSaCreated(child)) => child.clone(),
+            _ => panic!("no Child SA created in {outputs:?}"),
+        }
+    }
+
+    /// Runs IKE_SA_INIT, any IKE_INTERMEDIATE exchanges and IKE_AUTH,
+    /// returning the last outputs of either end.
+    fn establish(
+        initiator: IkeSaConfig,
+        responder: IkeSaConfig,
+        now: Instant,
+    ) -> ((IkeSa, Vec<Output>), (IkeSa, Vec<Output>)) {
+        let (mut initiator, outputs) = IkeSa::initiator(initiator, now).unwrap();
+        let (mut responder, mut responses) =
+            IkeSa::responder(responder, now, &sent(&outputs)).unwrap();
+        loop {
+            let outputs = initiator.handle(now, &sent(&responses)).unwrap();
+            if !outputs.iter().any(|o| matches!(o, Output::Send(_))) {
+                return ((initiator, outputs), (responder, responses));
+            }
+            responses = responder.handle(now, &sent(&outputs)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_establish() {
+        let now = Instant::now();
+        for (additional, message_id) in [(None, 2), (Some(transform::KE::ML_KEM_768), 3)] {
+            let (initiator_config, responder_config) = configs(vec![ike_proposal(
+                &[transform::KE::ECP_256],
+                additional.clone(),
+            )]);
+            let ((initiator, outputs), (responder, responses)) =
+                establish(initiator_config, responder_config, now);
+            let child_i = created(&outputs);
+            let child_r = created(&responses);
+            assert_eq!(
+                events(&outputs),
+                [
+                    &Output::Established,
+                    &Output::ChildSaCreated(child_i.clone())
+                ]
+            );
+            assert_eq!(
+                events(&responses),
+                [
+                    &Output::Established,
+                    &Output::ChildSaCreated(child_r.clone())
+                ]
+            );
+
+            for sa in [&initiator, &responder] {
+                assert!(sa.is_established());
+                assert_eq!(sa.timeout(), None);
+                assert_eq!(sa.spis(), initiator.spis());
+                assert_eq!(sa.keys(), initiator.keys());
+                assert_eq!(
+                    sa.proposal().unwrap().additional_key_exchanges(),
+                    additional.iter().collect::<Vec<_>>()
+                );
+            }
+            assert_ne!(initiator.spis().1, 0);
+            assert_eq!(initiator.message_ids(), (message_id, 0));
+            assert_eq!(responder.message_ids(), (0, message_id));
+            assert_eq!(
+                initiator.peer_identity(),
+                Some(&Identity::Fqdn("sun.example.com".to_string()))
+            );
+            assert_eq!(
+                responder.peer_identity(),
+                Some(&Identity::Fqdn("moon.example.com".to_string()))
+            );
+
+            // the Child SA of IKE_AUTH, narrowed to the responder's policy
+            assert_eq!(initiator.child_sas(), std::slice::from_ref(&child_i));
+            assert_eq!(responder.child_sas(), std::slice::from_ref(&child_r));
+            assert_eq!(child_i.inbound_spi, child_r.outbound_spi);
+            assert_eq!(child_i.outbound_spi, child_r.inbound_spi);
+            assert_eq!(child_r.proposal.spi, child_r.inbound_spi);
+            assert_eq!(child_i.proposal, child_r.proposal);
+            assert_eq!(child_i.keys, child_r.keys);
+            assert!(child_i.initiator && !child_r.initiator);
+            assert_eq!(child_i.local_ts, [prefix("10.1.0.0", 16)]);
+            assert_eq!(child_i.remote_ts, [prefix("10.2.0.0", 16)]);
+            assert_eq!(child_r.local_ts, child_i.remote_ts);
+            assert_eq!(child_r.remote_ts, child_i.local_ts);
+        }
+    }
+
+    #[test]
+    fn test_child_sas() {
+        let now = Instant::now();
+        let (mut initiator_config, mut responder_config) =
+            configs(vec![ike_proposal(&[transform::KE::ECP_256], None)]);
+        initiator_config.child_proposals = vec![esp_proposal(Some(transform::KE::Curve25519))];
+        responder_config.child_proposals = vec![
+            esp_proposal(Some(transform::KE::ECP_256)),
+            esp_proposal(Some(transform::KE::Curve25519)),
+        ];
+        let ((mut initiator, _), (mut responder, _)) =
+            establish(initiator_config, responder_config, now);
+
+        // the responder's preferred key exchange is refused
+        let request = sent(&responder.create_child_sa(now).unwrap());
+        assert_eq!(responder.timeout(), Some(now + RETRANSMIT_TIMEOUT));
+        let outputs = initiator.handle(now, &request).unwrap();
+        let refused = Output::ChildSaFailed(Error::Notify(NotifyType::INVALID_KE_PAYLOAD));
+        assert_eq!(events(&outputs), [&refused]);
+        assert_eq!(responder.handle(now, &sent(&outputs)), Ok(vec![refused]));
+
+        // while the initiator's is accepted
+        let request = sent(&initiator.create_child_sa(now).unwrap());
+        let outputs = responder.handle(now, &request).unwrap();
+        let child_r = created(&outputs);
+        let outputs = initiator.handle(now, &sent(&outputs)).unwrap();
+        let child_i = created(&outputs);
+        assert_eq!(events(&outputs), [&Output::ChildSaCreated(child_i.clone())]);
+        assert!(child_i.initiator && !child_r.initiator);
+        assert_eq!(child_i.keys, child_r.keys);
+        assert_eq!(child_i.inbound_spi, child_r.outbound_spi);
+        assert_eq!(child_i.proposal.ke(), Some(&transform::KE::Curve25519));
+        assert_eq!(initiator.child_sas().len(), 2);
+        assert_eq!(initiator.message_ids(), (3, 1));
+        assert_eq!(responder.message_ids(), (1, 3));
+
+        // requests wait for the outstanding one
+        let auth_child_i = initiator.child_sas()[0].clone();
+        let auth_child_r = responder.child_sas()[0].clone();
+        let request = sent(&initiator.liveness(now).unwrap());
+        assert_eq!(
+            initiator.delete_child_sa(now, auth_child_i.inbound_spi),
+            Ok(vec![])
+        );
+        assert_eq!(
+            initiator.delete_child_sa(now, [0; 4]),
+            Err(Error::Notify(NotifyType::CHILD_SA_NOT_FOUND))
+        );
+        let outputs = responder.handle(now, &request).unwrap();
+        assert!(events(&outputs).is_empty());
+        let request = sent(&initiator.handle(now, &sent(&outputs)).unwrap());
+        let outputs = responder.handle(now, &request).unwrap();
+        let response = sent(&outputs);
+        assert_eq!(events(&outputs), [&Output::ChildSaDeleted(auth_child_r)]);
+        assert_eq!(
+            initiator.handle(now, &response),
+            Ok(vec![Output::ChildSaDeleted(auth_child_i)])
+        );
+        assert_eq!(initiator.child_sas(), [child_i]);
+        assert_eq!(responder.child_sas(), [child_r]);
+
+        // deleting the IKE SA ends both ends
+        let request = sent(&responder.delete(now).unwrap());
+        let outputs = initiator.handle(now, &request).unwrap();
+        assert_eq!(events(&outputs), [&Output::Closed(None)]);
+        assert_eq!(
+            responder.handle(now, &sent(&outputs)),
+            Ok(vec![Output::Closed(None)])
+        );
+        for sa in [&mut initiator, &mut responder] {
+            assert!(sa.is_closed());
+            assert!(sa.child_sas().is_empty());
+            assert_eq!(
+                sa.create_child_sa(now),
+                Err(Error::UnexpectedMessage(ExchangeType::CREATE_CHILD_SA))
+            );
+        }
+    }
+
+    #[test]
+    fn test_retransmission() {
+        let now = Instant::now();
+        let second = Duration::from_secs(1);
+        let (initiator_config, responder_config) =
+            configs(vec![ike_proposal(&[transform::KE::Curve25519], None)]);
+        let (mut initiator, outputs) = IkeSa::initiator(initiator_config, now).unwrap();
+        let sa_init = sent(&outputs);
+
+        // lost requests are sent again after 1, 2, 4, ... seconds
+        assert_eq!(initiator.timeout(), Some(now + second));
+        assert!(initiator.handle_timeout(now).is_empty());
+        assert_eq!(
+            initiator.handle_timeout(now + second),
+            [Output::Send(sa_init.clone())]
+        );
+        assert_eq!(initiator.timeout(), Some(now + 3 * second));
+
+        let (mut responder, responses) = IkeSa::responder(responder_config, now, &sa_init).unwrap();
+        let auth = sent(&initiator.handle(now, &sent(&responses)).unwrap());
+        assert_eq!(initiator.timeout(), Some(now + second));
+        let response = sent(&responder.handle(now, &auth).unwrap());
+
+        // retransmitted requests get the same response
+        assert_eq!(
+            responder.handle(now, &auth),
+            Ok(vec![Output::Send(response)])
+        );
+        assert_eq!(responder.message_ids(), (0, 2));
+        assert_eq!(
+            responder.handle(now, &sa_init),
+            Err(Error::Notify(NotifyType::INVALID_MESSAGE_ID))
+        );
+
+        // responses to other SAs or requests are dropped
+        let mut other = auth.clone();
+        other[0] ^= 1;
+        assert_eq!(
+            initiator.handle(now, &other),
+            Err(Error::Notify(NotifyType::INVALID_IKE_SPI))
+        );
+        assert_eq!(
+            initiator.handle(now, &sent(&responses)),
+            Err(Error::UnexpectedMessage(ExchangeType::IKE_SA_INIT))
+        );
+
+        // until the retransmissions run out
+        let mut at = now;
+        for retransmit in 1..=RETRANSMIT_TRIES {
+            at = initiator.timeout().unwrap();
+            assert_eq!(at - now, (1 << retransmit) * second - second);
+            assert_eq!(initiator.handle_timeout(at), [Output::Send(auth.clone())]);
+        }
+        assert_eq!(
+            initiator.handle_timeout(initiator.timeout().unwrap()),
+            [Output::Closed(Some(Error::Timeout(ExchangeType::IKE_AUTH)))]
+        );
+        assert!(initiator.is_closed());
+        assert_eq!(initiator.timeout(), None);
+        assert!(initiator.handle_timeout(at + 100 * second).is_empty());
+    }
+
+    #[test]
+    fn test_failures() {
+        let now = Instant::now();
+
+        // the initiator retries with the key exchange method asked for
+        let (initiator_config, mut responder_config) = configs(vec![ike_proposal(
+            &[transform::KE::ECP_256, transform::KE::Curve25519],
+            None,
+        )]);
+        responder_config.ike_proposals = vec![ike_proposal(&[transform::KE::Curve25519], None)];
+        let (mut initiator, outputs) = IkeSa::initiator(initiator_config.clone(), now).unwrap();
+        let (mut responder, responses) =
+            IkeSa::responder(responder_config.clone(), now, &sent(&outputs)).unwrap();
+        assert_eq!(responder.message_ids(), (0, 0));
+        let response = IkeMessage::parse(&sent(&responses)).unwrap();
+        assert_eq!(response.header.responder_spi, 0);
+        assert_eq!(
+            find_notify(&response, NotifyType::INVALID_KE_PAYLOAD).map(|n| &n.data[..]),
+            Some(&[0, 31][..])
+        );
+        let request = sent(&initiator.handle(now, &sent(&responses)).unwrap());
+        let request = IkeMessage::parse(&request).unwrap();
+        let Some(PayloadBody::KE(ke)) = body(&request, PayloadType::KE) else {
+            panic!("no KE payload");
+        };
+        assert_eq!(ke.dh_group, transform::KE::Curve25519);
+        assert_eq!(request.header.message_id, 0);
+        let responses = responder.handle(now, &request.to_bytes().unwrap()).unwrap();
+        initiator.handle(now, &sent(&responses)).unwrap();
+        assert_eq!(
+            initiator.proposal().and_then(Proposal::ke),
+            Some(&transform::KE::Curve25519)
+        );
+
+        // no acceptable proposal
+        let mut config = responder_config.clone();
+        config.ike_proposals[0].transforms[0].transform_attributes =
+            vec![Attribute::KeyLength(128)];
+        let (mut initiator, outputs) = IkeSa::initiator(initiator_config.clone(), now).unwrap();
+        let (responder, responses) = IkeSa::responder(config, now, &sent(&outputs)).unwrap();
+        assert!(responder.is_closed());
+        assert_eq!(
+            events(&responses),
+            [&Output::Closed(Some(Error::Notify(
+                NotifyType::NO_PROPOSAL_CHOSEN
+            )))]
+        );
+        assert_eq!(
+            initiator.handle(now, &sent(&responses)),
+            Ok(vec![Output::Closed(Some(Error::Notify(
+                NotifyType::NO_PROPOSAL_CHOSEN
+            )))])
+        );
+
+        // the traffic selectors of the Child SA are unacceptable
+        let mut config = responder_config.clone();
+        config.remote_ts = vec![prefix("192.0.2.0", 24)];
+        let ((initiator, outputs), (responder, responses)) =
+            establish(initiator_config.clone(), config, now);
+        let refused = Output::ChildSaFailed(Error::Notify(NotifyType::TS_UNACCEPTABLE));
+        assert_eq!(events(&outputs), [&Output::Established, &refused]);
+        assert_eq!(events(&responses), [&Output::Established, &refused]);
+        assert!(initiator.child_sas().is_empty() && responder.child_sas().is_empty());
+
+        // the pre-shared keys differ
+        let mut config = responder_config;
+        config.psk = b"another secret".to_vec();
+        let ((initiator, outputs), (responder, responses)) =
+            establish(initiator_config, config, now);
+        let failed = Error::Notify(NotifyType::AUTHENTICATION_FAILED);
+        assert_eq!(events(&responses), [&Output::Closed(Some(failed))]);
+        assert_eq!(
+            outputs,
+            [Output::Closed(Some(Error::Notify(
+                NotifyType::AUTHENTICATION_FAILED
+            )))]
+        );
+        assert!(initiator.is_closed() && responder.is_closed());
+    }
+
+    #[test]
+    fn test_cookie() {
+        let now = Instant::now();
+        let (initiator_config, responder_config) =
+            configs(vec![ike_proposal(&[transform::KE::Curve25519], None)]);
+        let (mut initiator, outputs) = IkeSa::initiator(initiator_config, now).unwrap();
+        let request = sent(&outputs);
+        let (responder, _) = IkeSa::responder(responder_config, now, &request).unwrap();
+
+        // the retry only puts the COOKIE in front, keeping the KE payload
+        let cookie = responder
+            .sa_init_error(NotifyType::COOKIE, vec![0xc0; 16])
+            .unwrap();
+        let retry = sent(&initiator.handle(now, &cookie).unwrap());
+        let (request, retry) = (
+            IkeMessage::parse(&request).unwrap(),
+            IkeMessage::parse(&retry).unwrap(),
+        );
+        assert_eq!(
+            retry.payloads[0],
+            notify(NotifyType::COOKIE, vec![0xc0; 16])
+        );
+        assert_eq!(retry.payloads[1..], request.payloads);
+        let ke = |message: &IkeMessage| match body(message, PayloadType::KE) {
+            Some(PayloadBody::KE(ke)) => ke.to_bytes().unwrap(),
+            _ => panic!("no KE payload"),
+        };
+        assert_eq!(ke(&retry), ke(&request));
+
+        // and so does a second round
+        let retry_2 = sent(&initiator.handle(now, &cookie).unwrap());
+        assert_eq!(IkeMessage::parse(&retry_2).unwrap(), retry);
+    }
+}
@@ -0,0 +1,475 @@
+use deku::prelude::*;
+use rand_core::{OsRng, RngCore};
+
+use crate::auth::signed_octets;
+use crate::consts::*;
+use crate::error::Error;
+use crate::ke::{self, KeyExchange};
+use crate::keys::{IkeSaKeys, prf};
+use crate::message::*;
+use crate::sk::SkCipher;
+use crate::transform;
+use crate::types::*;
+
+/// Drives the additional key exchanges of RFC 9370 through IKE_INTERMEDIATE
+/// exchanges (RFC 9242) between IKE_SA_INIT and IKE_AUTH, one for each
+/// ADDKEn transform of the negotiated proposal.
+///
+/// Each exchange is protected with the current keys, which are replaced
+/// once it completes. The messages of every exchange are accumulated into
+/// IntAuth, which the AUTH payloads of IKE_AUTH then cover.
+pub struct AdditionalKeyExchanges {
+    initiator: bool,
+    proposal: Proposal,
+    header: IKEHeader,
+    ni: Vec<u8>,
+    nr: Vec<u8>,
+    keys: IkeSaKeys,
+    /// Methods still to be performed, in reverse order.
+    methods: Vec<transform::KE>,
+    /// The initiator's outstanding request.
+    pending: Option<(u32, Box<dyn KeyExchange>)>,
+    int_auth_i: Vec<u8>,
+    int_auth_r: Vec<u8>,
+}
+
+impl AdditionalKeyExchanges {
+    /// Starts from the IKE_SA_INIT exchange: its negotiated proposal, the
+    /// header of its response carrying both SPIs, both nonces and the keys
+    /// derived from its key exchange.
+    pub fn new(
+        initiator: bool,
+        proposal: &Proposal,
+        header: &IKEHeader,
+        ni: &[u8],
+        nr: &[u8],
+        keys: IkeSaKeys,
+    ) -> Self {
+        let mut methods: Vec<_> = proposal
+            .additional_key_exchanges()
+            .into_iter()
+            .cloned()
+            .collect();
+        methods.reverse();
+        AdditionalKeyExchanges {
+            initiator,
+            proposal: proposal.clone(),
+            header: header.clone(),
+            ni: ni.to_vec(),
+            nr: nr.to_vec(),
+            keys,
+            methods,
+            pending: None,
+            int_auth_i: vec![],
+            int_auth_r: vec![],
+        }
+    }
+
+    /// The keys after the exchanges completed so far.
+    pub fn keys(&self) -> &IkeSaKeys {
+        &self.keys
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.methods.is_empty() && self.pending.is_none()
+    }
+
+    /// The initiator's next IKE_INTERMEDIATE request, or `None` once all
+    /// additional key exchanges are done.
+    pub fn request(&mut self, message_id: u32) -> Result<Option<Vec<u8>>, Error> {
+        if !self.initiator || self.pending.is_some() {
+            return Err(Error::UnexpectedMessage(ExchangeType::IKE_INTERMEDIATE));
+        }
+        let Some(method) = self.methods.last() else {
+            return Ok(None);
+        };
+        let key_exchange = ke::generate(method)?;
+        let request = self.message(message_id, method, key_exchange.public_value());
+        let int_auth_i = self.int_auth(true, &request)?;
+        let data = encrypt(&self.cipher(true)?, &request)?;
+        self.int_auth_i = int_auth_i;
+        self.methods.pop();
+        self.pending = Some((message_id, key_exchange));
+        Ok(Some(data))
+    }
+
+    /// The responder's answer to an IKE_INTERMEDIATE request. Its keys are
+    /// updated once the response is built.
+    pub fn respond(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let unexpected = Error::UnexpectedMessage(ExchangeType::IKE_INTERMEDIATE);
+        let Some(method) = self.methods.last().filter(|_| !self.initiator).cloned() else {
+            return Err(unexpected);
+        };
+        let request = self.cipher(true)?.decrypt(data)?;
+        if request.header.exchange_type != ExchangeType::IKE_INTERMEDIATE
+            || request.header.flags.response
+        {
+            return Err(unexpected);
+        }
+        let peer = key_exchange_data(&request, &method)?;
+        let (public_value, shared_secret) = ke::respond(&method, peer)?;
+
+        let response = self.message(request.header.message_id, &method, public_value);
+        let int_auth_i = self.int_auth(true, &request)?;
+        let int_auth_r = self.int_auth(false, &response)?;
+        let data = encrypt(&self.cipher(false)?, &response)?;
+        self.int_auth_i = int_auth_i;
+        self.int_auth_r = int_auth_r;
+        self.update(&shared_secret)?;
+        self.methods.pop();
+        Ok(data)
+    }
+
+    /// Completes the initiator's outstanding exchange with its response.
+    pub fn handle_response(&mut self, data: &[u8]) -> Result<(), Error> {
+        let unexpected = Error::UnexpectedMessage(ExchangeType::IKE_INTERMEDIATE);
+        let Some((message_id, key_exchange)) = &self.pending else {
+            return Err(unexpected);
+        };
+        let response = self.cipher(false)?.decrypt(data)?;
+        if response.header.exchange_type != ExchangeType::IKE_INTERMEDIATE
+            || !response.header.flags.response
+            || response.header.message_id != *message_id
+        {
+            return Err(unexpected);
+        }
+        let peer = key_exchange_data(&response, &key_exchange.method())?;
+        let shared_secret = key_exchange.shared_secret(peer)?;
+        self.int_auth_r = self.int_auth(false, &response)?;
+        self.update(&shared_secret)?;
+        self.pending = None;
+        Ok(())
+    }
+
+    /// IntAuth = IntAuth_iN | IntAuth_rN | IKE_AUTH_MID, empty if no
+    /// IKE_INTERMEDIATE exchange took place.
+    pub fn int_auth_octets(&self, ike_auth_message_id: u32) -> Vec<u8> {
+        if self.int_auth_i.is_empty() {
+            return vec![];
+        }
+        [
+            &self.int_auth_i[..],
+            &self.int_auth_r,
+            &ike_auth_message_id.to_be_bytes(),
+        ]
+        .concat()
+    }
+
+    /// The octets covered by the initiator's (or the responder's) AUTH
+    /// payload, as `auth::signed_octets` computes them with the final SK_pi
+    /// (or SK_pr), followed by IntAuth (RFC 9242 §3.3.2). `message` is the
+    /// initiator's (or the responder's) IKE_SA_INIT message.
+    pub fn signed_octets(
+        &self,
+        initiator: bool,
+        message: &[u8],
+        id: &IdPayload,
+        ike_auth_message_id: u32,
+    ) -> Result<Vec<u8>, Error> {
+        let prf = self.prf()?;
+        let octets = if initiator {
+            signed_octets(prf, &self.keys.sk_pi, message, &self.nr, id)?
+        } else {
+            signed_octets(prf, &self.keys.sk_pr, message, &self.ni, id)?
+        };
+        Ok([octets, self.int_auth_octets(ike_auth_message_id)].concat())
+    }
+
+    fn prf(&self) -> Result<&transform::PRF, Error> {
+        self.proposal.prf().ok_or(Error::MissingTransform("PRF"))
+    }
+
+    /// Protects messages sent by the initiator (or the responder).
+    fn cipher(&self, initiator: bool) -> Result<SkCipher, Error> {
+        SkCipher::from_keys(&self.proposal, &self.keys, initiator)
+    }
+
+    /// IntAuth_i (or IntAuth_r) extended with a message sent by the
+    /// initiator (or the responder):
+    /// IntAuth_in = prf(SK_pi, IntAuth_i(n-1) | IntAuth_in_A | IntAuth_in_P)
+    fn int_auth(&self, initiator: bool, message: &IkeMessage) -> Result<Vec<u8>, Error> {
+        let (sk_p, previous) = if initiator {
+            (&self.keys.sk_pi, &self.int_auth_i)
+        } else {
+            (&self.keys.sk_pr, &self.int_auth_r)
+        };
+        let data = [&previous[..], &int_auth_data(message)?].concat();
+        prf(self.prf()?, sk_p, &data)
+    }
+
+    fn update(&mut self, shared_secret: &[u8]) -> Result<(), Error> {
+        self.keys = self.keys.update(
+            &self.proposal,
+            &self.header,
+            &self.ni,
+            &self.nr,
+            shared_secret,
+        )?;
+        Ok(())
+    }
+
+    /// An IKE_INTERMEDIATE message of this side carrying a KE payload.
+    fn message(&self, message_id: u32, method: &transform::KE, data: Vec<u8>) -> IkeMessage {
+        let mut header = self.header.clone();
+        header.exchange_type = ExchangeType::IKE_INTERMEDIATE;
+        header.flags = Flags {
+            unused_0: false,
+            unused_1: false,
+            response: !self.initiator,
+            version: false,
+            initiator: self.initiator,
+            unused_2: false,
+            unused_3: false,
+            unused_4: false,
+        };
+        header.message_id = message_id;
+        IkeMessage {
+            header,
+            payloads: vec![Payload {
+                payload_type: PayloadType::KE,
+                critical: false,
+                body: PayloadBody::KE(KePayload {
+                    dh_group: method.clone(),
+                    reserved: 0,
+                    data,
+                }),
+            }],
+        }
+    }
+}
+
+/// IntAuth_A | IntAuth_P (RFC 9242 §3.3.2) of a message in the plaintext
+/// form `SkCipher` takes and returns, all of whose payloads are inside the
+/// Encrypted payload. The IKE header and the Encrypted payload header are
+/// authenticated with lengths that leave out the IV, padding and ICV.
+pub fn int_auth_data(message: &IkeMessage) -> Result<Vec<u8>, Error> {
+    let mut payloads = message.payloads.clone();
+    let (first, inner) = encode_payloads(&mut payloads)?;
+    let payload_length = PAYLOAD_HEADER_LENGTH + inner.len();
+    let mut header = message.header.clone();
+    header.next_payload = PayloadType::SK;
+    header.length = u32::try_from(IKE_HEADER_LENGTH + payload_length)
+        .map_err(|_| Error::MessageTooLong(IKE_HEADER_LENGTH + payload_length))?;
+    let payload_header = PayloadHeader {
+        next_payload: first,
+        critical: false,
+        reserved: 0,
+        payload_length: u16::try_from(payload_length)
+            .map_err(|_| Error::PayloadTooLong(PayloadType::SK, payload_length))?,
+    };
+    Ok([header.to_bytes()?, payload_header.to_bytes()?, inner].concat())
+}
+
+/// Encrypts with a random IV.
+pub(crate) fn encrypt(cipher: &SkCipher, message: &IkeMessage) -> Result<Vec<u8>, Error> {
+    let mut iv = vec![0; cipher.iv_length()];
+    OsRng.fill_bytes(&mut iv);
+    cipher.encrypt(message, &iv)
+}
+
+/// The Key Exchange Data of the only KE payload, which must be for `method`
+/// (RFC 9370 §2.2.2).
+fn key_exchange_data<'a>(
+    message: &'a IkeMessage,
+    method: &transform::KE,
+) -> Result<&'a [u8], Error> {
+    let mut ke = message.payloads.iter().filter_map(|p| match &p.body {
+        PayloadBody::KE(ke) => Some(ke),
+        _ => None,
+    });
+    match (ke.next(), ke.next()) {
+        (Some(ke), None) if ke.dh_group == *method => Ok(&ke.data),
+        _ => Err(Error::Notify(NotifyType::INVALID_SYNTAX)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZero;
+
+    use super::*;
+    use crate::test_util::{proposal, transform};
+    use crate::types::Identity;
+
+    const IKE_SA_INIT: &[u8] = include_bytes!("testdata/ike_sa_init.bin");
+
+    fn ike_proposal(additional: &[TransformType]) -> Proposal {
+        let mut transforms = vec![
+            transform(
+                TransformType::ENCR(0, transform::ENCR::ENCR_AES_GCM_16),
+                Some(256),
+            ),
+            transform(
+                TransformType::PRF(0, transform::PRF::PRF_HMAC_SHA2_256),
+                None,
+            ),
+            transform(TransformType::KE(0, transform::KE::ECP_256), None),
+        ];
+        transforms.extend(additional.iter().map(|t| transform(t.clone(), None)));
+        proposal(ProtocolIdentifier::IKE, transforms)
+    }
+
+    /// Both ends of an IKE SA after IKE_SA_INIT with an ECP_256 key exchange.
+    fn ike_sa_init(proposal: &Proposal) -> (AdditionalKeyExchanges, AdditionalKeyExchanges) {
+        let mut header = IKEHeader::try_from(&IKE_SA_INIT[..28]).unwrap();
+        header.initiator_spi = NonZero::new(0x0102030405060708).unwrap();
+        header.responder_spi = 0x1112131415161718;
+        let ni: Vec<u8> = (0..0x20).collect();
+        let nr: Vec<u8> = (0x20..0x40).collect();
+
+        let initiator = ke::generate(&transform::KE::ECP_256).unwrap();
+        let (response, shared_secret) =
+            ke::respond(&transform::KE::ECP_256, &initiator.public_value()).unwrap();
+        assert_eq!(
+            initiator.shared_secret(&response),
+            Ok(shared_secret.clone())
+        );
+        let keys = IkeSaKeys::derive(proposal, &header, &ni, &nr, &shared_secret).unwrap();
+        (
+            AdditionalKeyExchanges::new(true, proposal, &header, &ni, &nr, keys.clone()),
+            AdditionalKeyExchanges::new(false, proposal, &header, &ni, &nr, keys),
+        )
+    }
+
+    fn cipher(keys: &IkeSaKeys, initiator: bool) -> SkCipher {
+        let encr = transform::ENCR::ENCR_AES_GCM_16;
+        let integ = transform::INTEG::NONE;
+        if initiator {
+            SkCipher::new(encr, integ, &keys.sk_ei, &keys.sk_ai).unwrap()
+        } else {
+            SkCipher::new(encr, integ, &keys.sk_er, &keys.sk_ar).unwrap()
+        }
+    }
+
+    #[test]
+    fn test_exchanges() {
+        let proposal = ike_proposal(&[
+            TransformType::ADDKE1(0, transform::KE::ML_KEM_768),
+            TransformType::ADDKE2(0, transform::KE::NONE),
+            TransformType::ADDKE3(0, transform::KE::Curve25519),
+        ]);
+        assert_eq!(
+            proposal.additional_key_exchanges(),
+            [&transform::KE::ML_KEM_768, &transform::KE::Curve25519]
+        );
+        let (mut initiator, mut responder) = ike_sa_init(&proposal);
+
+        let mut message_id = 1;
+        let mut methods = vec![];
+        while let Some(request) = initiator.request(message_id).unwrap() {
+            assert!(!initiator.is_complete());
+            // each exchange is protected with the keys in place before it
+            let keys = responder.keys().clone();
+            let plaintext = cipher(&keys, true).decrypt(&request).unwrap();
+            assert_eq!(
+                plaintext.header.exchange_type,
+                ExchangeType::IKE_INTERMEDIATE
+            );
+            assert_eq!(plaintext.header.message_id, message_id);
+            let PayloadBody::KE(ke) = &plaintext.payloads[0].body else {
+                panic!("no KE payload");
+            };
+            methods.push(ke.dh_group.clone());
+
+            let response = responder.respond(&request).unwrap();
+            assert!(cipher(&keys, false).decrypt(&response).is_ok());
+            initiator.handle_response(&response).unwrap();
+            assert_eq!(initiator.keys(), responder.keys());
+            assert_ne!(initiator.keys(), &keys);
+            message_id += 1;
+        }
+        assert_eq!(
+            methods,
+            [transform::KE::ML_KEM_768, transform::KE::Curve25519]
+        );
+        assert!(initiator.is_complete());
+        assert!(responder.is_complete());
+
+        let int_auth = initiator.int_auth_octets(3);
+        assert_eq!(int_auth.len(), 32 + 32 + 4);
+        assert_eq!(int_auth[64..], [0, 0, 0, 3]);
+        assert_eq!(responder.int_auth_octets(3), int_auth);
+
+        let idi = IdPayload::from(Identity::Fqdn("initiator.example.com".to_string()));
+        let octets = initiator.signed_octets(true, IKE_SA_INIT, &idi, 3).unwrap();
+        assert_eq!(
+            responder.signed_octets(true, IKE_SA_INIT, &idi, 3),
+            Ok(octets.clone())
+        );
+        let prf = transform::PRF::PRF_HMAC_SHA2_256;
+        let nr: Vec<u8> = (0x20..0x40).collect();
+        assert_eq!(
+            octets,
+            [
+                signed_octets(&prf, &initiator.keys().sk_pi, IKE_SA_INIT, &nr, &idi).unwrap(),
+                int_auth
+            ]
+            .concat()
+        );
+
+        let unexpected = || Error::UnexpectedMessage(ExchangeType::IKE_INTERMEDIATE);
+        assert_eq!(initiator.request(message_id), Ok(None));
+        assert_eq!(initiator.handle_response(&[]), Err(unexpected()));
+        assert_eq!(responder.respond(&[]), Err(unexpected()));
+        assert_eq!(responder.request(message_id), Err(unexpected()));
+    }
+
+    #[test]
+    fn test_int_auth() {
+        let proposal = ike_proposal(&[TransformType::ADDKE1(0, transform::KE::ML_KEM_512)]);
+        let (mut initiator, mut responder) = ike_sa_init(&proposal);
+        let keys = initiator.keys().clone();
+        let request = initiator.request(1).unwrap().unwrap();
+        let response = responder.respond(&request).unwrap();
+        initiator.handle_response(&response).unwrap();
+
+        // the lengths cover the 800 byte encapsulation key in a KE payload
+        let request = cipher(&keys, true).decrypt(&request).unwrap();
+        let data = int_auth_data(&request).unwrap();
+        assert_eq!(data.len(), 28 + 4 + 8 + 800);
+        assert_eq!(data[16..18], [46, 0x20]);
+        assert_eq!(data[24..28], 840u32.to_be_bytes());
+        assert_eq!(data[28..32], [34, 0, 0x03, 0x2c]);
+        assert_eq!(data[32..36], [0, 0, 0x03, 0x28]);
+
+        let prf = transform::PRF::PRF_HMAC_SHA2_256;
+        let response = cipher(&keys, false).decrypt(&response).unwrap();
+        let int_auth = initiator.int_auth_octets(2);
+        assert_eq!(int_auth[..32], self::prf(&prf, &keys.sk_pi, &data).unwrap());
+        assert_eq!(
+            int_auth[32..64],
+            self::prf(&prf, &keys.sk_pr, &int_auth_data(&response).unwrap()).unwrap()
+        );
+
+        // without additional key exchanges nothing is added to AUTH
+        let (mut initiator, _) = ike_sa_init(&ike_proposal(&[]));
+        assert!(initiator.is_complete());
+        assert_eq!(initiator.request(1), Ok(None));
+        assert!(initiator.int_auth_octets(1).is_empty());
+    }
+
+    #[test]
+    fn test_invalid_request() {
+        let proposal = ike_proposal(&[TransformType::ADDKE1(0, transform::KE::ML_KEM_768)]);
+        let (initiator, mut responder) = ike_sa_init(&proposal);
+        let keys = initiator.keys().clone();
+
+        let ecp = ke::generate(&transform::KE::ECP_256).unwrap();
+        let request = initiator.message(1, &transform::KE::ECP_256, ecp.public_value());
+        let data = cipher(&keys, true).encrypt(&request, &[0; 8]).unwrap();
+        assert_eq!(
+            responder.respond(&data),
+            Err(Error::Notify(NotifyType::INVALID_SYNTAX))
+        );
+
+        let mut request = initiator.message(1, &transform::KE::ML_KEM_768, vec![0; 1184]);
+        request.header.exchange_type = ExchangeType::IKE_AUTH;
+        let data = cipher(&keys, true).encrypt(&request, &[0; 8]).unwrap();
+        assert_eq!(
+            responder.respond(&data),
+            Err(Error::UnexpectedMessage(ExchangeType::IKE_INTERMEDIATE))
+        );
+        assert!(!responder.is_complete());
+        assert_eq!(responder.keys(), &keys);
+    }
+}
@@ -0,0 +1,779 @@
+use crypto_bigint::modular::runtime_mod::{DynResidue, DynResidueParams};
+use crypto_bigint::{Encoding, U1024, U1536, U2048, U3072, U4096, U6144, U8192, Uint};
+use ml_kem::kem::{Decapsulate, Encapsulate};
+use ml_kem::{B32, Ciphertext, EncodedSizeUser, KemCore, MlKem512, MlKem768, MlKem1024};
+use p256::elliptic_curve::sec1::{FromEncodedPoint, ModulusSize, ToEncodedPoint};
+use p256::elliptic_curve::{AffinePoint, CurveArithmetic, FieldBytesSize, PublicKey, SecretKey};
+use rand_core::{OsRng, RngCore};
+
+use crate::consts::TransformType;
+use crate::error::Error;
+use crate::transform;
+
+/// The initiator's side of a key exchange method negotiated with Transform
+/// Type 4. The responder answers with [`respond`].
+pub trait KeyExchange {
+    fn method(&self) -> transform::KE;
+
+    /// Key Exchange Data sent by the initiator.
+    fn public_value(&self) -> Vec<u8>;
+
+    /// The shared secret, computed from the responder's Key Exchange Data.
+    fn shared_secret(&self, peer: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// Starts a key exchange as the initiator with a fresh private value.
+pub fn generate(method: &transform::KE) -> Result<Box<dyn KeyExchange>, Error> {
+    Ok(match method {
+        transform::KE::ML_KEM_512 | transform::KE::ML_KEM_768 | transform::KE::ML_KEM_1024 => {
+            Box::new(MlKem::generate(method)?)
+        }
+        _ => Box::new(DiffieHellman::generate(method)?),
+    })
+}
+
+/// The responder's Key Exchange Data and the shared secret, computed from
+/// the initiator's Key Exchange Data.
+pub fn respond(method: &transform::KE, peer: &[u8]) -> Result<(Vec<u8>, Vec<u8>), Error> {
+    match method {
+        transform::KE::ML_KEM_512 | transform::KE::ML_KEM_768 | transform::KE::ML_KEM_1024 => {
+            MlKem::encapsulate(method, peer)
+        }
+        _ => DiffieHellman::generate(method)?.respond(peer),
+    }
+}
+
+/// Diffie-Hellman over the MODP groups of RFC 2409 and RFC 3526, the NIST
+/// curves of RFC 5903 and the Montgomery curves of RFC 8031.
+pub struct DiffieHellman {
+    method: transform::KE,
+    secret: Secret,
+}
+
+enum Secret {
+    /// Big-endian, the exponent padded to the length of the prime.
+    Modp {
+        prime: Vec<u8>,
+        exponent: Vec<u8>,
+    },
+    P256(p256::SecretKey),
+    P384(p384::SecretKey),
+    P521(p521::SecretKey),
+    X25519(x25519_dalek::StaticSecret),
+    X448(x448::Secret),
+}
+
+/// Length of the random MODP private exponents, more than twice the
+/// strength of the largest group.
+const MODP_EXPONENT_LENGTH: usize = 64;
+
+impl DiffieHellman {
+    pub fn generate(method: &transform::KE) -> Result<Self, Error> {
+        let random = |length| {
+            let mut bytes = vec![0; length];
+            OsRng.fill_bytes(&mut bytes);
+            bytes
+        };
+        let secret = match method {
+            transform::KE::ECP_256 => Secret::P256(p256::SecretKey::random(&mut OsRng)),
+            transform::KE::ECP_384 => Secret::P384(p384::SecretKey::random(&mut OsRng)),
+            transform::KE::ECP_512 => Secret::P521(p521::SecretKey::random(&mut OsRng)),
+            transform::KE::Curve25519 => {
+                Secret::X25519(x25519_dalek::StaticSecret::random_from_rng(OsRng))
+            }
+            transform::KE::Curve448 => return Self::from_secret(method, &random(56)),
+            _ => return Self::from_secret(method, &random(MODP_EXPONENT_LENGTH)),
+        };
+        Ok(DiffieHellman {
+            method: method.clone(),
+            secret,
+        })
+    }
+
+    /// Uses a fixed private value: the big-endian MODP exponent or ECP
+    /// scalar, or the X25519 and X448 private key. MODP exponents must be
+    /// at least 2 and no longer than the prime.
+    pub fn from_secret(method: &transform::KE, secret: &[u8]) -> Result<Self, Error> {
+        let invalid = || Error::InvalidKeyLength(secret.len());
+        let secret = match method {
+            transform::KE::ECP_256 => {
+                Secret::P256(SecretKey::from_slice(secret).map_err(|_| invalid())?)
+            }
+            transform::KE::ECP_384 => {
+                Secret::P384(SecretKey::from_slice(secret).map_err(|_| invalid())?)
+            }
+            transform::KE::ECP_512 => {
+                Secret::P521(SecretKey::from_slice(secret).map_err(|_| invalid())?)
+            }
+            transform::KE::Curve25519 => {
+                Secret::X25519(<[u8; 32]>::try_from(secret).map_err(|_| invalid())?.into())
+            }
+            transform::KE::Curve448 => {
+                Secret::X448(<[u8; 56]>::try_from(secret).map_err(|_| invalid())?.into())
+            }
+            _ => {
+                let prime = modp_prime(method).ok_or_else(|| {
+                    Error::UnsupportedTransform(TransformType::KE(0, method.clone()))
+                })?;
+                if secret.len() > prime.len() || is_below(secret, 2) {
+                    return Err(invalid());
+                }
+                let exponent = padded(secret, prime.len());
+                Secret::Modp { prime, exponent }
+            }
+        };
+        Ok(DiffieHellman {
+            method: method.clone(),
+            secret,
+        })
+    }
+
+    /// The responder's Key Exchange Data and the shared secret for this
+    /// private value.
+    pub fn respond(&self, peer: &[u8]) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        Ok((self.public_value(), self.shared_secret(peer)?))
+    }
+}
+
+impl KeyExchange for DiffieHellman {
+    fn method(&self) -> transform::KE {
+        self.method.clone()
+    }
+
+    fn public_value(&self) -> Vec<u8> {
+        match &self.secret {
+            Secret::Modp { prime, exponent } => modpow(prime, &padded(&[2], prime.len()), exponent),
+            Secret::P256(secret) => ecp_public_value(secret),
+            Secret::P384(secret) => ecp_public_value(secret),
+            Secret::P521(secret) => ecp_public_value(secret),
+            Secret::X25519(secret) => x25519_dalek::PublicKey::from(secret).as_bytes().to_vec(),
+            Secret::X448(secret) => x448::PublicKey::from(secret).as_bytes().to_vec(),
+        }
+    }
+
+    /// Fails with `KeyExchangeLength` for data of the wrong size and with
+    /// `InvalidPublicValue` for values rejected by the checks of RFC 6989.
+    fn shared_secret(&self, peer: &[u8]) -> Result<Vec<u8>, Error> {
+        check_length(&self.method, peer, false)?;
+        let invalid = || Error::InvalidPublicValue(self.method.clone());
+        match &self.secret {
+            Secret::Modp { prime, exponent } => {
+                // 1 < y < p-1 suffices for the safe primes used here, which
+                // end in 0xff so that p-1 only differs in the last byte
+                let mut p_minus_1 = prime.clone();
+                *p_minus_1.last_mut().unwrap() -= 1;
+                if is_below(peer, 2) || *peer >= *p_minus_1 {
+                    return Err(invalid());
+                }
+                Ok(modpow(prime, peer, exponent))
+            }
+            Secret::P256(secret) => ecp_shared_secret(secret, peer).ok_or_else(invalid),
+            Secret::P384(secret) => ecp_shared_secret(secret, peer).ok_or_else(invalid),
+            Secret::P521(secret) => ecp_shared_secret(secret, peer).ok_or_else(invalid),
+            Secret::X25519(secret) => {
+                let peer = <[u8; 32]>::try_from(peer).map_err(|_| invalid())?;
+                let shared = secret.diffie_hellman(&peer.into());
+                // an all-zero result means a small order point (RFC 8031 §2.3)
+                if !shared.was_contributory() {
+                    return Err(invalid());
+                }
+                Ok(shared.as_bytes().to_vec())
+            }
+            Secret::X448(secret) => {
+                let peer = x448::PublicKey::from_bytes(peer).ok_or_else(invalid)?;
+                let shared = secret.as_diffie_hellman(&peer).ok_or_else(invalid)?;
+                Ok(shared.as_bytes().to_vec())
+            }
+        }
+    }
+}
+
+/// ML-KEM (FIPS 203): the initiator sends its encapsulation key, the
+/// responder a ciphertext encapsulating the shared secret to it
+/// (draft-ietf-ipsecme-ikev2-mlkem). Only the initiator holds a key pair.
+pub struct MlKem {
+    method: transform::KE,
+    encapsulation_key: Vec<u8>,
+    decapsulation_key: DecapsulationKey,
+}
+
+enum DecapsulationKey {
+    MlKem512(Box<<MlKem512 as KemCore>::DecapsulationKey>),
+    MlKem768(Box<<MlKem768 as KemCore>::DecapsulationKey>),
+    MlKem1024(Box<<MlKem1024 as KemCore>::DecapsulationKey>),
+}
+
+impl MlKem {
+    pub fn generate(method: &transform::KE) -> Result<Self, Error> {
+        let mut seed = [0; 64];
+        OsRng.fill_bytes(&mut seed);
+        Self::from_seed(method, &seed)
+    }
+
+    /// Derives the key pair from a fixed 64 byte seed d || z.
+    pub fn from_seed(method: &transform::KE, seed: &[u8]) -> Result<Self, Error> {
+        let seed = <[u8; 64]>::try_from(seed).map_err(|_| Error::InvalidKeyLength(seed.len()))?;
+        let (d, z) = seed.split_at(32);
+        let (d, z) = (B32::try_from(d).unwrap(), B32::try_from(z).unwrap());
+        let (decapsulation_key, encapsulation_key) = match method {
+            transform::KE::ML_KEM_512 => {
+                let (dk, ek) = MlKem512::generate_deterministic(&d, &z);
+                (
+                    DecapsulationKey::MlKem512(Box::new(dk)),
+                    ek.as_bytes().to_vec(),
+                )
+            }
+            transform::KE::ML_KEM_768 => {
+                let (dk, ek) = MlKem768::generate_deterministic(&d, &z);
+                (
+                    DecapsulationKey::MlKem768(Box::new(dk)),
+                    ek.as_bytes().to_vec(),
+                )
+            }
+            transform::KE::ML_KEM_1024 => {
+                let (dk, ek) = MlKem1024::generate_deterministic(&d, &z);
+                (
+                    DecapsulationKey::MlKem1024(Box::new(dk)),
+                    ek.as_bytes().to_vec(),
+                )
+            }
+            _ => {
+                return Err(Error::UnsupportedTransform(TransformType::KE(
+                    0,
+                    method.clone(),
+                )));
+            }
+        };
+        Ok(MlKem {
+            method: method.clone(),
+            encapsulation_key,
+            decapsulation_key,
+        })
+    }
+
+    /// The responder's ciphertext and the fresh shared secret it
+    /// encapsulates to the initiator's encapsulation key, failing with
+    /// `InvalidPublicValue` if the key does not pass the modulus check of
+    /// FIPS 203 §7.2.
+    pub fn encapsulate(
+        method: &transform::KE,
+        encapsulation_key: &[u8],
+    ) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        check_length(method, encapsulation_key, false)?;
+        match method {
+            transform::KE::ML_KEM_512 => encapsulate::<MlKem512>(encapsulation_key),
+            transform::KE::ML_KEM_768 => encapsulate::<MlKem768>(encapsulation_key),
+            transform::KE::ML_KEM_1024 => encapsulate::<MlKem1024>(encapsulation_key),
+            _ => {
+                return Err(Error::UnsupportedTransform(TransformType::KE(
+                    0,
+                    method.clone(),
+                )));
+            }
+        }
+        .ok_or_else(|| Error::InvalidPublicValue(method.clone()))
+    }
+}
+
+impl KeyExchange for MlKem {
+    fn method(&self) -> transform::KE {
+        self.method.clone()
+    }
+
+    /// The encapsulation key.
+    fn public_value(&self) -> Vec<u8> {
+        self.encapsulation_key.clone()
+    }
+
+    /// Decapsulates the responder's ciphertext. An invalid ciphertext yields
+    /// an unrelated secret (implicit rejection) and fails AUTH later.
+    fn shared_secret(&self, peer: &[u8]) -> Result<Vec<u8>, Error> {
+        check_length(&self.method, peer, true)?;
+        let invalid = || Error::InvalidPublicValue(self.method.clone());
+        match &self.decapsulation_key {
+            DecapsulationKey::MlKem512(dk) => decapsulate::<MlKem512>(dk, peer),
+            DecapsulationKey::MlKem768(dk) => decapsulate::<MlKem768>(dk, peer),
+            DecapsulationKey::MlKem1024(dk) => decapsulate::<MlKem1024>(dk, peer),
+        }
+        .ok_or_else(invalid)
+    }
+}
+
+/// Fails with `KeyExchangeLength` unless the Key Exchange Data of the
+/// request or response has the size required by the method.
+fn check_length(method: &transform::KE, peer: &[u8], response: bool) -> Result<(), Error> {
+    let expected = method.data_length(response).unwrap_or_default();
+    if peer.len() != expected {
+        return Err(Error::KeyExchangeLength {
+            group: method.clone(),
+            expected,
+            actual: peer.len(),
+        });
+    }
+    Ok(())
+}
+
+fn decapsulate<K: KemCore>(key: &K::DecapsulationKey, ciphertext: &[u8]) -> Option<Vec<u8>> {
+    let ciphertext = Ciphertext::<K>::try_from(ciphertext).ok()?;
+    Some(key.decapsulate(&ciphertext).ok()?.to_vec())
+}
+
+/// The ciphertext and shared secret, or `None` if the encapsulation key
+/// holds coefficients that are not reduced modulo q.
+fn encapsulate<K: KemCore>(encapsulation_key: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let encoded = encapsulation_key.try_into().ok()?;
+    let key = K::EncapsulationKey::from_bytes(&encoded);
+    if key.as_bytes() != encoded {
+        return None;
+    }
+    let (ciphertext, shared_secret) = key.encapsulate(&mut OsRng).ok()?;
+    Some((ciphertext.to_vec(), shared_secret.to_vec()))
+}
+
+/// Left-pads a big-endian value to `length` bytes.
+fn padded(value: &[u8], length: usize) -> Vec<u8> {
+    [&vec![0; length - value.len()][..], value].concat()
+}
+
+/// Whether the big-endian value is less than `bound`.
+fn is_below(value: &[u8], bound: u8) -> bool {
+    match value.split_last() {
+        Some((last, rest)) => rest.iter().all(|b| *b == 0) && *last < bound,
+        None => true,
+    }
+}
+
+/// `base ^ exponent mod prime` in constant time, for big-endian values as
+/// long as the prime.
+fn modpow(prime: &[u8], base: &[u8], exponent: &[u8]) -> Vec<u8> {
+    match prime.len() {
+        128 => modpow_uint::<{ U1024::LIMBS }>(prime, base, exponent),
+        192 => modpow_uint::<{ U1536::LIMBS }>(prime, base, exponent),
+        256 => modpow_uint::<{ U2048::LIMBS }>(prime, base, exponent),
+        384 => modpow_uint::<{ U3072::LIMBS }>(prime, base, exponent),
+        512 => modpow_uint::<{ U4096::LIMBS }>(prime, base, exponent),
+        768 => modpow_uint::<{ U6144::LIMBS }>(prime, base, exponent),
+        _ => modpow_uint::<{ U8192::LIMBS }>(prime, base, exponent),
+    }
+}
+
+fn modpow_uint<const LIMBS: usize>(prime: &[u8], base: &[u8], exponent: &[u8]) -> Vec<u8>
+where
+    Uint<LIMBS>: Encoding,
+{
+    let params = DynResidueParams::new(&Uint::<LIMBS>::from_be_slice(prime));
+    let base = DynResidue::new(&Uint::<LIMBS>::from_be_slice(base), params);
+    let power = base.pow(&Uint::<LIMBS>::from_be_slice(exponent)).retrieve();
+    power.to_be_bytes().as_ref().to_vec()
+}
+
+/// The x and y coordinates of the public key (RFC 5903 §7).
+fn ecp_public_value<C>(secret: &SecretKey<C>) -> Vec<u8>
+where
+    C: CurveArithmetic,
+    AffinePoint<C>: FromEncodedPoint<C> + ToEncodedPoint<C>,
+    FieldBytesSize<C>: ModulusSize,
+{
+    secret.public_key().to_encoded_point(false).as_bytes()[1..].to_vec()
+}
+
+/// The x coordinate of the shared point, or `None` if the peer's value is
+/// not a point on the curve (RFC 6989 §2.3).
+fn ecp_shared_secret<C>(secret: &SecretKey<C>, peer: &[u8]) -> Option<Vec<u8>>
+where
+    C: CurveArithmetic,
+    AffinePoint<C>: FromEncodedPoint<C> + ToEncodedPoint<C>,
+    FieldBytesSize<C>: ModulusSize,
+{
+    let peer = PublicKey::<C>::from_sec1_bytes(&[&[0x04], peer].concat()).ok()?;
+    let shared =
+        p256::elliptic_curve::ecdh::diffie_hellman(secret.to_nonzero_scalar(), peer.as_affine());
+    Some(shared.raw_secret_bytes().to_vec())
+}
+
+fn modp_prime(method: &transform::KE) -> Option<Vec<u8>> {
+    let prime = match method {
+        transform::KE::MODP_1024 => MODP_1024,
+        transform::KE::MODP_1536 => MODP_1536,
+        transform::KE::MODP_2048 => MODP_2048,
+        transform::KE::MODP_3072 => MODP_3072,
+        transform::KE::MODP_4096 => MODP_4096,
+        transform::KE::MODP_6144 => MODP_6144,
+        transform::KE::MODP_8192 => MODP_8192,
+        _ => return None,
+    };
+    prime
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+/// 1024-bit MODP Group, RFC 2409 §6.2
+const MODP_1024: &str = "\
+    FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74\
+    020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437\
+    4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED\
+    EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE65381FFFFFFFFFFFFFFFF";
+
+/// 1536-bit MODP Group, RFC 3526 §2
+const MODP_1536: &str = "\
+    FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74\
+    020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437\
+    4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED\
+    EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05\
+    98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB\
+    9ED529077096966D670C354E4ABC9804F1746C08CA237327FFFFFFFFFFFFFFFF";
+
+/// 2048-bit MODP Group, RFC 3526 §3
+const MODP_2048: &str = "\
+    FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74\
+    020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437\
+    4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED\
+    EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05\
+    98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB\
+    9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B\
+    E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718\
+    3995497CEA956AE515D2261898FA051015728E5A8AACAA68FFFFFFFFFFFFFFFF";
+
+/// 3072-bit MODP Group, RFC 3526 §4
+const MODP_3072: &str = "\
+    FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74\
+    020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437\
+    4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED\
+    EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05\
+    98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB\
+    9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B\
+    E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718\
+    3995497CEA956AE515D2261898FA051015728E5A8AAAC42DAD33170D04507A33\
+    A85521ABDF1CBA64ECFB850458DBEF0A8AEA71575D060C7DB3970F85A6E1E4C7\
+    ABF5AE8CDB0933D71E8C94E04A25619DCEE3D2261AD2EE6BF12FFA06D98A0864\
+    D87602733EC86A64521F2B18177B200CBBE117577A615D6C770988C0BAD946E2\
+    08E24FA074E5AB3143DB5BFCE0FD108E4B82D120A93AD2CAFFFFFFFFFFFFFFFF";
+
+/// 4096-bit MODP Group, RFC 3526 §5
+const MODP_4096: &str = "\
+    FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74\
+    020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437\
+    4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED\
+    EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05\
+    98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB\
+    9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B\
+    E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718\
+    3995497CEA956AE515D2261898FA051015728E5A8AAAC42DAD33170D04507A33\
+    A85521ABDF1CBA64ECFB850458DBEF0A8AEA71575D060C7DB3970F85A6E1E4C7\
+    ABF5AE8CDB0933D71E8C94E04A25619DCEE3D2261AD2EE6BF12FFA06D98A0864\
+    D87602733EC86A64521F2B18177B200CBBE117577A615D6C770988C0BAD946E2\
+    08E24FA074E5AB3143DB5BFCE0FD108E4B82D120A92108011A723C12A787E6D7\
+    88719A10BDBA5B2699C327186AF4E23C1A946834B6150BDA2583E9CA2AD44CE8\
+    DBBBC2DB04DE8EF92E8EFC141FBECAA6287C59474E6BC05D99B2964FA090C3A2\
+    233BA186515BE7ED1F612970CEE2D7AFB81BDD762170481CD0069127D5B05AA9\
+    93B4EA988D8FDDC186FFB7DC90A6C08F4DF435C934063199FFFFFFFFFFFFFFFF";
+
+/// 6144-bit MODP Group, RFC 3526 §6
+const MODP_6144: &str = "\
+    FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74\
+    020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437\
+    4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED\
+    EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05\
+    98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB\
+    9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B\
+    E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718\
+    3995497CEA956AE515D2261898FA051015728E5A8AAAC42DAD33170D04507A33\
+    A85521ABDF1CBA64ECFB850458DBEF0A8AEA71575D060C7DB3970F85A6E1E4C7\
+    ABF5AE8CDB0933D71E8C94E04A25619DCEE3D2261AD2EE6BF12FFA06D98A0864\
+    D87602733EC86A64521F2B18177B200CBBE117577A615D6C770988C0BAD946E2\
+    08E24FA074E5AB3143DB5BFCE0FD108E4B82D120A92108011A723C12A787E6D7\
+    88719A10BDBA5B2699C327186AF4E23C1A946834B6150BDA2583E9CA2AD44CE8\
+    DBBBC2DB04DE8EF92E8EFC141FBECAA6287C59474E6BC05D99B2964FA090C3A2\
+    233BA186515BE7ED1F612970CEE2D7AFB81BDD762170481CD0069127D5B05AA9\
+    93B4EA988D8FDDC186FFB7DC90A6C08F4DF435C93402849236C3FAB4D27C7026\
+    C1D4DCB2602646DEC9751E763DBA37BDF8FF9406AD9E530EE5DB382F413001AE\
+    B06A53ED9027D831179727B0865A8918DA3EDBEBCF9B14ED44CE6CBACED4BB1B\
+    DB7F1447E6CC254B332051512BD7AF426FB8F401378CD2BF5983CA01C64B92EC\
+    F032EA15D1721D03F482D7CE6E74FEF6D55E702F46980C82B5A84031900B1C9E\
+    59E7C97FBEC7E8F323A97A7E36CC88BE0F1D45B7FF585AC54BD407B22B4154AA\
+    CC8F6D7EBF48E1D814CC5ED20F8037E0A79715EEF29BE32806A1D58BB7C5DA76\
+    F550AA3D8A1FBFF0EB19CCB1A313D55CDA56C9EC2EF29632387FE8D76E3C0468\
+    043E8F663F4860EE12BF2D5B0B7474D6E694F91E6DCC4024FFFFFFFFFFFFFFFF";
+
+/// 8192-bit MODP Group, RFC 3526 §7
+const MODP_8192: &str = "\
+    FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74\
+    020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437\
+    4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED\
+    EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05\
+    98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB\
+    9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B\
+    E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718\
+    3995497CEA956AE515D2261898FA051015728E5A8AAAC42DAD33170D04507A33\
+    A85521ABDF1CBA64ECFB850458DBEF0A8AEA71575D060C7DB3970F85A6E1E4C7\
+    ABF5AE8CDB0933D71E8C94E04A25619DCEE3D2261AD2EE6BF12FFA06D98A0864\
+    D87602733EC86A64521F2B18177B200CBBE117577A615D6C770988C0BAD946E2\
+    08E24FA074E5AB3143DB5BFCE0FD108E4B82D120A92108011A723C12A787E6D7\
+    88719A10BDBA5B2699C327186AF4E23C1A946834B6150BDA2583E9CA2AD44CE8\
+    DBBBC2DB04DE8EF92E8EFC141FBECAA6287C59474E6BC05D99B2964FA090C3A2\
+    233BA186515BE7ED1F612970CEE2D7AFB81BDD762170481CD0069127D5B05AA9\
+    93B4EA988D8FDDC186FFB7DC90A6C08F4DF435C93402849236C3FAB4D27C7026\
+    C1D4DCB2602646DEC9751E763DBA37BDF8FF9406AD9E530EE5DB382F413001AE\
+    B06A53ED9027D831179727B0865A8918DA3EDBEBCF9B14ED44CE6CBACED4BB1B\
+    DB7F1447E6CC254B332051512BD7AF426FB8F401378CD2BF5983CA01C64B92EC\
+    F032EA15D1721D03F482D7CE6E74FEF6D55E702F46980C82B5A84031900B1C9E\
+    59E7C97FBEC7E8F323A97A7E36CC88BE0F1D45B7FF585AC54BD407B22B4154AA\
+    CC8F6D7EBF48E1D814CC5ED20F8037E0A79715EEF29BE32806A1D58BB7C5DA76\
+    F550AA3D8A1FBFF0EB19CCB1A313D55CDA56C9EC2EF29632387FE8D76E3C0468\
+    043E8F663F4860EE12BF2D5B0B7474D6E694F91E6DBE115974A3926F12FEE5E4\
+    38777CB6A932DF8CD8BEC4D073B931BA3BC832B68D9DD300741FA7BF8AFC47ED\
+    2576F6936BA424663AAB639C5AE4F5683423B4742BF1C978238F16CBE39D652D\
+    E3FDB8BEFC848AD922222E04A4037C0713EB57A81A23F0C73473FC646CEA306B\
+    4BCBC8862F8385DDFA9D4B7FA2C087E879683303ED5BDD3A062B3CF5B3A278A6\
+    6D2A13F83F44F82DDF310EE074AB6A364597E899A0255DC164F31CC50846851D\
+    F9AB48195DED7EA1B1D510BD7EE74D73FAF36BC31ECFA268359046F4EB879F92\
+    4009438B481C6CD7889A002ED5EE382BC9190DA6FC026E479558E4475677E9AA\
+    9E3050E2765694DFC81F56E880B96E7160C980DD98EDD3DFFFFFFFFFFFFFFFFF";
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::hex;
+
+    #[test]
+    fn test_vectors() {
+        // Public values and shared secret that Python's cryptography package
+        // derives for the private values 0x01 0x02 .. and 0x41 0x42 ..
+        let cases = [
+            (
+                transform::KE::MODP_2048,
+                32,
+                "ce8736a3ad7c8260e0c5e5a457931ad19966d693e5c32414496b73d7f4e8bdaf\
+                 51763d49d07a56b0a188048d61d52bd2a06e706a7a46664113e794057474d04c\
+                 96b5bb151a50b27818a513cd8d6ecf037fc638b3b2f1084c58d4419735ba12c9\
+                 a4391e2b3133bd660785e468569be85c7ad4f904d98a13f5e8695ff8cc5e2283\
+                 4228a8ea2c082ff49a07607e8e5c1278ce8091725678fde112491091bf4cf3b1\
+                 759ccb32c1de0b90a074b1e3ffe179d71a292ff29ce9580c3a978793598c0e00\
+                 8289e7ab7783b2b75caf9152267e8c981dbb28fde07f2e11ffe1e3984097d160\
+                 2c61ffd0714d9523455377c177df3be6b3c81e69f762c04be910a225f323882d",
+                "1d091c89554262052900d0ab9f49c801e034a04b9db6de06efa60314e1cef81e\
+                 f07e49bef94a825e7ed5773d354dc56075dc45b08e6bdb23e8c65c79bd4cd9b7\
+                 038d05e3a5a08a0551a52f4dc54cb0a8fcbba08f9008d7e07559177f3affeccc\
+                 c04fd26612b813d1268aee13b39c1346bfad03c9e3fdb67c0564003071464cdf\
+                 4a8c9afceaa20bc57888eed275a1c40b6c3eef4f561948de3b3158bbd22648ed\
+                 c728265f14e83a3ae1f7b4a6d6be24d76b37de0c427d7e35f64c1e812c5a9baa\
+                 92b9614dcf2c8a908c43ddd73ee90044d74b2466fe213b501e64ad05fa8a98cf\
+                 72a92cc59c30db5a760e008e676b1ad9427f99d34e71ede23ed0a5bdafa85ffd",
+            ),
+            (
+                transform::KE::ECP_256,
+                32,
+                "515c3d6eb9e396b904d3feca7f54fdcd0cc1e997bf375dca515ad0a6c3b4035f\
+                 4536be3a50f318fbf9a5475902a221502bef0d57e08c53b2cc0a56f17d9f9354",
+                "7434f2c52e0d08dc73394aae3d2d55d5b88bc570f21cc2b8f8b80b273ddbf76f",
+            ),
+            (
+                transform::KE::ECP_384,
+                48,
+                "c76f2283dda95cd49b0ed9e733d2904474e37216f124e13d2c9ab4cf01021c49\
+                 ad9cabb3d0b97499aef2f0ab313fa02826bc1f83451b5c8962a75caff73588d4\
+                 400a6296436154fb343c393e91048a6c7bcbadc83cd8a5f26feae883156f92a1",
+                "4506b6844fec66eacaafa353f344ef68e64416f9ab4051a4c54c7c13c7d3f92d\
+                 f2449d0783dce977257e1029e3b0bcc7",
+            ),
+            (
+                transform::KE::ECP_512,
+                66,
+                "01f7eafa08fc845f03d01f7631f407fa17e6191d45b304d46aa1eaf6484a21fc\
+                 78bc7003a1db5e8f450724721a2a611bebdbe6c4ca6f8fcf8b8edaaf2d09b815\
+                 9f5001342df71a870f66a1e91c0efd895324ee3dfb33475bfc69790bd8704413\
+                 11db0416d4704116712f03e9680b5a5d7fed0694e4fd37b6a085dfc030ecb5bf\
+                 daf11743",
+                "011dc09f2294bde5c73ebfe7a7d52db9dfe29c944b3adb29a35b9cfe3284a7bb\
+                 49e8b5a63ee405bd333159658a89d81b6b7a4e8bf5a76211bd424df3da948d27\
+                 fdc1",
+            ),
+            (
+                transform::KE::Curve25519,
+                32,
+                "07a37cbc142093c8b755dc1b10e86cb426374ad16aa853ed0bdfc0b2b86d1c7c",
+                "26c2c17fdb82161cb21ad16e721315355b64d1763119b10bfc962530dc7cc163",
+            ),
+            (
+                transform::KE::Curve448,
+                56,
+                "bda7365ba1bd9a66f2ef38db6ec5ac5fad5452e990d8b2f88f721fd53363237e\
+                 775f65205d1d4667d473f0e1f4c57694d2d802e8dff06026",
+                "8d7b06fdfdd74c9fa1fb430f0e99a98cad3c6bccc9834d701aa06665c5bacd85\
+                 6c35489d71c95db0a0ef6491e9eb554a560d2e2101e50748",
+            ),
+        ];
+        for (method, length, public_value, shared_secret) in cases {
+            let mut secret: Vec<u8> = (1..=length as u8).collect();
+            let mut peer_secret: Vec<u8> = (0x41..0x41 + length as u8).collect();
+            if method == transform::KE::ECP_512 {
+                // keep the scalars below the order of P-521
+                secret[0] = 0;
+                peer_secret[0] = 0;
+            }
+            let initiator = DiffieHellman::from_secret(&method, &secret).unwrap();
+            let responder = DiffieHellman::from_secret(&method, &peer_secret).unwrap();
+            assert_eq!(initiator.public_value(), hex(public_value));
+            assert_eq!(
+                initiator.shared_secret(&responder.public_value()),
+                Ok(hex(shared_secret))
+            );
+            assert_eq!(
+                responder.respond(&initiator.public_value()),
+                Ok((responder.public_value(), hex(shared_secret)))
+            );
+        }
+    }
+
+    #[test]
+    fn test_generate() {
+        for method in [
+            transform::KE::MODP_1024,
+            transform::KE::MODP_1536,
+            transform::KE::MODP_3072,
+            transform::KE::ECP_256,
+            transform::KE::ECP_384,
+            transform::KE::ECP_512,
+            transform::KE::Curve25519,
+            transform::KE::Curve448,
+            transform::KE::ML_KEM_512,
+            transform::KE::ML_KEM_768,
+            transform::KE::ML_KEM_1024,
+        ] {
+            let initiator = generate(&method).unwrap();
+            assert_eq!(initiator.method(), method);
+            let request = initiator.public_value();
+            assert_eq!(Some(request.len()), method.data_length(false));
+            let (response, shared_secret) = respond(&method, &request).unwrap();
+            assert_eq!(Some(response.len()), method.data_length(true));
+            assert_eq!(initiator.shared_secret(&response), Ok(shared_secret));
+        }
+        for method in [transform::KE::MODP_768, transform::KE::NONE] {
+            assert!(matches!(
+                generate(&method),
+                Err(Error::UnsupportedTransform(_))
+            ));
+            assert!(matches!(
+                respond(&method, &[]),
+                Err(Error::UnsupportedTransform(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_public_value_checks() {
+        let modp = DiffieHellman::generate(&transform::KE::MODP_1024).unwrap();
+        let prime = modp_prime(&transform::KE::MODP_1024).unwrap();
+        let mut p_minus_1 = prime.clone();
+        p_minus_1[127] -= 1;
+        for y in [padded(&[0], 128), padded(&[1], 128), p_minus_1, prime] {
+            assert_eq!(
+                modp.shared_secret(&y),
+                Err(Error::InvalidPublicValue(transform::KE::MODP_1024))
+            );
+        }
+        assert_eq!(
+            modp.shared_secret(&[2; 127]),
+            Err(Error::KeyExchangeLength {
+                group: transform::KE::MODP_1024,
+                expected: 128,
+                actual: 127
+            })
+        );
+
+        // private exponents below 2 or longer than the prime are refused
+        for exponent in [&[][..], &[0], &[1], &[0, 0, 1], &[2; 129]] {
+            assert_eq!(
+                DiffieHellman::from_secret(&transform::KE::MODP_1024, exponent).err(),
+                Some(Error::InvalidKeyLength(exponent.len()))
+            );
+        }
+        assert!(DiffieHellman::from_secret(&transform::KE::MODP_1024, &[0, 2]).is_ok());
+
+        // (1, 1) is not on the curve
+        let ecp = DiffieHellman::generate(&transform::KE::ECP_256).unwrap();
+        let mut point = [0; 64];
+        point[31] = 1;
+        point[63] = 1;
+        assert_eq!(
+            ecp.shared_secret(&point),
+            Err(Error::InvalidPublicValue(transform::KE::ECP_256))
+        );
+
+        // points of small order
+        let x25519 = DiffieHellman::generate(&transform::KE::Curve25519).unwrap();
+        let mut one = [0; 32];
+        one[0] = 1;
+        for point in [[0; 32], one] {
+            assert_eq!(
+                x25519.shared_secret(&point),
+                Err(Error::InvalidPublicValue(transform::KE::Curve25519))
+            );
+        }
+        let x448 = DiffieHellman::generate(&transform::KE::Curve448).unwrap();
+        assert_eq!(
+            x448.shared_secret(&[0; 56]),
+            Err(Error::InvalidPublicValue(transform::KE::Curve448))
+        );
+    }
+
+    #[test]
+    fn test_ml_kem() {
+        // Key pair generated by OpenSSL 3.5 from the seed 0x00 0x01 .. 0x3f,
+        // which also encapsulated the ciphertext.
+        let method = transform::KE::ML_KEM_768;
+        let seed: Vec<u8> = (0..64).collect();
+        let initiator = MlKem::from_seed(&method, &seed).unwrap();
+        assert_eq!(
+            initiator.public_value(),
+            include_bytes!("testdata/ml_kem_768.pub")
+        );
+        assert_eq!(
+            initiator.shared_secret(include_bytes!("testdata/ml_kem_768.ct")),
+            Ok(hex(
+                "caefd12130e2c7f66835b16c2fbbfb99d43e254a294804c943e04e1393480aac"
+            ))
+        );
+
+        let (ciphertext, shared_secret) = respond(&method, &initiator.public_value()).unwrap();
+        assert_eq!(initiator.shared_secret(&ciphertext), Ok(shared_secret));
+
+        // the encapsulation key is not accepted as a ciphertext and vice versa
+        assert_eq!(
+            initiator.shared_secret(&initiator.public_value()),
+            Err(Error::KeyExchangeLength {
+                group: method.clone(),
+                expected: 1088,
+                actual: 1184
+            })
+        );
+        assert_eq!(
+            respond(&method, &ciphertext),
+            Err(Error::KeyExchangeLength {
+                group: method.clone(),
+                expected: 1184,
+                actual: 1088
+            })
+        );
+
+        // a coefficient of 0xfff is not reduced modulo q = 3329
+        let mut unreduced = initiator.public_value();
+        unreduced[0] = 0xff;
+        unreduced[1] |= 0x0f;
+        assert_eq!(
+            respond(&method, &unreduced),
+            Err(Error::InvalidPublicValue(method.clone()))
+        );
+
+        assert_eq!(
+            MlKem::from_seed(&method, &seed[..32]).err(),
+            Some(Error::InvalidKeyLength(32))
+        );
+        assert!(matches!(
+            MlKem::generate(&transform::KE::ECP_256),
+            Err(Error::UnsupportedTransform(_))
+        ));
+    }
+}
@@ -0,0 +1,693 @@
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Sha256, Sha384, Sha512};
+
+use crate::consts::{ProtocolIdentifier, TransformType};
+use crate::error::Error;
+use crate::transform;
+use crate::types::{IKEHeader, Proposal};
+
+/// Untruncated HMAC of `data`.
+pub(crate) fn hmac<M: Mac + KeyInit>(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = <M as KeyInit>::new_from_slice(key).expect("HMAC accepts any key size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// The negotiated pseudorandom function, `prf(key, data)`.
+pub fn prf(prf: &transform::PRF, key: &[u8], data: &[u8]) -> Result<Vec<u8>, Error> {
+    Ok(match prf {
+        transform::PRF::PRF_HMAC_SHA1 => hmac::<Hmac<Sha1>>(key, data),
+        transform::PRF::PRF_HMAC_SHA2_256 => hmac::<Hmac<Sha256>>(key, data),
+        transform::PRF::PRF_HMAC_SHA2_384 => hmac::<Hmac<Sha384>>(key, data),
+        transform::PRF::PRF_HMAC_SHA2_512 => hmac::<Hmac<Sha512>>(key, data),
+        _ => {
+            return Err(Error::UnsupportedTransform(TransformType::PRF(
+                0,
+                prf.clone(),
+            )));
+        }
+    })
+}
+
+/// `prf+` of RFC 7296 §2.13, truncated to `length` bytes.
+pub fn prf_plus(
+    prf: &transform::PRF,
+    key: &[u8],
+    seed: &[u8],
+    length: usize,
+) -> Result<Vec<u8>, Error> {
+    let mut output = Vec::with_capacity(length);
+    let mut block = vec![];
+    for counter in 1..=u8::MAX {
+        if output.len() >= length {
+            break;
+        }
+        block = self::prf(prf, key, &[&block, seed, &[counter]].concat())?;
+        output.extend(&block);
+    }
+    if output.len() < length {
+        return Err(Error::KeyMaterialTooLong(length));
+    }
+    output.truncate(length);
+    Ok(output)
+}
+
+/// SKEYSEED = prf(Ni | Nr, g^ir)
+pub fn skeyseed(
+    prf: &transform::PRF,
+    ni: &[u8],
+    nr: &[u8],
+    shared_secret: &[u8],
+) -> Result<Vec<u8>, Error> {
+    self::prf(prf, &[ni, nr].concat(), shared_secret)
+}
+
+/// Keys of an IKE SA (RFC 7296 §2.14).
+#[derive(Clone, Debug, PartialEq)]
+pub struct IkeSaKeys {
+    pub sk_d: Vec<u8>,
+    pub sk_ai: Vec<u8>,
+    pub sk_ar: Vec<u8>,
+    pub sk_ei: Vec<u8>,
+    pub sk_er: Vec<u8>,
+    pub sk_pi: Vec<u8>,
+    pub sk_pr: Vec<u8>,
+}
+
+impl IkeSaKeys {
+    /// Derives the keys of a new IKE SA from the negotiated IKE proposal,
+    /// the SPIs in `header`, both nonces and the Diffie-Hellman shared secret.
+    pub fn derive(
+        proposal: &Proposal,
+        header: &IKEHeader,
+        ni: &[u8],
+        nr: &[u8],
+        shared_secret: &[u8],
+    ) -> Result<Self, Error> {
+        let prf = proposal.prf().ok_or(Error::MissingTransform("PRF"))?;
+        let skeyseed = skeyseed(prf, ni, nr, shared_secret)?;
+        Self::from_skeyseed(proposal, header, ni, nr, &skeyseed)
+    }
+
+    /// Expands SKEYSEED into the individual keys:
+    /// {SK_d | SK_ai | SK_ar | SK_ei | SK_er | SK_pi | SK_pr}
+    ///     = prf+ (SKEYSEED, Ni | Nr | SPIi | SPIr)
+    pub fn from_skeyseed(
+        proposal: &Proposal,
+        header: &IKEHeader,
+        ni: &[u8],
+        nr: &[u8],
+        skeyseed: &[u8],
+    ) -> Result<Self, Error> {
+        let prf = proposal.prf().ok_or(Error::MissingTransform("PRF"))?;
+        let (encr, key_length) = proposal.encr().ok_or(Error::MissingTransform("ENCR"))?;
+        let integ = proposal.integ().unwrap_or(&transform::INTEG::NONE);
+
+        let prf_length = prf
+            .output_length()
+            .ok_or_else(|| Error::UnsupportedTransform(TransformType::PRF(0, prf.clone())))?;
+        let encr_length = encr
+            .key_material_length(key_length)
+            .ok_or_else(|| Error::UnsupportedTransform(TransformType::ENCR(0, encr.clone())))?;
+        let integ_length = integ
+            .key_length()
+            .ok_or_else(|| Error::UnsupportedTransform(TransformType::INTEG(0, integ.clone())))?;
+
+        let seed = [
+            ni,
+            nr,
+            &header.initiator_spi.get().to_be_bytes(),
+            &header.responder_spi.to_be_bytes(),
+        ]
+        .concat();
+        let length = 3 * prf_length + 2 * integ_length + 2 * encr_length;
+        let mut keymat = prf_plus(prf, skeyseed, &seed, length)?.into_iter();
+        let mut take = |n: usize| keymat.by_ref().take(n).collect::<Vec<u8>>();
+        Ok(IkeSaKeys {
+            sk_d: take(prf_length),
+            sk_ai: take(integ_length),
+            sk_ar: take(integ_length),
+            sk_ei: take(encr_length),
+            sk_er: take(encr_length),
+            sk_pi: take(prf_length),
+            sk_pr: take(prf_length),
+        })
+    }
+
+    /// Replaces the keys after an additional key exchange (RFC 9370 §2.2.2):
+    /// SKEYSEED(n) = prf(SK_d(n-1), SK(n) | Ni | Nr)
+    pub fn update(
+        &self,
+        proposal: &Proposal,
+        header: &IKEHeader,
+        ni: &[u8],
+        nr: &[u8],
+        shared_secret: &[u8],
+    ) -> Result<Self, Error> {
+        let prf = proposal.prf().ok_or(Error::MissingTransform("PRF"))?;
+        let skeyseed = self::prf(prf, &self.sk_d, &[shared_secret, ni, nr].concat())?;
+        Self::from_skeyseed(proposal, header, ni, nr, &skeyseed)
+    }
+}
+
+/// Keys of a Child SA (RFC 7296 §2.17), in the order they are taken from
+/// KEYMAT. Encryption keys of AEAD and counter mode algorithms end with the
+/// salt, e.g. the four bytes of RFC 4106 for AES-GCM.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChildSaKeys {
+    pub encr_i: Vec<u8>,
+    pub integ_i: Vec<u8>,
+    pub encr_r: Vec<u8>,
+    pub integ_r: Vec<u8>,
+}
+
+impl ChildSaKeys {
+    /// Derives the keys of the ESP or AH SA negotiated with `proposal`, where
+    /// `prf` and `sk_d` belong to the IKE SA and `shared_secret` is the
+    /// optional g^ir (new) of a CREATE_CHILD_SA exchange:
+    /// KEYMAT = prf+(SK_d, [g^ir (new) |] Ni | Nr)
+    pub fn derive(
+        prf: &transform::PRF,
+        sk_d: &[u8],
+        proposal: &Proposal,
+        shared_secret: Option<&[u8]>,
+        ni: &[u8],
+        nr: &[u8],
+    ) -> Result<Self, Error> {
+        let encr_length = match proposal.protocol_id {
+            ProtocolIdentifier::ESP => {
+                let (encr, key_length) = proposal.encr().ok_or(Error::MissingTransform("ENCR"))?;
+                encr.key_material_length(key_length).ok_or_else(|| {
+                    Error::UnsupportedTransform(TransformType::ENCR(0, encr.clone()))
+                })?
+            }
+            ProtocolIdentifier::AH => 0,
+            ref protocol => return Err(Error::UnexpectedProtocol(protocol.clone())),
+        };
+        let integ = match proposal.protocol_id {
+            ProtocolIdentifier::AH => proposal.integ().ok_or(Error::MissingTransform("INTEG"))?,
+            _ => proposal.integ().unwrap_or(&transform::INTEG::NONE),
+        };
+        let integ_length = integ
+            .key_length()
+            .ok_or_else(|| Error::UnsupportedTransform(TransformType::INTEG(0, integ.clone())))?;
+
+        let seed = [shared_secret.unwrap_or_default(), ni, nr].concat();
+        let length = 2 * (encr_length + integ_length);
+        let mut keymat = prf_plus(prf, sk_d, &seed, length)?.into_iter();
+        let mut take = |n: usize| keymat.by_ref().take(n).collect::<Vec<u8>>();
+        Ok(ChildSaKeys {
+            encr_i: take(encr_length),
+            integ_i: take(integ_length),
+            encr_r: take(encr_length),
+            integ_r: take(integ_length),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZero;
+
+    use super::*;
+    use crate::consts::*;
+    use crate::test_util::{hex, proposal, transform};
+    use crate::types::*;
+
+    fn header() -> IKEHeader {
+        let data = include_bytes!("testdata/ike_sa_init.bin");
+        let mut header = IKEHeader::try_from(&data[..28]).unwrap();
+        header.initiator_spi = NonZero::new(0x0102030405060708).unwrap();
+        header.responder_spi = 0x1112131415161718;
+        header
+    }
+
+    #[test]
+    fn test_prf() {
+        // RFC 2202 test case 2
+        assert_eq!(
+            prf(
+                &transform::PRF::PRF_HMAC_SHA1,
+                b"Jefe",
+                b"what do ya want for nothing?"
+            ),
+            Ok(hex("effcdf6ae5eb2fa2d27416d5f184df9c259a7c79"))
+        );
+        // RFC 4231 test cases 1 and 2
+        let key = [0x0b; 20];
+        assert_eq!(
+            prf(&transform::PRF::PRF_HMAC_SHA2_256, &key, b"Hi There"),
+            Ok(hex(
+                "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+            ))
+        );
+        assert_eq!(
+            prf(&transform::PRF::PRF_HMAC_SHA2_384, &key, b"Hi There"),
+            Ok(hex("afd03944d84895626b0825f4ab46907f15f9dadbe4101ec6\
+                 82aa034c7cebc59cfaea9ea9076ede7f4af152e8b2fa9cb6"))
+        );
+        assert_eq!(
+            prf(&transform::PRF::PRF_HMAC_SHA2_512, &key, b"Hi There"),
+            Ok(hex(
+                "87aa7cdea5ef619d4ff0b4241a1d6cb02379f4e2ce4ec2787ad0b30545e17cde\
+                 daa833b7d6b8a702038b274eaea3f4e4be9d914eeb61f1702e696c203a126854"
+            ))
+        );
+        assert_eq!(
+            prf(
+                &transform::PRF::PRF_HMAC_SHA2_256,
+                b"Jefe",
+                b"what do ya want for nothing?"
+            ),
+            Ok(hex(
+                "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+            ))
+        );
+        assert_eq!(
+            prf(&transform::PRF::PRF_AES128_XCBC, &key, b""),
+            Err(Error::UnsupportedTransform(TransformType::PRF(
+                0,
+                transform::PRF::PRF_AES128_XCBC
+            )))
+        );
+    }
+
+    fn ike_proposal(prf: &transform::PRF, additional: bool) -> Proposal {
+        let mut transforms = vec![
+            transform(
+                TransformType::ENCR(0, transform::ENCR::ENCR_AES_CBC),
+                Some(256),
+            ),
+            transform(TransformType::PRF(0, prf.clone()), None),
+            transform(
+                TransformType::INTEG(0, transform::INTEG::AUTH_HMAC_SHA2_256_128),
+                None,
+            ),
+            transform(TransformType::KE(0, transform::KE::MODP_2048), None),
+        ];
+        if additional {
+            transforms.push(transform(
+                TransformType::ADDKE1(0, transform::KE::ML_KEM_768),
+                None,
+            ));
+        }
+        proposal(ProtocolIdentifier::IKE, transforms)
+    }
+
+    /// Splits known keying material into the keys of an IKE SA with 32-byte
+    /// encryption and integrity keys, in the order of RFC 7296 §2.14.
+    fn ike_sa_keys(keymat: &[u8], prf_length: usize) -> IkeSaKeys {
+        let mut keymat = keymat.iter().copied();
+        let mut take = |n: usize| keymat.by_ref().take(n).collect::<Vec<u8>>();
+        IkeSaKeys {
+            sk_d: take(prf_length),
+            sk_ai: take(32),
+            sk_ar: take(32),
+            sk_ei: take(32),
+            sk_er: take(32),
+            sk_pi: take(prf_length),
+            sk_pr: take(prf_length),
+        }
+    }
+
+    #[test]
+    fn test_prf_plus() {
+        // prf+ ("key", "seed") into one block and a bit, from Python's hmac module
+        let cases = [
+            (
+                transform::PRF::PRF_HMAC_SHA1,
+                "0a4dd4d286ae93c5e1d24f24f3756216b533dd67ad15d19cef25f483",
+            ),
+            (
+                transform::PRF::PRF_HMAC_SHA2_256,
+                "a2392e429a99b173341b368bb5ce320bfd483d89567c14ec187c2d77e3c0a208\
+                 ba45d21d42611712",
+            ),
+            (
+                transform::PRF::PRF_HMAC_SHA2_384,
+                "245b57f46db11477bf9cd2e6adbc6244448af40400d70a9203399a48756b7dbf\
+                 53087f405e438cf9bbb7d38b34915f6b7dab328fb2580112",
+            ),
+            (
+                transform::PRF::PRF_HMAC_SHA2_512,
+                "173a6224816997fa5b2129f1c4d26d629028846663e6e27dd28d35d0de375705\
+                 6e7da6c4214436a07fa827ba4329baf9097b2eddf4c1ea2b89caf65189987801\
+                 bbb7a7125bce5d65",
+            ),
+        ];
+        for (prf, expected) in cases {
+            let length = prf.output_length().unwrap();
+            assert_eq!(
+                prf_plus(&prf, b"key", b"seed", length + 8),
+                Ok(hex(expected))
+            );
+            assert_eq!(
+                prf_plus(&prf, b"key", b"seed", 255 * length).unwrap().len(),
+                255 * length
+            );
+            assert_eq!(
+                prf_plus(&prf, b"key", b"seed", 255 * length + 1),
+                Err(Error::KeyMaterialTooLong(255 * length + 1))
+            );
+        }
+    }
+
+    #[test]
+    fn test_derive() {
+        // SKEYSEED and {SK_d | SK_ai | SK_ar | SK_ei | SK_er | SK_pi | SK_pr}
+        // from Python's hmac module
+        let ni: Vec<u8> = (0..0x20).collect();
+        let nr: Vec<u8> = (0x20..0x40).collect();
+        let shared_secret: Vec<u8> = (0..64u8).map(|i| i.wrapping_mul(7)).collect();
+
+        let sha256 = ike_proposal(&transform::PRF::PRF_HMAC_SHA2_256, false);
+        assert_eq!(
+            skeyseed(&transform::PRF::PRF_HMAC_SHA2_256, &ni, &nr, &shared_secret),
+            Ok(hex(
+                "c74b0436b33eaf088bc863c4c8d51b925e234999f23c46b566ea3143c27ab2b2"
+            ))
+        );
+        assert_eq!(
+            IkeSaKeys::derive(&sha256, &header(), &ni, &nr, &shared_secret),
+            Ok(IkeSaKeys {
+                sk_d: hex("1e7ee0407f6477462e44d2ab2f77ea025ff9cc5e4f474a5aaa1a6de307b60f16"),
+                sk_ai: hex("74adebd08be342db3aa95b7f139ae0de7fc0c2c753ab458294d3bc53842f339d"),
+                sk_ar: hex("8e4e09bb6bcc80c7606d076277f2e470e28c4f05d120529eaac4a74d1c310216"),
+                sk_ei: hex("37a139467c83c8401b43ec1f3f73b0e2e59dfc7dbd9b54ef5f820edb6d059e36"),
+                sk_er: hex("2e728556053ba33f0337819f601e9f8f3d33da2646e6b71c3e3178c70752601a"),
+                sk_pi: hex("f140597f5d6445d7ae1a8aa988d7e55327d12e466df3af3e0000cbc8c61ba899"),
+                sk_pr: hex("62786df00c5faddb0e3916b472e6c5e37e6a72d70806b40418edd204ed5eff98"),
+            })
+        );
+
+        let cases = [
+            (
+                transform::PRF::PRF_HMAC_SHA1,
+                "0a897a894088ba47572846d17f4f4576d34f1cdc",
+                "a1f7aa2bbb53528f6b920c2b21536dc0469d22abf3096bbb693323144ef69030\
+                 ddb7b771473127c8787633443026d92705b6ba8b892ba66336b21356be974d22\
+                 ca525d14bc307509c6b2e8763be977e6bf950c3bf8429bf19aa5cfb93d108dfa\
+                 7debd3ad501f4a59725a9a32096caa4d771d807586ef8787e21c926583106701\
+                 0b244b589af786a3e4fb7f9378b6680d94fb6eabc7a4831014915998c8f63802\
+                 5296759c199964413bf2674c1f6ac63e3e3f11804e5a052a3ff436ab",
+            ),
+            (
+                transform::PRF::PRF_HMAC_SHA2_384,
+                "8c82e16243c6d8b9fca74bea6f375e66bfcc14699eed14c2d187c2a574c35972\
+                 e48e5d6fed2c39bea46e2fa291a6a57a",
+                "cff482055e5cecee1f5492d31c61bb9a6d9adc0eec874aa310fbee8dd34f012e\
+                 3b2b053ea451c1bc4d61d73f981b5168ddde8adef3f327b96e74231cf2c08183\
+                 677fe5e5555706d51a3a30a8bf54ba9eee6ef40ed8181ac2e3d0ed1dcb3b4ee8\
+                 1316199b0adac7c4cec73c24021e31dd2eae11d4b1a3387d256ecea1db8e42c8\
+                 37d897b6d1a69add2dc345f4ab96da381f508883993501635d5290e69f504bda\
+                 ea42e8a1fffcc97ec8dc8e775a1959d08fa02b00abc51da72661c489f0581577\
+                 64ebbce2c46ab13ca8180b9a10a5bcd8d9b8c26618c50eb51ea79f628cc42055\
+                 60187fb91b0ddc43ec8d1d2efac67e4badf0f17a12cc9795fc528b5d433860ca\
+                 80e03f27d6f271e9ad7d15dc2f6f5fb9",
+            ),
+            (
+                transform::PRF::PRF_HMAC_SHA2_512,
+                "3806690c344f9a1a2724bf112d0595a4690a7e2749206155cf7b50b2b7ec6663\
+                 98df37bfb1b624a2283508ddbc763a8a4729955f727fe810dc61409d5960ee4f",
+                "9fbe2fc922302d39f9dcd3cfdfa3c3fb2a7102ad439c7cbba4f54f56ec87365e\
+                 d0dc86d1a0b8835399a7ed437d43cbd627251d2bba9c2680caa83f99fe00f8d3\
+                 3c2b53a884b1209c37d651e049836c4480ce27c8ed5384e79bb04e1b66ae6219\
+                 b6806c723f10757bc53f66ce0fff86658ee2f544dee1bd6f28ba0c8bd7082ce8\
+                 8064266668b6fcf0697365e7332b0f4b187861feb36b71f0dbf4e3e963ff947c\
+                 c0c95491a0006bb4ca288878ed5a09de838bfaea7608108dfa87951818346966\
+                 03e9dec723cb23eee62b575b7c3ae92ef10105ffe6734630484b067bbef96234\
+                 6dd45bb9119fdbdfb18b4bdda054eba299c0356b306ad9394b75993a8db342be\
+                 35237783164ce639b322e58e608c5c611eb30b49be6955157b447e822fa5092d\
+                 3abe1537f4b0612ddefd47aebe4f1e3d7bd82b629a9bfc0da560d5d777729cb5",
+            ),
+        ];
+        for (prf, expected, keymat) in cases {
+            assert_eq!(skeyseed(&prf, &ni, &nr, &shared_secret), Ok(hex(expected)));
+            assert_eq!(
+                IkeSaKeys::derive(
+                    &ike_proposal(&prf, false),
+                    &header(),
+                    &ni,
+                    &nr,
+                    &shared_secret
+                ),
+                Ok(ike_sa_keys(&hex(keymat), prf.output_length().unwrap()))
+            );
+        }
+
+        // AEAD ciphers take no integrity keys and their salt with the key
+        let gcm = proposal(
+            ProtocolIdentifier::IKE,
+            vec![
+                transform(
+                    TransformType::ENCR(0, transform::ENCR::ENCR_AES_GCM_16),
+                    Some(128),
+                ),
+                transform(
+                    TransformType::PRF(0, transform::PRF::PRF_HMAC_SHA2_384),
+                    None,
+                ),
+                transform(TransformType::KE(0, transform::KE::Curve25519), None),
+            ],
+        );
+        let keymat = hex(
+            "cff482055e5cecee1f5492d31c61bb9a6d9adc0eec874aa310fbee8dd34f012e\
+             3b2b053ea451c1bc4d61d73f981b5168ddde8adef3f327b96e74231cf2c08183\
+             677fe5e5555706d51a3a30a8bf54ba9eee6ef40ed8181ac2e3d0ed1dcb3b4ee8\
+             1316199b0adac7c4cec73c24021e31dd2eae11d4b1a3387d256ecea1db8e42c8\
+             37d897b6d1a69add2dc345f4ab96da381f508883993501635d5290e69f504bda\
+             ea42e8a1fffcc97ec8dc8e775a1959d08fa02b00abc51da7",
+        );
+        let keys = IkeSaKeys::derive(&gcm, &header(), &ni, &nr, &shared_secret).unwrap();
+        assert_eq!(keys.sk_d, keymat[..48]);
+        assert!(keys.sk_ai.is_empty());
+        assert!(keys.sk_ar.is_empty());
+        assert_eq!(keys.sk_ei, keymat[48..68]);
+        assert_eq!(keys.sk_er, keymat[68..88]);
+        assert_eq!(keys.sk_pi, keymat[88..136]);
+        assert_eq!(keys.sk_pr, keymat[136..]);
+
+        let no_prf = proposal(
+            ProtocolIdentifier::IKE,
+            vec![transform(
+                TransformType::ENCR(0, transform::ENCR::ENCR_AES_CBC),
+                Some(128),
+            )],
+        );
+        assert_eq!(
+            IkeSaKeys::derive(&no_prf, &header(), &ni, &nr, &shared_secret),
+            Err(Error::MissingTransform("PRF"))
+        );
+    }
+
+    #[test]
+    fn test_update() {
+        // Keys from SKEYSEED(1) = prf(SK_d(0), SK(1) | Ni | Nr), continuing
+        // from the keys of test_derive, from Python's hmac module
+        let ni: Vec<u8> = (0..0x20).collect();
+        let nr: Vec<u8> = (0x20..0x40).collect();
+        let shared_secret: Vec<u8> = (0..64u8).map(|i| i.wrapping_mul(7)).collect();
+        let additional_secret: Vec<u8> = (0..32u8).map(|i| i.wrapping_mul(11)).collect();
+
+        let proposal = ike_proposal(&transform::PRF::PRF_HMAC_SHA2_256, true);
+        let keys = IkeSaKeys::derive(&proposal, &header(), &ni, &nr, &shared_secret).unwrap();
+        assert_eq!(
+            keys.update(&proposal, &header(), &ni, &nr, &additional_secret),
+            Ok(IkeSaKeys {
+                sk_d: hex("b384779c6445f4720f7ed98db490a7f73d26a667bc490d0b54d225e51bd876d8"),
+                sk_ai: hex("fba0764bf7a9f887706269cd6d884ccccbd5c4100ad2e55ec5da94e80849889e"),
+                sk_ar: hex("afdfc471007124ea64b67a39a6716e9700abc7821c544a338ce958266f8f40dc"),
+                sk_ei: hex("f9c772ba2374f3e3269a7fc4b28e950ff42d6ee13ae7ddcb223f3afccae5f718"),
+                sk_er: hex("1e8fd1ec4aa14bc531216096e60db8d986decdfdbca24656e78983ce4a5ac729"),
+                sk_pi: hex("63ad1e85f6ab695e56a92852b2135f253803f67bb8d8816780d3afbfe45574d5"),
+                sk_pr: hex("de3f070946c5a218b889bfab5c57e87c803ba6d2a7fd22591d0fa9fbb5aa55cd"),
+            })
+        );
+
+        let cases = [
+            (
+                transform::PRF::PRF_HMAC_SHA1,
+                "c428ea03f179f076b442100aa23fdc71f0c4fd6f41931be2a46e252728840d7a\
+                 e2ea1a7aa166f1e3a9e93baddbe0628415cbb2a625e57140a1f9b8a9a4aea300\
+                 b4138a767d536c479a8ef2e01f9c3166425b05d570d9a052186af8de49eb7cd5\
+                 0934b452d4ae5de0dcfac86bf10b9a9c11351d9257bf2fc54ac6a174f4e61654\
+                 8a89413e40b1c701195bc8034994dfad95e7842e044f2a311f59f5a359666ef4\
+                 ec1b53e754c5bd34d772e96e9ecad89bb8c98482712e97859a9f806e",
+            ),
+            (
+                transform::PRF::PRF_HMAC_SHA2_384,
+                "abcd72646cc90e930cbae6a52b4f7ebcc245c1ebed1723848a73c3e11cfc4253\
+                 78352c6936938643369d6f341a5bf2b4337f4d70968b2278a70efb3101f9a62a\
+                 c8c6c67ab9b3b9cf9f65c738013ac05693b51dfb19bc2036a147764e1b2fbc9c\
+                 1181c17b9339d48161ace6caf217336d4ac8c2eea008ac53ca504d2fa97fcd9c\
+                 d3434a7c209609d29fc4ec1d50d46591d9421d98df286ba20676d622a5829912\
+                 a2c32d8662c1cc858e857415b33a4821b44d7184f0ea227d4e90a620b2fb1fde\
+                 15ad4349f7e44d1085d58a4254319fd8300fc7081ebf0fd2ad7248217a5f3cf1\
+                 477681cb00fd2d2057bb4707fdb41288ab3b7a50aff3808dbe01893253d214c0\
+                 238210c7906bfefa4e19e439a20d3c44",
+            ),
+            (
+                transform::PRF::PRF_HMAC_SHA2_512,
+                "dd1275bae83446399b6a1d6b8d0372d9dc493d52430783ed6d7fe722361d77ab\
+                 50351025e735f727b4da8f2c4380ee42a5a0139c2bf589822d8b5be1e7ff9688\
+                 fa7ddaad3d5e7d70c0c3e604e56f91b70ae26d11fb501ea044ec188bbe53f6f8\
+                 7281c8af40fd79081c116fe83c27c759db5f9a5161a9aea93d2b95991f717056\
+                 8b607c0f6f58ef5ba2a08d1184aa5d7ce94bfeb10d8bb4aa1de73acac2e5a84a\
+                 ee2bdeb9fe50d64414eb964c6ff14faadb51eb0c0622b15e2aa8b046f392f4b2\
+                 d23af957bfda30227e08d54f064ae7316b5b82e6d5858068be67edb117bc0ab3\
+                 68280e57d2df00a869e83d3063ab983abf4c36b0d09ae8f3a38243f21d1b0ab0\
+                 66490377ff8ba3e57114e5eea24f62a331be1124cf2ceaf5d1ab23edf0ee4972\
+                 fab8e92d957a2c87238804b9f8caeed67f8ef0e579b01d5edd30f85091cfeaa0",
+            ),
+        ];
+        for (prf, keymat) in cases {
+            let proposal = ike_proposal(&prf, true);
+            let keys = IkeSaKeys::derive(&proposal, &header(), &ni, &nr, &shared_secret).unwrap();
+            assert_eq!(
+                keys.update(&proposal, &header(), &ni, &nr, &additional_secret),
+                Ok(ike_sa_keys(&hex(keymat), prf.output_length().unwrap()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_child_sa_keys() {
+        let sk_d: Vec<u8> = (0x40..0x60).collect();
+        let ni: Vec<u8> = (0..0x20).collect();
+        let nr: Vec<u8> = (0x20..0x40).collect();
+        let shared_secret = [0xaa; 32];
+
+        let gcm = proposal(
+            ProtocolIdentifier::ESP,
+            vec![transform(
+                TransformType::ENCR(0, transform::ENCR::ENCR_AES_GCM_16),
+                Some(256),
+            )],
+        );
+        let cbc = proposal(
+            ProtocolIdentifier::ESP,
+            vec![
+                transform(
+                    TransformType::ENCR(0, transform::ENCR::ENCR_AES_CBC),
+                    Some(128),
+                ),
+                transform(
+                    TransformType::INTEG(0, transform::INTEG::AUTH_HMAC_SHA2_256_128),
+                    None,
+                ),
+            ],
+        );
+        let mut ah = proposal(
+            ProtocolIdentifier::AH,
+            vec![transform(
+                TransformType::INTEG(0, transform::INTEG::AUTH_HMAC_SHA2_256_128),
+                None,
+            )],
+        );
+
+        // KEYMAT = prf+(SK_d, Ni | Nr) and prf+(SK_d, g^ir (new) | Ni | Nr),
+        // from Python's hmac module
+        let prf = transform::PRF::PRF_HMAC_SHA2_256;
+        let keymat = hex(
+            "3e4b18037ac81676e07836acb30310948c408dab39cad45276b62602ddd30a26\
+             aeb332d0b6992f5f4a626d9d8312a02f32bd85d6125c1ac436f734c9fd9b4f93\
+             4d60814817c3bdad",
+        );
+        assert_eq!(
+            ChildSaKeys::derive(&prf, &sk_d, &gcm, None, &ni, &nr),
+            Ok(ChildSaKeys {
+                encr_i: keymat[..36].to_vec(),
+                integ_i: vec![],
+                encr_r: keymat[36..].to_vec(),
+                integ_r: vec![],
+            })
+        );
+
+        let keymat = hex(
+            "890310d7513b02972a0980ced3afc4a1fa5905856f076c3630bcb10a0f654a02\
+             a37e5902f6d1156ef63621da5f0785b457e054ca02d64b046176c6b9fa4abb77\
+             4bf0bcbf4004f3530bd5e83856efd7a3f513a48b6df4ab9eaf2682dcafcd89a7",
+        );
+        let keys = ChildSaKeys::derive(&prf, &sk_d, &cbc, Some(&shared_secret), &ni, &nr).unwrap();
+        assert_eq!(keys.encr_i, keymat[..16]);
+        assert_eq!(keys.integ_i, keymat[16..48]);
+        assert_eq!(keys.encr_r, keymat[48..64]);
+        assert_eq!(keys.integ_r, keymat[64..]);
+
+        // AH takes only integrity keys from the same KEYMAT
+        let keys = ChildSaKeys::derive(&prf, &sk_d, &ah, Some(&shared_secret), &ni, &nr).unwrap();
+        assert!(keys.encr_i.is_empty());
+        assert_eq!(keys.integ_i, keymat[..32]);
+        assert_eq!(keys.integ_r, keymat[32..64]);
+
+        let cases = [
+            (
+                transform::PRF::PRF_HMAC_SHA1,
+                "4473a41d97291a0ac60e6fe0cf35ea7a22dd5e8096693c60e75fbd80fdc55979\
+                 446bfb23eb903c882542a6beb1b5da3652ceeb008e724f7634a0b2baccfee94d\
+                 3074f18848bf16d2",
+                "72020e0970007cc5edaa30887800630cf3d4e21c470abc894f98bde062acb2be\
+                 d22f765d45caf3f318f76c32955ddfb53797af617a3b5dc4e7ab996af75ea771\
+                 94aa755eda46e0dee87247852c0b73939081497ef5331abb5c891c6deb1489f9",
+            ),
+            (
+                transform::PRF::PRF_HMAC_SHA2_384,
+                "66fa65fa600e555ada50f8ba11d90f523aab81e36b7f81601f74684c3ca93d9e\
+                 ad857d52e71ad79e8396a5d8548717646bb0a3c4c524733c1c73114e5c54bc40\
+                 ad8396f526a3abc7",
+                "d42360eb92c8cb807f1892389d7f1c1ca40886f7513d96f16c622e4f2b643e5a\
+                 1f4d078a09c6246cc9dafc3952c854367c88577ba51e0f8454d6e199c1a9aff5\
+                 448186e24abcbb8ab096016a8e73d2a51384176a34c4a426bd4a0d67b63945ed",
+            ),
+            (
+                transform::PRF::PRF_HMAC_SHA2_512,
+                "d838e63bf902bebcd0a40f1439c0a5c28754362d3b62b8f0dc746efd9cd44cbc\
+                 07c3cfdc86e573bc5d677bfcca988353f34aaa2cb6858d0779b823065ddb19ed\
+                 5f168493c49b4c30",
+                "b15888d2007d63850dae6d1f8cf2912b20e9fd739471dcc74e12644abf8207b4\
+                 8b1da6e29ed61e1f08f0df6ec9340ef254275429a2e2a671383f5dffc2d93894\
+                 9e13926687be94fa16477a8f0da2b0c05250e344a2da53429382bea8e0b47c17",
+            ),
+        ];
+        for (prf, keymat, pfs_keymat) in cases {
+            let (keymat, pfs_keymat) = (hex(keymat), hex(pfs_keymat));
+            assert_eq!(
+                ChildSaKeys::derive(&prf, &sk_d, &gcm, None, &ni, &nr),
+                Ok(ChildSaKeys {
+                    encr_i: keymat[..36].to_vec(),
+                    integ_i: vec![],
+                    encr_r: keymat[36..].to_vec(),
+                    integ_r: vec![],
+                })
+            );
+            assert_eq!(
+                ChildSaKeys::derive(&prf, &sk_d, &cbc, Some(&shared_secret), &ni, &nr),
+                Ok(ChildSaKeys {
+                    encr_i: pfs_keymat[..16].to_vec(),
+                    integ_i: pfs_keymat[16..48].to_vec(),
+                    encr_r: pfs_keymat[48..64].to_vec(),
+                    integ_r: pfs_keymat[64..].to_vec(),
+                })
+            );
+        }
+
+        ah.transforms.clear();
+        assert_eq!(
+            ChildSaKeys::derive(&prf, &sk_d, &ah, None, &ni, &nr),
+            Err(Error::MissingTransform("INTEG"))
+        );
+        assert_eq!(
+            ChildSaKeys::derive(
+                &prf,
+                &sk_d,
+                &proposal(ProtocolIdentifier::IKE, vec![]),
+                None,
+                &ni,
+                &nr
+            ),
+            Err(Error::UnexpectedProtocol(ProtocolIdentifier::IKE))
+        );
+    }
+}
@@ -1,3 +1,22 @@
+pub mod auth;
+pub mod cert;
+pub mod config;
 pub mod consts;
+pub mod eap;
+pub mod error;
+pub mod ike_sa;
+pub mod intermediate;
+pub mod ke;
+pub mod keys;
+pub mod message;
+pub mod pool;
+pub mod proposal;
+pub mod sig;
+pub mod sk;
 pub mod transform;
+pub mod ts;
 pub mod types;
+pub mod x509;
+
+#[cfg(test)]
+mod test_util;
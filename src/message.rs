@@ -0,0 +1,407 @@
+use deku::prelude::*;
+
+use crate::auth::AuthPayload;
+use crate::cert::{CertPayload, CertReqPayload};
+use crate::config::ConfigPayload;
+use crate::consts::*;
+use crate::eap::EapPayload;
+use crate::error::Error;
+use crate::ts::TsPayload;
+use crate::types::*;
+
+pub(crate) const IKE_HEADER_LENGTH: usize = 28;
+pub(crate) const PAYLOAD_HEADER_LENGTH: usize = 4;
+
+/// A complete IKEv2 message: the fixed header followed by its payload chain.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IkeMessage {
+    pub header: IKEHeader,
+    pub payloads: Vec<Payload>,
+}
+
+/// A single payload of the chain, stripped of its generic payload header.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Payload {
+    pub payload_type: PayloadType,
+    pub critical: bool,
+    pub body: PayloadBody,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PayloadBody {
+    SA(SaPayload),
+    KE(KePayload),
+    /// Body of an IDi or IDr payload
+    ID(IdPayload),
+    CERT(CertPayload),
+    CERTREQ(CertReqPayload),
+    AUTH(AuthPayload),
+    Nonce(NoncePayload),
+    N(NotifyPayload),
+    D(DeletePayload),
+    V(VendorIdPayload),
+    /// Body of a TSi or TSr payload
+    TS(TsPayload),
+    CP(ConfigPayload),
+    EAP(EapPayload),
+    /// Body of an SK or SKF payload. The chain ends here; `next_payload`
+    /// names the first payload inside the ciphertext.
+    Encrypted {
+        next_payload: PayloadType,
+        data: Vec<u8>,
+    },
+    Raw(Vec<u8>),
+}
+
+impl IkeMessage {
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < IKE_HEADER_LENGTH {
+            return Err(Error::TruncatedHeader(data.len()));
+        }
+        let header = IKEHeader::try_from(&data[..IKE_HEADER_LENGTH])?;
+        if header.length as usize != data.len() {
+            return Err(Error::LengthMismatch {
+                header: header.length,
+                actual: data.len(),
+            });
+        }
+
+        let payloads = parse_payloads(
+            header.next_payload.clone(),
+            &data[IKE_HEADER_LENGTH..],
+            header.flags.response,
+        )?;
+        Ok(IkeMessage { header, payloads })
+    }
+
+    /// Recomputes every length, next-payload link and `LastSubstructure`
+    /// marker from the payload tree.
+    pub fn update(&mut self) -> Result<(), Error> {
+        self.encode_payloads().map(|_| ())
+    }
+
+    /// Encodes the message, deriving all framing fields from the payload tree
+    /// regardless of what is stored in them.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        let mut message = self.clone();
+        let payloads = message.encode_payloads()?;
+        let mut data = message.header.to_bytes()?;
+        data.extend(payloads);
+        Ok(data)
+    }
+
+    fn encode_payloads(&mut self) -> Result<Vec<u8>, Error> {
+        let (first, data) = encode_payloads(&mut self.payloads)?;
+        self.header.next_payload = first;
+        let length = IKE_HEADER_LENGTH + data.len();
+        self.header.length = u32::try_from(length).map_err(|_| Error::MessageTooLong(length))?;
+        Ok(data)
+    }
+}
+
+/// Parses a payload chain starting with a payload of type `first`. The
+/// chain ends at a payload with no next payload or at an encrypted payload.
+pub(crate) fn parse_payloads(
+    first: PayloadType,
+    data: &[u8],
+    response: bool,
+) -> Result<Vec<Payload>, Error> {
+    let mut payloads = vec![];
+    let mut rest = data;
+    let mut current = first;
+    while current != PayloadType::NoNextPayload {
+        if rest.len() < PAYLOAD_HEADER_LENGTH {
+            return Err(Error::TruncatedPayload(current));
+        }
+        let payload_header = PayloadHeader::try_from(&rest[..PAYLOAD_HEADER_LENGTH])?;
+        let length = payload_header.payload_length;
+        if (length as usize) < PAYLOAD_HEADER_LENGTH {
+            return Err(Error::InvalidPayloadLength(current, length));
+        }
+        if length as usize > rest.len() {
+            return Err(Error::PayloadOverrun {
+                payload_type: current,
+                length,
+                remaining: rest.len(),
+            });
+        }
+        let body = &rest[PAYLOAD_HEADER_LENGTH..length as usize];
+        rest = &rest[length as usize..];
+
+        let encrypted = matches!(current, PayloadType::SK | PayloadType::SKF);
+        let body = match current {
+            _ if encrypted => PayloadBody::Encrypted {
+                next_payload: payload_header.next_payload.clone(),
+                data: body.to_vec(),
+            },
+            PayloadType::SA => {
+                let sa = SaPayload::try_from(body)?;
+                sa.validate()?;
+                PayloadBody::SA(sa)
+            }
+            PayloadType::KE => {
+                let ke = KePayload::try_from(body)?;
+                ke.validate(response)?;
+                PayloadBody::KE(ke)
+            }
+            PayloadType::IDi | PayloadType::IDr => PayloadBody::ID(IdPayload::try_from(body)?),
+            PayloadType::CERT => PayloadBody::CERT(CertPayload::try_from(body)?),
+            PayloadType::CERTREQ => PayloadBody::CERTREQ(CertReqPayload::try_from(body)?),
+            PayloadType::AUTH => PayloadBody::AUTH(AuthPayload::try_from(body)?),
+            PayloadType::Nonce => {
+                let nonce = NoncePayload::try_from(body)?;
+                nonce.validate()?;
+                PayloadBody::Nonce(nonce)
+            }
+            PayloadType::N => PayloadBody::N(NotifyPayload::try_from(body)?),
+            PayloadType::D => {
+                let delete = DeletePayload::try_from(body)?;
+                delete.validate()?;
+                PayloadBody::D(delete)
+            }
+            PayloadType::V => PayloadBody::V(VendorIdPayload::try_from(body)?),
+            PayloadType::TSi | PayloadType::TSr => PayloadBody::TS(TsPayload::try_from(body)?),
+            PayloadType::CP => PayloadBody::CP(ConfigPayload::try_from(body)?),
+            PayloadType::EAP => {
+                let eap = EapPayload::try_from(body)?;
+                eap.validate()?;
+                PayloadBody::EAP(eap)
+            }
+            _ => PayloadBody::Raw(body.to_vec()),
+        };
+        payloads.push(Payload {
+            payload_type: current,
+            critical: payload_header.critical,
+            body,
+        });
+        current = if encrypted {
+            PayloadType::NoNextPayload
+        } else {
+            payload_header.next_payload
+        };
+    }
+    if !rest.is_empty() {
+        return Err(Error::TrailingData(rest.len()));
+    }
+
+    Ok(payloads)
+}
+
+/// Encodes a payload chain, linking each payload to the next, and returns
+/// the type of the first payload along with the encoding.
+pub(crate) fn encode_payloads(payloads: &mut [Payload]) -> Result<(PayloadType, Vec<u8>), Error> {
+    let first = payloads
+        .first()
+        .map_or(PayloadType::NoNextPayload, |p| p.payload_type.clone());
+    let following: Vec<_> = payloads
+        .iter()
+        .skip(1)
+        .map(|p| Some(p.payload_type.clone()))
+        .chain([None])
+        .collect();
+    let mut data = vec![];
+    for (payload, following) in payloads.iter_mut().zip(following) {
+        let next_payload = match (&payload.body, following) {
+            (PayloadBody::Encrypted { next_payload, .. }, None) => next_payload.clone(),
+            (PayloadBody::Encrypted { .. }, Some(_)) => {
+                return Err(Error::PayloadAfterEncrypted(payload.payload_type.clone()));
+            }
+            (_, next) => next.unwrap_or(PayloadType::NoNextPayload),
+        };
+        let body = payload.body.encode()?;
+        let length = body.len() + PAYLOAD_HEADER_LENGTH;
+        let payload_header = PayloadHeader {
+            next_payload,
+            critical: payload.critical,
+            reserved: 0,
+            payload_length: u16::try_from(length)
+                .map_err(|_| Error::PayloadTooLong(payload.payload_type.clone(), length))?,
+        };
+        data.extend(payload_header.to_bytes()?);
+        data.extend(body);
+    }
+    Ok((first, data))
+}
+
+impl PayloadBody {
+    /// Fixes up the body's internal framing and returns its encoding.
+    fn encode(&mut self) -> Result<Vec<u8>, Error> {
+        match self {
+            PayloadBody::SA(sa) => {
+                sa.update()?;
+                Ok(sa.to_bytes()?)
+            }
+            PayloadBody::KE(ke) => Ok(ke.to_bytes()?),
+            PayloadBody::ID(id) => Ok(id.to_bytes()?),
+            PayloadBody::CERT(cert) => Ok(cert.to_bytes()?),
+            PayloadBody::CERTREQ(certreq) => Ok(certreq.to_bytes()?),
+            PayloadBody::AUTH(auth) => Ok(auth.to_bytes()?),
+            PayloadBody::Nonce(nonce) => Ok(nonce.to_bytes()?),
+            PayloadBody::N(notify) => {
+                notify.update()?;
+                Ok(notify.to_bytes()?)
+            }
+            PayloadBody::D(delete) => {
+                delete.update()?;
+                Ok(delete.to_bytes()?)
+            }
+            PayloadBody::V(vendor_id) => Ok(vendor_id.to_bytes()?),
+            PayloadBody::TS(ts) => {
+                ts.update()?;
+                Ok(ts.to_bytes()?)
+            }
+            PayloadBody::CP(cp) => Ok(cp.to_bytes()?),
+            PayloadBody::EAP(eap) => Ok(eap.to_bytes()?),
+            PayloadBody::Encrypted { data, .. } | PayloadBody::Raw(data) => Ok(data.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const IKE_SA_INIT: &[u8] = include_bytes!("testdata/ike_sa_init.bin");
+
+    #[test]
+    fn test_parse_sa_init() {
+        let message = IkeMessage::parse(IKE_SA_INIT).unwrap();
+        assert_eq!(message.header.exchange_type, ExchangeType::IKE_SA_INIT);
+        assert_eq!(
+            message
+                .payloads
+                .iter()
+                .map(|p| p.payload_type.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                PayloadType::SA,
+                PayloadType::KE,
+                PayloadType::Nonce,
+                PayloadType::N,
+                PayloadType::N,
+                PayloadType::N,
+                PayloadType::N,
+                PayloadType::N,
+            ]
+        );
+        let PayloadBody::SA(sa) = &message.payloads[0].body else {
+            panic!("SA payload not decoded");
+        };
+        assert_eq!(sa.proposals.len(), 2);
+        assert_eq!(sa.proposals[1].last_substructure, LastSubstructure::Last);
+        let PayloadBody::KE(ke) = &message.payloads[1].body else {
+            panic!("KE payload not decoded");
+        };
+        assert_eq!(ke.dh_group, crate::transform::KE::ECP_256);
+        assert_eq!(
+            message.payloads[2].body,
+            PayloadBody::Nonce(NoncePayload {
+                nonce: IKE_SA_INIT[852..884].to_vec()
+            })
+        );
+        assert_eq!(
+            message.payloads[3..]
+                .iter()
+                .map(|p| match &p.body {
+                    PayloadBody::N(notify) => notify.notify_type.clone(),
+                    _ => panic!("notify payload not decoded"),
+                })
+                .collect::<Vec<_>>(),
+            vec![
+                NotifyType::NAT_DETECTION_SOURCE_IP,
+                NotifyType::NAT_DETECTION_DESTINATION_IP,
+                NotifyType::IKEV2_FRAGMENTATION_SUPPORTED,
+                NotifyType::SIGNATURE_HASH_ALGORITHMS,
+                NotifyType::REDIRECT_SUPPORTED,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            IkeMessage::parse(&IKE_SA_INIT[..20]),
+            Err(Error::TruncatedHeader(20))
+        );
+        assert_eq!(
+            IkeMessage::parse(&IKE_SA_INIT[..900]),
+            Err(Error::LengthMismatch {
+                header: 972,
+                actual: 900,
+            })
+        );
+
+        // Shrink the header length so the last notify runs past the end.
+        let mut data = IKE_SA_INIT[..968].to_vec();
+        data[24..28].copy_from_slice(&968u32.to_be_bytes());
+        assert_eq!(
+            IkeMessage::parse(&data),
+            Err(Error::PayloadOverrun {
+                payload_type: PayloadType::N,
+                length: 8,
+                remaining: 4,
+            })
+        );
+
+        // Terminate the chain one notify early.
+        let mut data = IKE_SA_INIT.to_vec();
+        data[948] = 0;
+        assert_eq!(IkeMessage::parse(&data), Err(Error::TrailingData(8)));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let message = IkeMessage::parse(IKE_SA_INIT).unwrap();
+        assert_eq!(message.to_bytes().unwrap(), IKE_SA_INIT);
+    }
+
+    #[test]
+    fn test_encode_fixups() {
+        let expected = IkeMessage::parse(IKE_SA_INIT).unwrap();
+        let mut message = expected.clone();
+        message.header.next_payload = PayloadType::NoNextPayload;
+        message.header.length = 0;
+        let PayloadBody::SA(sa) = &mut message.payloads[0].body else {
+            unreachable!()
+        };
+        for proposal in sa.proposals.iter_mut() {
+            proposal.last_substructure = LastSubstructure::Proposal;
+            proposal.proposal_length = 0;
+            proposal.spi_size = 0;
+            proposal.num_transforms = 0;
+            for transform in proposal.transforms.iter_mut() {
+                transform.last_substructure = LastSubstructure::Last;
+                transform.transform_length = 0;
+            }
+        }
+        assert_eq!(message.to_bytes().unwrap(), IKE_SA_INIT);
+        message.update().unwrap();
+        assert_eq!(message, expected);
+    }
+
+    #[test]
+    fn test_encode_encrypted_last() {
+        let mut message = IkeMessage::parse(IKE_SA_INIT).unwrap();
+        message.payloads.insert(
+            1,
+            Payload {
+                payload_type: PayloadType::SK,
+                critical: false,
+                body: PayloadBody::Encrypted {
+                    next_payload: PayloadType::IDi,
+                    data: vec![],
+                },
+            },
+        );
+        assert_eq!(
+            message.to_bytes(),
+            Err(Error::PayloadAfterEncrypted(PayloadType::SK))
+        );
+
+        message.payloads.truncate(2);
+        let data = message.to_bytes().unwrap();
+        assert_eq!(data[IKE_HEADER_LENGTH], 46);
+        assert_eq!(data[IKE_HEADER_LENGTH + 748..], [35, 0, 0, 4]);
+        assert_eq!(IkeMessage::parse(&data).unwrap().payloads, message.payloads);
+    }
+}
@@ -0,0 +1,326 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ops::RangeInclusive;
+
+use crate::config::{ConfigAttribute, ConfigPayload, ConfigValue};
+use crate::consts::*;
+use crate::error::Error;
+use crate::types::Identity;
+
+/// Virtual IP addresses leased to peers from configured IPv4 and IPv6
+/// ranges, keyed by peer identity (RFC 7296 §2.19).
+///
+/// IKE SAs of the same identity share its lease, which is released when the
+/// last of them goes. A released lease stays with its identity, so a peer
+/// that reconnects gets the same address back. Released leases are only
+/// handed to other peers, oldest first, once a range has no unused addresses
+/// left.
+pub struct AddressPool {
+    ipv4: Option<Leases>,
+    ipv6: Option<Leases>,
+}
+
+/// Leases of one address family, as offsets from the start of its range.
+struct Leases {
+    start: u128,
+    size: u128,
+    /// Offsets below this have been leased at least once.
+    used: u128,
+    by_identity: HashMap<Identity, Lease>,
+    by_offset: HashMap<u128, Identity>,
+    /// Offsets of released leases, oldest first.
+    released: VecDeque<u128>,
+}
+
+struct Lease {
+    offset: u128,
+    /// IKE SAs holding the lease; it is released at zero.
+    holders: usize,
+}
+
+impl AddressPool {
+    pub fn new(
+        ipv4: Option<RangeInclusive<Ipv4Addr>>,
+        ipv6: Option<RangeInclusive<Ipv6Addr>>,
+    ) -> Self {
+        AddressPool {
+            ipv4: ipv4
+                .map(|r| Leases::new(u32::from(*r.start()).into(), u32::from(*r.end()).into())),
+            ipv6: ipv6.map(|r| Leases::new((*r.start()).into(), (*r.end()).into())),
+        }
+    }
+
+    /// Leases the addresses asked for by INTERNAL_IP4_ADDRESS and
+    /// INTERNAL_IP6_ADDRESS attributes of a CFG_REQUEST and returns the
+    /// CFG_REPLY assigning them. Other attributes are left to the caller.
+    ///
+    /// Fails with INTERNAL_ADDRESS_FAILURE if addresses are requested but
+    /// none can be assigned.
+    pub fn reply(
+        &mut self,
+        identity: &Identity,
+        request: &ConfigPayload,
+    ) -> Result<ConfigPayload, Error> {
+        if request.cfg_type != CfgType::CFG_REQUEST {
+            return Err(Error::Notify(NotifyType::INVALID_SYNTAX));
+        }
+        let requested = |attribute_type| {
+            request
+                .attributes
+                .iter()
+                .any(|a| a.attribute_type == attribute_type)
+        };
+        let ipv4 = requested(ConfigAttributeType::INTERNAL_IP4_ADDRESS);
+        let ipv6 = requested(ConfigAttributeType::INTERNAL_IP6_ADDRESS);
+
+        let mut attributes = vec![];
+        if ipv4 && let Some(offset) = self.ipv4.as_mut().and_then(|l| l.acquire(identity)) {
+            attributes.push(ConfigAttribute::new(
+                ConfigAttributeType::INTERNAL_IP4_ADDRESS,
+                ConfigValue::Ipv4(Ipv4Addr::from(offset as u32)),
+            )?);
+        }
+        if ipv6 && let Some(offset) = self.ipv6.as_mut().and_then(|l| l.acquire(identity)) {
+            attributes.push(ConfigAttribute::new(
+                ConfigAttributeType::INTERNAL_IP6_ADDRESS,
+                ConfigValue::Ipv6Prefix {
+                    address: Ipv6Addr::from(offset),
+                    prefix_len: 128,
+                },
+            )?);
+        }
+        if (ipv4 || ipv6) && attributes.is_empty() {
+            return Err(Error::Notify(NotifyType::INTERNAL_ADDRESS_FAILURE));
+        }
+        Ok(ConfigPayload::new(CfgType::CFG_REPLY, attributes))
+    }
+
+    /// Releases the leases of a peer whose IKE SA was deleted once no other
+    /// IKE SA of it holds them, keeping them reserved for the same identity
+    /// while the pool allows.
+    pub fn release(&mut self, identity: &Identity) {
+        for leases in [&mut self.ipv4, &mut self.ipv6].into_iter().flatten() {
+            leases.release(identity);
+        }
+    }
+
+    /// Addresses currently leased to the identity.
+    pub fn addresses(&self, identity: &Identity) -> Vec<IpAddr> {
+        let ipv4 = self.ipv4.as_ref().and_then(|l| l.online(identity));
+        let ipv6 = self.ipv6.as_ref().and_then(|l| l.online(identity));
+        ipv4.map(|a| IpAddr::V4(Ipv4Addr::from(a as u32)))
+            .into_iter()
+            .chain(ipv6.map(|a| IpAddr::V6(Ipv6Addr::from(a))))
+            .collect()
+    }
+}
+
+impl Leases {
+    fn new(start: u128, end: u128) -> Self {
+        Leases {
+            start,
+            size: end.checked_sub(start).map_or(0, |d| d.saturating_add(1)),
+            used: 0,
+            by_identity: HashMap::new(),
+            by_offset: HashMap::new(),
+            released: VecDeque::new(),
+        }
+    }
+
+    /// The identity's address, reusing its previous lease if it has one.
+    fn acquire(&mut self, identity: &Identity) -> Option<u128> {
+        if let Some(lease) = self.by_identity.get_mut(identity) {
+            if lease.holders == 0 {
+                let offset = lease.offset;
+                self.released.retain(|o| *o != offset);
+            }
+            lease.holders += 1;
+            return Some(self.start + lease.offset);
+        }
+        let offset = if self.used < self.size {
+            self.used += 1;
+            self.used - 1
+        } else {
+            let offset = self.released.pop_front()?;
+            let previous = self.by_offset.remove(&offset)?;
+            self.by_identity.remove(&previous);
+            offset
+        };
+        self.by_identity
+            .insert(identity.clone(), Lease { offset, holders: 1 });
+        self.by_offset.insert(offset, identity.clone());
+        Some(self.start + offset)
+    }
+
+    fn release(&mut self, identity: &Identity) {
+        if let Some(lease) = self.by_identity.get_mut(identity)
+            && lease.holders > 0
+        {
+            lease.holders -= 1;
+            if lease.holders == 0 {
+                self.released.push_back(lease.offset);
+            }
+        }
+    }
+
+    fn online(&self, identity: &Identity) -> Option<u128> {
+        self.by_identity
+            .get(identity)
+            .filter(|lease| lease.holders > 0)
+            .map(|lease| self.start + lease.offset)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn peer(name: &str) -> Identity {
+        Identity::Fqdn(name.to_string())
+    }
+
+    fn address(reply: &ConfigPayload, attribute_type: ConfigAttributeType) -> ConfigValue {
+        let mut values = reply.values(&attribute_type).unwrap();
+        assert_eq!(values.len(), 1);
+        values.remove(0)
+    }
+
+    fn ipv4(address: &str) -> ConfigValue {
+        ConfigValue::Ipv4(address.parse().unwrap())
+    }
+
+    #[test]
+    fn test_reply() {
+        let mut pool = AddressPool::new(
+            Some("10.0.0.1".parse().unwrap()..="10.0.0.2".parse().unwrap()),
+            Some("2001:db8::1".parse().unwrap()..="2001:db8::ffff".parse().unwrap()),
+        );
+        let request = ConfigPayload::request([
+            ConfigAttributeType::INTERNAL_IP4_ADDRESS,
+            ConfigAttributeType::INTERNAL_IP6_ADDRESS,
+            ConfigAttributeType::INTERNAL_IP4_DNS,
+        ]);
+        let reply = pool.reply(&peer("alice"), &request).unwrap();
+        assert_eq!(reply.cfg_type, CfgType::CFG_REPLY);
+        assert_eq!(reply.attributes.len(), 2);
+        assert_eq!(
+            address(&reply, ConfigAttributeType::INTERNAL_IP4_ADDRESS),
+            ipv4("10.0.0.1")
+        );
+        assert_eq!(
+            address(&reply, ConfigAttributeType::INTERNAL_IP6_ADDRESS),
+            ConfigValue::Ipv6Prefix {
+                address: "2001:db8::1".parse().unwrap(),
+                prefix_len: 128,
+            }
+        );
+        assert_eq!(
+            pool.addresses(&peer("alice")),
+            [
+                "10.0.0.1".parse::<IpAddr>().unwrap(),
+                "2001:db8::1".parse().unwrap()
+            ]
+        );
+
+        // only the requested families are assigned
+        let request = ConfigPayload::request([ConfigAttributeType::INTERNAL_IP4_ADDRESS]);
+        let reply = pool.reply(&peer("bob"), &request).unwrap();
+        assert_eq!(reply.attributes.len(), 1);
+        assert_eq!(
+            address(&reply, ConfigAttributeType::INTERNAL_IP4_ADDRESS),
+            ipv4("10.0.0.2")
+        );
+
+        // nothing to assign
+        let reply = pool.reply(
+            &peer("carol"),
+            &ConfigPayload::request([ConfigAttributeType::INTERNAL_IP4_DNS]),
+        );
+        assert_eq!(reply.unwrap().attributes, []);
+        assert_eq!(
+            pool.reply(&peer("carol"), &request),
+            Err(Error::Notify(NotifyType::INTERNAL_ADDRESS_FAILURE))
+        );
+        let mut reply = request.clone();
+        reply.cfg_type = CfgType::CFG_REPLY;
+        assert_eq!(
+            pool.reply(&peer("carol"), &reply),
+            Err(Error::Notify(NotifyType::INVALID_SYNTAX))
+        );
+    }
+
+    #[test]
+    fn test_leases() {
+        let mut pool = AddressPool::new(
+            Some("192.0.2.10".parse().unwrap()..="192.0.2.11".parse().unwrap()),
+            None,
+        );
+        let request = ConfigPayload::request([ConfigAttributeType::INTERNAL_IP4_ADDRESS]);
+        let lease = |pool: &mut AddressPool, name| {
+            pool.reply(&peer(name), &request)
+                .map(|reply| address(&reply, ConfigAttributeType::INTERNAL_IP4_ADDRESS))
+        };
+        assert_eq!(lease(&mut pool, "alice"), Ok(ipv4("192.0.2.10")));
+        assert_eq!(lease(&mut pool, "bob"), Ok(ipv4("192.0.2.11")));
+        // a second IKE SA of the same identity shares its lease
+        assert_eq!(lease(&mut pool, "alice"), Ok(ipv4("192.0.2.10")));
+
+        pool.release(&peer("alice"));
+        assert_eq!(
+            pool.addresses(&peer("alice")),
+            ["192.0.2.10".parse::<IpAddr>().unwrap()]
+        );
+
+        // released leases come back to their identity
+        pool.release(&peer("alice"));
+        assert!(pool.addresses(&peer("alice")).is_empty());
+        assert_eq!(lease(&mut pool, "alice"), Ok(ipv4("192.0.2.10")));
+        assert_eq!(
+            lease(&mut pool, "carol"),
+            Err(Error::Notify(NotifyType::INTERNAL_ADDRESS_FAILURE))
+        );
+
+        // and to others, oldest first, once the range is exhausted
+        pool.release(&peer("bob"));
+        pool.release(&peer("alice"));
+        pool.release(&peer("alice"));
+        assert_eq!(lease(&mut pool, "carol"), Ok(ipv4("192.0.2.11")));
+        assert_eq!(lease(&mut pool, "bob"), Ok(ipv4("192.0.2.10")));
+        assert_eq!(
+            lease(&mut pool, "alice"),
+            Err(Error::Notify(NotifyType::INTERNAL_ADDRESS_FAILURE))
+        );
+        assert_eq!(
+            pool.addresses(&peer("carol")),
+            ["192.0.2.11".parse::<IpAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_shared_lease() {
+        let mut pool = AddressPool::new(
+            Some("10.0.0.1".parse().unwrap()..="10.0.0.1".parse().unwrap()),
+            None,
+        );
+        let request = ConfigPayload::request([ConfigAttributeType::INTERNAL_IP4_ADDRESS]);
+        assert!(pool.reply(&peer("alice"), &request).is_ok());
+        assert!(pool.reply(&peer("alice"), &request).is_ok());
+        pool.release(&peer("alice"));
+        assert_eq!(
+            pool.reply(&peer("bob"), &request),
+            Err(Error::Notify(NotifyType::INTERNAL_ADDRESS_FAILURE))
+        );
+        assert_eq!(
+            pool.addresses(&peer("alice")),
+            ["10.0.0.1".parse::<IpAddr>().unwrap()]
+        );
+
+        pool.release(&peer("alice"));
+        let reply = pool.reply(&peer("bob"), &request).unwrap();
+        assert_eq!(
+            address(&reply, ConfigAttributeType::INTERNAL_IP4_ADDRESS),
+            ipv4("10.0.0.1")
+        );
+        assert!(pool.addresses(&peer("alice")).is_empty());
+    }
+}
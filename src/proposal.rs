@@ -0,0 +1,764 @@
+use std::fmt;
+use std::mem::discriminant;
+use std::str::FromStr;
+
+use crate::consts::*;
+use crate::error::Error;
+use crate::transform;
+use crate::types::{Attribute, Proposal, Transform};
+
+/// Transforms acceptable for one protocol, each with its Key Length in bits.
+/// Any combination of one acceptable transform of each type may be chosen.
+///
+/// A listed transform type is required of the peer's proposals unless NONE
+/// (for SN, 32-bit sequence numbers) is among its transforms, which makes
+/// it optional. Types not listed are only accepted where the peer offers
+/// NONE for them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Policy {
+    pub protocol_id: ProtocolIdentifier,
+    pub transforms: Vec<(TransformType, Option<u16>)>,
+}
+
+impl From<&Proposal> for Policy {
+    fn from(proposal: &Proposal) -> Self {
+        Policy {
+            protocol_id: proposal.protocol_id.clone(),
+            transforms: proposal
+                .transforms
+                .iter()
+                .map(|t| (normalized(&t.transform_type), t.key_length()))
+                .collect(),
+        }
+    }
+}
+
+/// Chooses one of the peer's proposals as RFC 7296 §3.3.6 describes: the
+/// first, in the peer's order, that one of the policies accepts. It is
+/// returned with its number and SPI and only the peer's first acceptable
+/// transform of each type, or NO_PROPOSAL_CHOSEN if nothing is acceptable.
+///
+/// Proposals with transform types that do not belong to their protocol are
+/// skipped, as are transforms with attributes other than Key Length.
+/// Combined-mode ciphers are only chosen without an integrity algorithm
+/// (or with NONE), other ciphers only with one.
+pub fn negotiate(proposals: &[Proposal], policies: &[Policy]) -> Result<Proposal, Error> {
+    proposals
+        .iter()
+        .find_map(|proposal| policies.iter().find_map(|policy| choose(proposal, policy)))
+        .ok_or(Error::Notify(NotifyType::NO_PROPOSAL_CHOSEN))
+}
+
+/// The proposal reduced to one transform of each type, if the policy
+/// accepts it.
+fn choose(proposal: &Proposal, policy: &Policy) -> Option<Proposal> {
+    let protocol_id = &proposal.protocol_id;
+    if *protocol_id != policy.protocol_id
+        || !proposal
+            .transforms
+            .iter()
+            .all(|t| belongs_to(&t.transform_type, protocol_id))
+    {
+        return None;
+    }
+    let kind = |t: &TransformType| discriminant(t);
+    let listed = |t: &TransformType| policy.transforms.iter().any(|(p, _)| kind(p) == kind(t));
+
+    // the cipher decides whether an integrity algorithm is needed
+    let encr = proposal
+        .transforms
+        .iter()
+        .find_map(|t| match &t.transform_type {
+            TransformType::ENCR(_, encr) if accepts(policy, t) => Some(encr),
+            _ => None,
+        });
+    let combined_mode = encr.is_some_and(|encr| encr.icv_length().is_some());
+
+    let mut chosen: Vec<&Transform> = vec![];
+    for transform in &proposal.transforms {
+        let transform_type = &transform.transform_type;
+        if chosen
+            .iter()
+            .any(|c| kind(&c.transform_type) == kind(transform_type))
+        {
+            continue;
+        }
+        let acceptable = match transform_type {
+            TransformType::INTEG(..) if combined_mode => means_none(transform_type),
+            TransformType::INTEG(..) if encr.is_some() => {
+                !means_none(transform_type) && accepts(policy, transform)
+            }
+            _ if listed(transform_type) => accepts(policy, transform),
+            _ => means_none(transform_type),
+        };
+        if acceptable {
+            chosen.push(transform);
+        }
+    }
+    let is_chosen = |t: &TransformType| chosen.iter().any(|c| kind(&c.transform_type) == kind(t));
+
+    // every type offered must have been settled
+    if !proposal
+        .transforms
+        .iter()
+        .all(|t| is_chosen(&t.transform_type))
+    {
+        return None;
+    }
+    // types required by the policy must have been offered
+    let optional = |t: &TransformType| {
+        matches!(t, TransformType::INTEG(..)) && combined_mode
+            || policy
+                .transforms
+                .iter()
+                .any(|(p, _)| kind(p) == kind(t) && means_none(p))
+    };
+    if !policy
+        .transforms
+        .iter()
+        .all(|(t, _)| is_chosen(t) || optional(t))
+    {
+        return None;
+    }
+    // as must those of the protocol
+    let has = |is: fn(&TransformType) -> bool| {
+        chosen
+            .iter()
+            .any(|c| is(&c.transform_type) && !means_none(&c.transform_type))
+    };
+    let integ = has(|t| matches!(t, TransformType::INTEG(..)));
+    let complete = match protocol_id {
+        ProtocolIdentifier::IKE => {
+            encr.is_some()
+                && (combined_mode || integ)
+                && has(|t| matches!(t, TransformType::PRF(..)))
+                && has(|t| matches!(t, TransformType::KE(..)))
+        }
+        ProtocolIdentifier::ESP => encr.is_some() && (combined_mode || integ),
+        _ => integ,
+    };
+    if !complete {
+        return None;
+    }
+
+    let transforms = proposal
+        .transforms
+        .iter()
+        .filter(|t| chosen.iter().any(|c| std::ptr::eq(*c, *t)))
+        .cloned()
+        .collect();
+    Some(Proposal {
+        transforms,
+        ..proposal.clone()
+    })
+}
+
+/// Whether the transform is listed by the policy with the same Key Length,
+/// and has no other attributes.
+fn accepts(policy: &Policy, transform: &Transform) -> bool {
+    let transform_type = normalized(&transform.transform_type);
+    transform
+        .transform_attributes
+        .iter()
+        .all(|a| matches!(a, Attribute::KeyLength(_)))
+        && policy
+            .transforms
+            .iter()
+            .any(|(t, key_length)| *t == transform_type && *key_length == transform.key_length())
+}
+
+/// Whether the transform type may appear in a proposal for the protocol.
+fn belongs_to(transform_type: &TransformType, protocol_id: &ProtocolIdentifier) -> bool {
+    match transform_type {
+        TransformType::ENCR(..) => *protocol_id != ProtocolIdentifier::AH,
+        TransformType::PRF(..) => *protocol_id == ProtocolIdentifier::IKE,
+        TransformType::INTEG(..) | TransformType::KE(..) => true,
+        TransformType::SN(..) => *protocol_id != ProtocolIdentifier::IKE,
+        t => t.additional_key_exchange().is_some(),
+    }
+}
+
+/// Whether the transform amounts to leaving out its type: NONE, or 32-bit
+/// sequence numbers for SN.
+fn means_none(transform_type: &TransformType) -> bool {
+    match transform_type {
+        TransformType::INTEG(_, integ) => *integ == transform::INTEG::NONE,
+        TransformType::KE(_, ke) => *ke == transform::KE::NONE,
+        TransformType::SN(_, sn) => *sn == transform::SN::Sequential_32,
+        t => t
+            .additional_key_exchange()
+            .is_some_and(|(_, ke)| *ke == transform::KE::NONE),
+    }
+}
+
+/// The transform type with its reserved field cleared, as it is ignored on
+/// receipt.
+fn normalized(transform_type: &TransformType) -> TransformType {
+    match transform_type.clone() {
+        TransformType::Reserved(_, id) => TransformType::Reserved(0, id),
+        TransformType::ENCR(_, encr) => TransformType::ENCR(0, encr),
+        TransformType::PRF(_, prf) => TransformType::PRF(0, prf),
+        TransformType::INTEG(_, integ) => TransformType::INTEG(0, integ),
+        TransformType::KE(_, ke) => TransformType::KE(0, ke),
+        TransformType::SN(_, sn) => TransformType::SN(0, sn),
+        TransformType::ADDKE1(_, ke) => TransformType::ADDKE1(0, ke),
+        TransformType::ADDKE2(_, ke) => TransformType::ADDKE2(0, ke),
+        TransformType::ADDKE3(_, ke) => TransformType::ADDKE3(0, ke),
+        TransformType::ADDKE4(_, ke) => TransformType::ADDKE4(0, ke),
+        TransformType::ADDKE5(_, ke) => TransformType::ADDKE5(0, ke),
+        TransformType::ADDKE6(_, ke) => TransformType::ADDKE6(0, ke),
+        TransformType::ADDKE7(_, ke) => TransformType::ADDKE7(0, ke),
+        TransformType::KWA(_, kwa) => TransformType::KWA(0, kwa),
+        TransformType::GCAUTH(_, gcauth) => TransformType::GCAUTH(0, gcauth),
+        TransformType::Unassigned(t, _, id) => TransformType::Unassigned(t, 0, id),
+        TransformType::Private(t, _, id) => TransformType::Private(t, 0, id),
+    }
+}
+
+/// A proposal written as algorithm keywords joined by dashes, such as
+/// `aes256gcm16-prfsha384-ecp384` or `aes128-sha256-modp2048-esn`.
+///
+/// Each keyword stands for one transform with its Key Length, and several
+/// of the same type are alternatives in order of preference. Additional Key
+/// Exchanges take a `ke1_` to `ke7_` prefix, as in `ke1_mlkem768`. In IKE
+/// proposals without a PRF, each integrity algorithm implies the matching
+/// PRF. Transforms without a keyword are displayed in their `Debug` form.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Description {
+    pub transforms: Vec<(TransformType, Option<u16>)>,
+}
+
+impl Description {
+    /// Expands the description into a proposal numbered 1 without an SPI.
+    /// ESP and AH proposals without an SN transform get `noesn`.
+    pub fn proposal(&self, protocol_id: ProtocolIdentifier) -> Proposal {
+        let mut transforms = self.transforms.clone();
+        let has = |is: fn(&TransformType) -> bool| transforms.iter().any(|(t, _)| is(t));
+        match protocol_id {
+            ProtocolIdentifier::IKE if !has(|t| matches!(t, TransformType::PRF(..))) => {
+                let prfs: Vec<_> = transforms
+                    .iter()
+                    .filter_map(|(t, _)| match t {
+                        TransformType::INTEG(_, integ) => implied_prf(integ),
+                        _ => None,
+                    })
+                    .map(|prf| (TransformType::PRF(0, prf), None))
+                    .collect();
+                transforms.extend(prfs);
+            }
+            ProtocolIdentifier::ESP | ProtocolIdentifier::AH
+                if !has(|t| matches!(t, TransformType::SN(..))) =>
+            {
+                transforms.push((TransformType::SN(0, transform::SN::Sequential_32), None));
+            }
+            _ => {}
+        }
+        Proposal {
+            last_substructure: LastSubstructure::Last,
+            reserved: 0,
+            proposal_length: 0,
+            proposal_num: 1,
+            protocol_id,
+            spi_size: 0,
+            num_transforms: 0,
+            spi: vec![],
+            transforms: transforms
+                .into_iter()
+                .map(|(transform_type, key_length)| Transform {
+                    last_substructure: LastSubstructure::Transform,
+                    reserved_0: 0,
+                    transform_length: 0,
+                    transform_type,
+                    transform_attributes: key_length
+                        .map(Attribute::KeyLength)
+                        .into_iter()
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&Proposal> for Description {
+    fn from(proposal: &Proposal) -> Self {
+        Description {
+            transforms: Policy::from(proposal).transforms,
+        }
+    }
+}
+
+impl FromStr for Description {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let transforms = s
+            .split('-')
+            .map(|word| {
+                let unknown = || Error::UnknownKeyword(word.to_string());
+                let (n, keyword) = match word.split_once('_') {
+                    Some((prefix, keyword)) if prefix.starts_with("ke") => {
+                        let n = prefix[2..].parse().map_err(|_| unknown())?;
+                        (Some(n), keyword)
+                    }
+                    _ => (None, word),
+                };
+                let (_, transform_type, key_length) = KEYWORDS
+                    .iter()
+                    .find(|(k, ..)| *k == keyword)
+                    .ok_or_else(unknown)?;
+                let transform_type = match (n, transform_type) {
+                    (None, t) => t.clone(),
+                    (Some(n), TransformType::KE(_, ke)) => {
+                        additional_key_exchange(n, ke.clone()).ok_or_else(unknown)?
+                    }
+                    _ => return Err(unknown()),
+                };
+                Ok((transform_type, *key_length))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Description { transforms })
+    }
+}
+
+impl fmt::Display for Description {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (transform_type, key_length)) in self.transforms.iter().enumerate() {
+            if i > 0 {
+                write!(f, "-")?;
+            }
+            let (prefix, keyed) = match transform_type.additional_key_exchange() {
+                Some((n, ke)) => (format!("ke{n}_"), TransformType::KE(0, ke.clone())),
+                None => (String::new(), normalized(transform_type)),
+            };
+            match KEYWORDS
+                .iter()
+                .find(|(_, t, k)| *t == keyed && k == key_length)
+            {
+                Some((keyword, ..)) => write!(f, "{prefix}{keyword}")?,
+                None => write!(f, "{transform_type:?}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The PRF an integrity algorithm implies in IKE proposals.
+fn implied_prf(integ: &transform::INTEG) -> Option<transform::PRF> {
+    Some(match integ {
+        transform::INTEG::AUTH_HMAC_MD5_96 => transform::PRF::PRF_HMAC_MD5,
+        transform::INTEG::AUTH_HMAC_SHA1_96 => transform::PRF::PRF_HMAC_SHA1,
+        transform::INTEG::AUTH_AES_XCBC_96 => transform::PRF::PRF_AES128_XCBC,
+        transform::INTEG::AUTH_AES_CMAC_96 => transform::PRF::PRF_AES128_CMAC,
+        transform::INTEG::AUTH_HMAC_SHA2_256_128 => transform::PRF::PRF_HMAC_SHA2_256,
+        transform::INTEG::AUTH_HMAC_SHA2_384_192 => transform::PRF::PRF_HMAC_SHA2_384,
+        transform::INTEG::AUTH_HMAC_SHA2_512_256 => transform::PRF::PRF_HMAC_SHA2_512,
+        _ => return None,
+    })
+}
+
+/// The Additional Key Exchange n transform for the method.
+fn additional_key_exchange(n: u8, ke: transform::KE) -> Option<TransformType> {
+    Some(match n {
+        1 => TransformType::ADDKE1(0, ke),
+        2 => TransformType::ADDKE2(0, ke),
+        3 => TransformType::ADDKE3(0, ke),
+        4 => TransformType::ADDKE4(0, ke),
+        5 => TransformType::ADDKE5(0, ke),
+        6 => TransformType::ADDKE6(0, ke),
+        7 => TransformType::ADDKE7(0, ke),
+        _ => return None,
+    })
+}
+
+/// Algorithm keywords with their transform and Key Length. Where several
+/// keywords name the same transform the first is written.
+const KEYWORDS: &[(&str, TransformType, Option<u16>)] = {
+    use TransformType::{ENCR, INTEG, KE, PRF, SN};
+    use transform::{ENCR::*, INTEG::*, KE::*, PRF::*, SN::*};
+    &[
+        ("null", ENCR(0, ENCR_NULL), None),
+        ("3des", ENCR(0, ENCR_3DES), None),
+        ("aes128", ENCR(0, ENCR_AES_CBC), Some(128)),
+        ("aes192", ENCR(0, ENCR_AES_CBC), Some(192)),
+        ("aes256", ENCR(0, ENCR_AES_CBC), Some(256)),
+        ("aes128ctr", ENCR(0, ENCR_AES_CTR), Some(128)),
+        ("aes192ctr", ENCR(0, ENCR_AES_CTR), Some(192)),
+        ("aes256ctr", ENCR(0, ENCR_AES_CTR), Some(256)),
+        ("aes128ccm8", ENCR(0, ENCR_AES_CCM_8), Some(128)),
+        ("aes192ccm8", ENCR(0, ENCR_AES_CCM_8), Some(192)),
+        ("aes256ccm8", ENCR(0, ENCR_AES_CCM_8), Some(256)),
+        ("aes128ccm12", ENCR(0, ENCR_AES_CCM_12), Some(128)),
+        ("aes192ccm12", ENCR(0, ENCR_AES_CCM_12), Some(192)),
+        ("aes256ccm12", ENCR(0, ENCR_AES_CCM_12), Some(256)),
+        ("aes128ccm16", ENCR(0, ENCR_AES_CCM_16), Some(128)),
+        ("aes192ccm16", ENCR(0, ENCR_AES_CCM_16), Some(192)),
+        ("aes256ccm16", ENCR(0, ENCR_AES_CCM_16), Some(256)),
+        ("aes128gcm8", ENCR(0, ENCR_AES_GCM_8), Some(128)),
+        ("aes192gcm8", ENCR(0, ENCR_AES_GCM_8), Some(192)),
+        ("aes256gcm8", ENCR(0, ENCR_AES_GCM_8), Some(256)),
+        ("aes128gcm12", ENCR(0, ENCR_AES_GCM_12), Some(128)),
+        ("aes192gcm12", ENCR(0, ENCR_AES_GCM_12), Some(192)),
+        ("aes256gcm12", ENCR(0, ENCR_AES_GCM_12), Some(256)),
+        ("aes128gcm16", ENCR(0, ENCR_AES_GCM_16), Some(128)),
+        ("aes192gcm16", ENCR(0, ENCR_AES_GCM_16), Some(192)),
+        ("aes256gcm16", ENCR(0, ENCR_AES_GCM_16), Some(256)),
+        ("aes128gcm", ENCR(0, ENCR_AES_GCM_16), Some(128)),
+        ("aes192gcm", ENCR(0, ENCR_AES_GCM_16), Some(192)),
+        ("aes256gcm", ENCR(0, ENCR_AES_GCM_16), Some(256)),
+        ("chacha20poly1305", ENCR(0, ENCR_CHACHA20_POLY1305), None),
+        ("prfmd5", PRF(0, PRF_HMAC_MD5), None),
+        ("prfsha1", PRF(0, PRF_HMAC_SHA1), None),
+        ("prfaesxcbc", PRF(0, PRF_AES128_XCBC), None),
+        ("prfaescmac", PRF(0, PRF_AES128_CMAC), None),
+        ("prfsha256", PRF(0, PRF_HMAC_SHA2_256), None),
+        ("prfsha384", PRF(0, PRF_HMAC_SHA2_384), None),
+        ("prfsha512", PRF(0, PRF_HMAC_SHA2_512), None),
+        ("md5", INTEG(0, AUTH_HMAC_MD5_96), None),
+        ("sha1", INTEG(0, AUTH_HMAC_SHA1_96), None),
+        ("aesxcbc", INTEG(0, AUTH_AES_XCBC_96), None),
+        ("aescmac", INTEG(0, AUTH_AES_CMAC_96), None),
+        ("sha256", INTEG(0, AUTH_HMAC_SHA2_256_128), None),
+        ("sha384", INTEG(0, AUTH_HMAC_SHA2_384_192), None),
+        ("sha512", INTEG(0, AUTH_HMAC_SHA2_512_256), None),
+        ("sha", INTEG(0, AUTH_HMAC_SHA1_96), None),
+        ("sha2_256", INTEG(0, AUTH_HMAC_SHA2_256_128), None),
+        ("sha2_384", INTEG(0, AUTH_HMAC_SHA2_384_192), None),
+        ("sha2_512", INTEG(0, AUTH_HMAC_SHA2_512_256), None),
+        ("none", KE(0, transform::KE::NONE), None),
+        ("modp768", KE(0, MODP_768), None),
+        ("modp1024", KE(0, MODP_1024), None),
+        ("modp1536", KE(0, MODP_1536), None),
+        ("modp2048", KE(0, MODP_2048), None),
+        ("modp3072", KE(0, MODP_3072), None),
+        ("modp4096", KE(0, MODP_4096), None),
+        ("modp6144", KE(0, MODP_6144), None),
+        ("modp8192", KE(0, MODP_8192), None),
+        ("modp1024s160", KE(0, MODP_1024_PRIME_160), None),
+        ("modp2048s224", KE(0, MODP_2048_PRIME_224), None),
+        ("modp2048s256", KE(0, MODP_2048_PRIME_256), None),
+        ("ecp192", KE(0, ECP_192), None),
+        ("ecp224", KE(0, ECP_224), None),
+        ("ecp256", KE(0, ECP_256), None),
+        ("ecp384", KE(0, ECP_384), None),
+        ("ecp521", KE(0, ECP_512), None),
+        ("ecp224bp", KE(0, brainpoolP224r1), None),
+        ("ecp256bp", KE(0, brainpoolP256r1), None),
+        ("ecp384bp", KE(0, brainpoolP384r1), None),
+        ("ecp512bp", KE(0, brainpoolP512r1), None),
+        ("x25519", KE(0, Curve25519), None),
+        ("x448", KE(0, Curve448), None),
+        ("curve25519", KE(0, Curve25519), None),
+        ("curve448", KE(0, Curve448), None),
+        ("mlkem512", KE(0, ML_KEM_512), None),
+        ("mlkem768", KE(0, ML_KEM_768), None),
+        ("mlkem1024", KE(0, ML_KEM_1024), None),
+        ("noesn", SN(0, Sequential_32), None),
+        ("esn", SN(0, Sequential_64), None),
+    ]
+};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{proposal, transform};
+    use crate::transform::{ENCR, INTEG, KE, PRF, SN};
+
+    fn ike_policy() -> Policy {
+        Policy {
+            protocol_id: ProtocolIdentifier::IKE,
+            transforms: vec![
+                (TransformType::ENCR(0, ENCR::ENCR_AES_GCM_16), Some(256)),
+                (TransformType::ENCR(0, ENCR::ENCR_AES_CBC), Some(128)),
+                (TransformType::PRF(0, PRF::PRF_HMAC_SHA2_256), None),
+                (TransformType::INTEG(0, INTEG::AUTH_HMAC_SHA2_256_128), None),
+                (TransformType::KE(0, KE::Curve25519), None),
+                (TransformType::KE(0, KE::MODP_2048), None),
+                (TransformType::ADDKE1(0, KE::ML_KEM_768), None),
+                (TransformType::ADDKE1(0, KE::NONE), None),
+            ],
+        }
+    }
+
+    fn esp_policy() -> Policy {
+        Policy {
+            protocol_id: ProtocolIdentifier::ESP,
+            transforms: vec![
+                (TransformType::ENCR(0, ENCR::ENCR_AES_CBC), Some(256)),
+                (TransformType::INTEG(0, INTEG::AUTH_HMAC_SHA2_256_128), None),
+                (TransformType::SN(0, SN::Sequential_64), None),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_peer_preference() {
+        let offered = vec![
+            proposal(
+                ProtocolIdentifier::IKE,
+                vec![
+                    transform(TransformType::ENCR(0, ENCR::ENCR_AES_GCM_16), Some(128)),
+                    transform(TransformType::PRF(0, PRF::PRF_HMAC_SHA2_256), None),
+                    transform(TransformType::KE(0, KE::Curve25519), None),
+                ],
+            ),
+            Proposal {
+                proposal_num: 2,
+                spi_size: 4,
+                spi: vec![1, 2, 3, 4],
+                ..proposal(
+                    ProtocolIdentifier::IKE,
+                    vec![
+                        transform(TransformType::ENCR(0, ENCR::ENCR_AES_GCM_16), Some(128)),
+                        transform(TransformType::ENCR(7, ENCR::ENCR_AES_GCM_16), Some(256)),
+                        transform(TransformType::PRF(0, PRF::PRF_HMAC_SHA2_512), None),
+                        transform(TransformType::PRF(0, PRF::PRF_HMAC_SHA2_256), None),
+                        transform(TransformType::KE(0, KE::MODP_2048), None),
+                        transform(TransformType::KE(0, KE::Curve25519), None),
+                        transform(TransformType::ADDKE1(0, KE::NONE), None),
+                        transform(TransformType::ADDKE1(0, KE::ML_KEM_768), None),
+                    ],
+                )
+            },
+        ];
+        let chosen = negotiate(&offered, &[ike_policy()]).unwrap();
+        assert_eq!(chosen.proposal_num, 2);
+        assert_eq!(chosen.spi, [1, 2, 3, 4]);
+        assert_eq!(
+            chosen.transforms,
+            [
+                offered[1].transforms[1].clone(),
+                offered[1].transforms[3].clone(),
+                offered[1].transforms[4].clone(),
+                offered[1].transforms[6].clone(),
+            ]
+        );
+
+        // the first policy that accepts a proposal decides
+        let strict = Policy {
+            transforms: ike_policy().transforms[..7].to_vec(),
+            ..ike_policy()
+        };
+        let chosen = negotiate(&offered[1..], &[strict, ike_policy()]).unwrap();
+        assert_eq!(chosen.transforms[3], offered[1].transforms[7]);
+    }
+
+    #[test]
+    fn test_integrity() {
+        let cbc = |integ: Option<INTEG>| {
+            let mut transforms = vec![
+                transform(TransformType::ENCR(0, ENCR::ENCR_AES_CBC), Some(128)),
+                transform(TransformType::PRF(0, PRF::PRF_HMAC_SHA2_256), None),
+                transform(TransformType::KE(0, KE::Curve25519), None),
+            ];
+            transforms.extend(integ.map(|i| transform(TransformType::INTEG(0, i), None)));
+            proposal(ProtocolIdentifier::IKE, transforms)
+        };
+        assert!(negotiate(&[cbc(Some(INTEG::AUTH_HMAC_SHA2_256_128))], &[ike_policy()]).is_ok());
+        for integ in [None, Some(INTEG::NONE), Some(INTEG::AUTH_HMAC_SHA1_96)] {
+            assert!(negotiate(&[cbc(integ)], &[ike_policy()]).is_err());
+        }
+
+        let gcm = |integ: Option<INTEG>| {
+            let mut transforms = vec![
+                transform(TransformType::ENCR(0, ENCR::ENCR_AES_GCM_16), Some(256)),
+                transform(TransformType::PRF(0, PRF::PRF_HMAC_SHA2_256), None),
+                transform(TransformType::KE(0, KE::Curve25519), None),
+            ];
+            transforms.extend(integ.map(|i| transform(TransformType::INTEG(0, i), None)));
+            proposal(ProtocolIdentifier::IKE, transforms)
+        };
+        for integ in [None, Some(INTEG::NONE)] {
+            assert!(negotiate(&[gcm(integ)], &[ike_policy()]).is_ok());
+        }
+        let error = negotiate(&[gcm(Some(INTEG::AUTH_HMAC_SHA2_256_128))], &[ike_policy()]);
+        assert!(matches!(
+            error,
+            Err(Error::Notify(NotifyType::NO_PROPOSAL_CHOSEN))
+        ));
+    }
+
+    #[test]
+    fn test_optional_types() {
+        let esp = |transforms: Vec<Transform>| {
+            let mut all = vec![
+                transform(TransformType::ENCR(0, ENCR::ENCR_AES_CBC), Some(256)),
+                transform(TransformType::INTEG(0, INTEG::AUTH_HMAC_SHA2_256_128), None),
+            ];
+            all.extend(transforms);
+            negotiate(&[proposal(ProtocolIdentifier::ESP, all)], &[esp_policy()])
+        };
+        // ESN is required by the policy, a KE is only accepted as NONE
+        let chosen = esp(vec![
+            transform(TransformType::SN(0, SN::Sequential_32), None),
+            transform(TransformType::SN(0, SN::Sequential_64), None),
+            transform(TransformType::KE(0, KE::NONE), None),
+        ])
+        .unwrap();
+        assert_eq!(
+            chosen.transforms[2].transform_type,
+            TransformType::SN(0, SN::Sequential_64)
+        );
+        assert_eq!(chosen.transforms.len(), 4);
+        assert!(
+            esp(vec![transform(
+                TransformType::SN(0, SN::Sequential_32),
+                None
+            )])
+            .is_err()
+        );
+        assert!(esp(vec![]).is_err());
+        assert!(
+            esp(vec![
+                transform(TransformType::SN(0, SN::Sequential_64), None),
+                transform(TransformType::KE(0, KE::Curve25519), None),
+            ])
+            .is_err()
+        );
+
+        // the ADDKE1 NONE in the policy makes it optional
+        let ike = |addke: Option<KE>| {
+            let mut transforms = vec![
+                transform(TransformType::ENCR(0, ENCR::ENCR_AES_GCM_16), Some(256)),
+                transform(TransformType::PRF(0, PRF::PRF_HMAC_SHA2_256), None),
+                transform(TransformType::KE(0, KE::Curve25519), None),
+            ];
+            transforms.extend(addke.map(|ke| transform(TransformType::ADDKE1(0, ke), None)));
+            negotiate(
+                &[proposal(ProtocolIdentifier::IKE, transforms)],
+                &[ike_policy()],
+            )
+        };
+        assert!(ike(None).is_ok());
+        assert!(ike(Some(KE::NONE)).is_ok());
+        assert!(ike(Some(KE::ML_KEM_768)).is_ok());
+        assert!(ike(Some(KE::ML_KEM_1024)).is_err());
+    }
+
+    #[test]
+    fn test_no_proposal_chosen() {
+        let ike = |transforms: Vec<Transform>| {
+            negotiate(
+                &[proposal(ProtocolIdentifier::IKE, transforms)],
+                &[ike_policy()],
+            )
+        };
+        let gcm = |key_length| transform(TransformType::ENCR(0, ENCR::ENCR_AES_GCM_16), key_length);
+        let prf = || transform(TransformType::PRF(0, PRF::PRF_HMAC_SHA2_256), None);
+        let ke = |ke| transform(TransformType::KE(0, ke), None);
+
+        assert!(ike(vec![gcm(Some(256)), prf(), ke(KE::Curve25519)]).is_ok());
+        // Key Length must match
+        assert!(ike(vec![gcm(Some(128)), prf(), ke(KE::Curve25519)]).is_err());
+        assert!(ike(vec![gcm(None), prf(), ke(KE::Curve25519)]).is_err());
+        // so must every offered transform type
+        assert!(ike(vec![gcm(Some(256)), prf(), ke(KE::ECP_256)]).is_err());
+        // mandatory types for IKE
+        assert!(ike(vec![gcm(Some(256)), prf()]).is_err());
+        assert!(ike(vec![gcm(Some(256)), prf(), ke(KE::NONE)]).is_err());
+        assert!(ike(vec![gcm(Some(256)), ke(KE::Curve25519)]).is_err());
+        // unknown attributes
+        let mut unknown = gcm(Some(256));
+        unknown.transform_attributes.push(Attribute::TV(0x8001, 1));
+        assert!(ike(vec![unknown, prf(), ke(KE::Curve25519)]).is_err());
+        // types that do not belong to IKE
+        let sn = transform(TransformType::SN(0, SN::Sequential_32), None);
+        assert!(ike(vec![gcm(Some(256)), prf(), ke(KE::Curve25519), sn]).is_err());
+        // or the wrong protocol
+        let esp = proposal(ProtocolIdentifier::ESP, vec![gcm(Some(256))]);
+        assert!(matches!(
+            negotiate(&[esp], &[ike_policy()]),
+            Err(Error::Notify(NotifyType::NO_PROPOSAL_CHOSEN))
+        ));
+    }
+
+    #[test]
+    fn test_description() {
+        let description: Description = "aes256gcm16-prfsha384-ecp384".parse().unwrap();
+        assert_eq!(
+            description.transforms,
+            [
+                (TransformType::ENCR(0, ENCR::ENCR_AES_GCM_16), Some(256)),
+                (TransformType::PRF(0, PRF::PRF_HMAC_SHA2_384), None),
+                (TransformType::KE(0, KE::ECP_384), None),
+            ]
+        );
+        for s in [
+            "aes256gcm16-prfsha384-ecp384",
+            "aes128-sha256-modp2048-esn",
+            "aes128-aes256-sha256-sha1-x25519-ecp256-ke1_mlkem768-ke1_none",
+            "chacha20poly1305-prfsha256-ecp521-ke2_mlkem1024",
+        ] {
+            assert_eq!(s.parse::<Description>().unwrap().to_string(), s);
+        }
+        // aliases are written with the first keyword
+        let alias: Description = "aes128gcm-sha2_256-curve25519".parse().unwrap();
+        assert_eq!(alias.to_string(), "aes128gcm16-sha256-x25519");
+
+        for (s, keyword) in [
+            ("", ""),
+            ("aes", "aes"),
+            ("aes128--sha256", ""),
+            ("ke8_mlkem768", "ke8_mlkem768"),
+            ("kex_mlkem768", "kex_mlkem768"),
+            ("x25519-ke1_aes128", "ke1_aes128"),
+            ("sha2-256", "sha2"),
+        ] {
+            assert_eq!(
+                s.parse::<Description>(),
+                Err(Error::UnknownKeyword(keyword.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_description_proposal() {
+        let description: Description = "aes128-sha256-sha1-modp2048".parse().unwrap();
+        let ike = description.proposal(ProtocolIdentifier::IKE);
+        assert_eq!(ike.proposal_num, 1);
+        assert_eq!(
+            ike.transforms[1],
+            transform(TransformType::INTEG(0, INTEG::AUTH_HMAC_SHA2_256_128), None)
+        );
+        assert_eq!(ike.encr(), Some((&ENCR::ENCR_AES_CBC, Some(128))));
+        assert_eq!(
+            Description::from(&ike).to_string(),
+            "aes128-sha256-sha1-modp2048-prfsha256-prfsha1"
+        );
+        let esp = description.proposal(ProtocolIdentifier::ESP);
+        assert_eq!(
+            Description::from(&esp).to_string(),
+            "aes128-sha256-sha1-modp2048-noesn"
+        );
+
+        // an explicit PRF or SN is kept as it is
+        let description: Description = "aes256gcm16-prfsha384-ecp384-esn".parse().unwrap();
+        assert_eq!(
+            Description::from(&description.proposal(ProtocolIdentifier::IKE)),
+            description
+        );
+        assert_eq!(
+            Description::from(&description.proposal(ProtocolIdentifier::ESP)),
+            description
+        );
+
+        let mut unknown = description.proposal(ProtocolIdentifier::IKE);
+        unknown.transforms[0].transform_type = TransformType::ENCR(0, ENCR::ENCR_CAMELLIA_CBC);
+        assert_eq!(
+            Description::from(&unknown).to_string(),
+            "ENCR(0, ENCR_CAMELLIA_CBC)-prfsha384-ecp384-esn"
+        );
+
+        // the same vocabulary for both ends of a negotiation
+        let offered: Description = "aes256-aes128-sha256-x25519".parse().unwrap();
+        let accepted: Description = "aes128-sha256-x25519-modp2048".parse().unwrap();
+        let chosen = negotiate(
+            &[offered.proposal(ProtocolIdentifier::IKE)],
+            &[Policy::from(&accepted.proposal(ProtocolIdentifier::IKE))],
+        )
+        .unwrap();
+        assert_eq!(
+            Description::from(&chosen).to_string(),
+            "aes128-sha256-x25519-prfsha256"
+        );
+    }
+}
@@ -0,0 +1,670 @@
+use deku::prelude::*;
+use ecdsa::signature::hazmat::{PrehashSigner, PrehashVerifier};
+use ed25519_dalek::{Signer, Verifier};
+use rand_core::OsRng;
+use rsa::pkcs1::{RsaPssParams, TrailerField};
+use rsa::pkcs8::spki::SubjectPublicKeyInfoRef;
+use rsa::pkcs8::{ObjectIdentifier, PrivateKeyInfo};
+use rsa::{Pkcs1v15Sign, Pss, RsaPrivateKey, RsaPublicKey};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use x509_cert::der::asn1::{Any, AnyRef, OctetStringRef};
+use x509_cert::der::oid::db::rfc5912::{
+    ECDSA_WITH_SHA_256, ECDSA_WITH_SHA_384, ECDSA_WITH_SHA_512, ID_EC_PUBLIC_KEY, ID_MGF_1,
+    ID_RSASSA_PSS, ID_SHA_256, ID_SHA_384, ID_SHA_512, RSA_ENCRYPTION, SECP_256_R_1, SECP_384_R_1,
+    SECP_521_R_1, SHA_256_WITH_RSA_ENCRYPTION, SHA_384_WITH_RSA_ENCRYPTION,
+    SHA_512_WITH_RSA_ENCRYPTION,
+};
+use x509_cert::der::oid::db::rfc8410::{ID_ED_448, ID_ED_25519};
+use x509_cert::der::{Decode, Encode};
+use x509_cert::spki::{AlgorithmIdentifier, AlgorithmIdentifierOwned, AlgorithmIdentifierRef};
+
+use crate::consts::*;
+use crate::error::Error;
+use crate::types::NotifyPayload;
+
+/// Signature algorithm named by the AlgorithmIdentifier of a Digital
+/// Signature (RFC 7427 §3).
+#[derive(Clone, Debug, PartialEq)]
+pub enum SignatureAlgorithm {
+    /// RSASSA-PKCS1-v1_5
+    RsaPkcs1(HashAlgorithm),
+    /// RSASSA-PSS with MGF1 over the same hash
+    RsaPss {
+        hash: HashAlgorithm,
+        salt_length: usize,
+    },
+    Ecdsa(HashAlgorithm),
+    Ed25519,
+    Ed448,
+}
+
+impl SignatureAlgorithm {
+    /// RSASSA-PSS with a salt as long as the hash output.
+    pub fn rsa_pss(hash: HashAlgorithm) -> Result<Self, Error> {
+        let salt_length = digest(&hash, b"")?.len();
+        Ok(SignatureAlgorithm::RsaPss { hash, salt_length })
+    }
+
+    /// The hash algorithm to advertise in SIGNATURE_HASH_ALGORITHMS.
+    pub fn hash(&self) -> HashAlgorithm {
+        match self {
+            SignatureAlgorithm::RsaPkcs1(hash)
+            | SignatureAlgorithm::RsaPss { hash, .. }
+            | SignatureAlgorithm::Ecdsa(hash) => hash.clone(),
+            SignatureAlgorithm::Ed25519 | SignatureAlgorithm::Ed448 => HashAlgorithm::Identity,
+        }
+    }
+
+    /// Parses a DER AlgorithmIdentifier.
+    pub fn from_der(data: &[u8]) -> Result<Self, Error> {
+        let invalid = || Error::InvalidAlgorithmIdentifier;
+        let identifier = AlgorithmIdentifierRef::from_der(data).map_err(|_| invalid())?;
+        let algorithm = match identifier.oid {
+            SHA_256_WITH_RSA_ENCRYPTION => SignatureAlgorithm::RsaPkcs1(HashAlgorithm::SHA2_256),
+            SHA_384_WITH_RSA_ENCRYPTION => SignatureAlgorithm::RsaPkcs1(HashAlgorithm::SHA2_384),
+            SHA_512_WITH_RSA_ENCRYPTION => SignatureAlgorithm::RsaPkcs1(HashAlgorithm::SHA2_512),
+            ID_RSASSA_PSS => return pss_from_parameters(identifier.parameters).ok_or_else(invalid),
+            ECDSA_WITH_SHA_256 => SignatureAlgorithm::Ecdsa(HashAlgorithm::SHA2_256),
+            ECDSA_WITH_SHA_384 => SignatureAlgorithm::Ecdsa(HashAlgorithm::SHA2_384),
+            ECDSA_WITH_SHA_512 => SignatureAlgorithm::Ecdsa(HashAlgorithm::SHA2_512),
+            ID_ED_25519 => SignatureAlgorithm::Ed25519,
+            ID_ED_448 => SignatureAlgorithm::Ed448,
+            _ => return Err(invalid()),
+        };
+        // PKCS #1 identifiers carry NULL parameters, the others none
+        match (&algorithm, identifier.parameters) {
+            (_, None) => Ok(algorithm),
+            (SignatureAlgorithm::RsaPkcs1(_), Some(parameters)) if parameters.is_null() => {
+                Ok(algorithm)
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Encodes the DER AlgorithmIdentifier.
+    pub fn to_der(&self) -> Result<Vec<u8>, Error> {
+        let (oid, parameters) = match self {
+            SignatureAlgorithm::RsaPkcs1(hash) => {
+                let oid = match hash {
+                    HashAlgorithm::SHA2_256 => SHA_256_WITH_RSA_ENCRYPTION,
+                    HashAlgorithm::SHA2_384 => SHA_384_WITH_RSA_ENCRYPTION,
+                    HashAlgorithm::SHA2_512 => SHA_512_WITH_RSA_ENCRYPTION,
+                    _ => return Err(Error::InvalidAlgorithmIdentifier),
+                };
+                (oid, Some(Any::from(AnyRef::NULL)))
+            }
+            SignatureAlgorithm::RsaPss { hash, salt_length } => {
+                let hash = AlgorithmIdentifierRef {
+                    oid: hash_oid(hash)?,
+                    parameters: Some(AnyRef::NULL),
+                };
+                let parameters = RsaPssParams {
+                    hash,
+                    mask_gen: AlgorithmIdentifier {
+                        oid: ID_MGF_1,
+                        parameters: Some(hash),
+                    },
+                    salt_len: u8::try_from(*salt_length)
+                        .map_err(|_| Error::InvalidAlgorithmIdentifier)?,
+                    trailer_field: TrailerField::BC,
+                };
+                (
+                    ID_RSASSA_PSS,
+                    Some(
+                        Any::encode_from(&parameters)
+                            .map_err(|_| Error::InvalidAlgorithmIdentifier)?,
+                    ),
+                )
+            }
+            SignatureAlgorithm::Ecdsa(hash) => {
+                let oid = match hash {
+                    HashAlgorithm::SHA2_256 => ECDSA_WITH_SHA_256,
+                    HashAlgorithm::SHA2_384 => ECDSA_WITH_SHA_384,
+                    HashAlgorithm::SHA2_512 => ECDSA_WITH_SHA_512,
+                    _ => return Err(Error::InvalidAlgorithmIdentifier),
+                };
+                (oid, None)
+            }
+            SignatureAlgorithm::Ed25519 => (ID_ED_25519, None),
+            SignatureAlgorithm::Ed448 => (ID_ED_448, None),
+        };
+        AlgorithmIdentifierOwned { oid, parameters }
+            .to_der()
+            .map_err(|_| Error::InvalidAlgorithmIdentifier)
+    }
+}
+
+/// RSASSA-PSS-params, of which only SHA-2 hashes with a matching MGF1 hash,
+/// a salt length below 256 and the default trailer field are supported.
+fn pss_from_parameters(parameters: Option<AnyRef<'_>>) -> Option<SignatureAlgorithm> {
+    let parameters: RsaPssParams = parameters?.decode_as().ok()?;
+    // the hash algorithm defaults to SHA-1, which is not supported
+    let hash = hash_from_identifier(&parameters.hash)?;
+    let mask_gen = parameters.mask_gen;
+    if mask_gen.oid != ID_MGF_1 || hash_from_identifier(&mask_gen.parameters?)? != hash {
+        return None;
+    }
+    Some(SignatureAlgorithm::RsaPss {
+        hash,
+        salt_length: parameters.salt_len.into(),
+    })
+}
+
+fn hash_from_identifier(identifier: &AlgorithmIdentifierRef<'_>) -> Option<HashAlgorithm> {
+    if identifier.parameters.is_some_and(|p| !p.is_null()) {
+        return None;
+    }
+    match identifier.oid {
+        ID_SHA_256 => Some(HashAlgorithm::SHA2_256),
+        ID_SHA_384 => Some(HashAlgorithm::SHA2_384),
+        ID_SHA_512 => Some(HashAlgorithm::SHA2_512),
+        _ => None,
+    }
+}
+
+fn hash_oid(hash: &HashAlgorithm) -> Result<ObjectIdentifier, Error> {
+    Ok(match hash {
+        HashAlgorithm::SHA2_256 => ID_SHA_256,
+        HashAlgorithm::SHA2_384 => ID_SHA_384,
+        HashAlgorithm::SHA2_512 => ID_SHA_512,
+        _ => return Err(Error::InvalidAlgorithmIdentifier),
+    })
+}
+
+/// Hashes `data` with one of the SHA-2 hash algorithms.
+pub fn digest(hash: &HashAlgorithm, data: &[u8]) -> Result<Vec<u8>, Error> {
+    Ok(match hash {
+        HashAlgorithm::SHA2_256 => Sha256::digest(data).to_vec(),
+        HashAlgorithm::SHA2_384 => Sha384::digest(data).to_vec(),
+        HashAlgorithm::SHA2_512 => Sha512::digest(data).to_vec(),
+        _ => return Err(Error::InvalidAlgorithmIdentifier),
+    })
+}
+
+/// A private key for Digital Signature authentication, decoded from PKCS #8
+/// through `rsa::pkcs8::DecodePrivateKey`.
+#[derive(Clone, Debug)]
+pub enum SigningKey {
+    Rsa(RsaPrivateKey),
+    P256(p256::ecdsa::SigningKey),
+    P384(p384::ecdsa::SigningKey),
+    P521(ecdsa::SigningKey<p521::NistP521>),
+    Ed25519(ed25519_dalek::SigningKey),
+    Ed448(ed448_goldilocks_plus::SigningKey),
+}
+
+/// A public key for Digital Signature authentication, decoded from a
+/// SubjectPublicKeyInfo through `rsa::pkcs8::DecodePublicKey`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum VerifyingKey {
+    Rsa(RsaPublicKey),
+    P256(p256::ecdsa::VerifyingKey),
+    P384(p384::ecdsa::VerifyingKey),
+    P521(ecdsa::VerifyingKey<p521::NistP521>),
+    Ed25519(ed25519_dalek::VerifyingKey),
+    Ed448(ed448_goldilocks_plus::VerifyingKey),
+}
+
+impl SigningKey {
+    pub fn verifying_key(&self) -> VerifyingKey {
+        match self {
+            SigningKey::Rsa(key) => VerifyingKey::Rsa(key.to_public_key()),
+            SigningKey::P256(key) => VerifyingKey::P256(*key.verifying_key()),
+            SigningKey::P384(key) => VerifyingKey::P384(*key.verifying_key()),
+            SigningKey::P521(key) => VerifyingKey::P521(*key.verifying_key()),
+            SigningKey::Ed25519(key) => VerifyingKey::Ed25519(key.verifying_key()),
+            SigningKey::Ed448(key) => VerifyingKey::Ed448(key.verifying_key()),
+        }
+    }
+
+    /// Signs `data`, failing with `KeyMismatch` if the key cannot be used
+    /// with `algorithm`. ECDSA signatures are DER encoded.
+    pub fn sign(&self, algorithm: &SignatureAlgorithm, data: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(match (self, algorithm) {
+            (SigningKey::Rsa(key), SignatureAlgorithm::RsaPkcs1(hash)) => key
+                .sign(pkcs1v15(hash)?, &digest(hash, data)?)
+                .map_err(key_mismatch)?,
+            (SigningKey::Rsa(key), SignatureAlgorithm::RsaPss { hash, salt_length }) => key
+                .sign_with_rng(&mut OsRng, pss(hash, *salt_length)?, &digest(hash, data)?)
+                .map_err(key_mismatch)?,
+            (SigningKey::P256(key), SignatureAlgorithm::Ecdsa(hash)) => {
+                let signature: p256::ecdsa::Signature = key
+                    .sign_prehash(&digest(hash, data)?)
+                    .map_err(key_mismatch)?;
+                signature.to_der().as_bytes().to_vec()
+            }
+            (SigningKey::P384(key), SignatureAlgorithm::Ecdsa(hash)) => {
+                let signature: p384::ecdsa::Signature = key
+                    .sign_prehash(&digest(hash, data)?)
+                    .map_err(key_mismatch)?;
+                signature.to_der().as_bytes().to_vec()
+            }
+            (SigningKey::P521(key), SignatureAlgorithm::Ecdsa(hash)) => {
+                let key = p521::ecdsa::SigningKey::from(key.clone());
+                let signature: p521::ecdsa::Signature = key
+                    .sign_prehash(&digest(hash, data)?)
+                    .map_err(key_mismatch)?;
+                signature.to_der().as_bytes().to_vec()
+            }
+            (SigningKey::Ed25519(key), SignatureAlgorithm::Ed25519) => {
+                key.sign(data).to_bytes().to_vec()
+            }
+            (SigningKey::Ed448(key), SignatureAlgorithm::Ed448) => {
+                key.sign(data).to_bytes().to_vec()
+            }
+            _ => return Err(Error::KeyMismatch),
+        })
+    }
+}
+
+impl VerifyingKey {
+    /// Verifies a signature over `data`, failing with AUTHENTICATION_FAILED
+    /// if it does not verify or the key cannot be used with `algorithm`.
+    pub fn verify(
+        &self,
+        algorithm: &SignatureAlgorithm,
+        data: &[u8],
+        signature: &[u8],
+    ) -> Result<(), Error> {
+        match (self, algorithm) {
+            (VerifyingKey::Rsa(key), SignatureAlgorithm::RsaPkcs1(hash)) => key
+                .verify(pkcs1v15(hash)?, &digest(hash, data)?, signature)
+                .map_err(authentication_failed),
+            (VerifyingKey::Rsa(key), SignatureAlgorithm::RsaPss { hash, salt_length }) => key
+                .verify(pss(hash, *salt_length)?, &digest(hash, data)?, signature)
+                .map_err(authentication_failed),
+            (VerifyingKey::P256(key), SignatureAlgorithm::Ecdsa(hash)) => {
+                let signature =
+                    p256::ecdsa::Signature::from_der(signature).map_err(authentication_failed)?;
+                key.verify_prehash(&digest(hash, data)?, &signature)
+                    .map_err(authentication_failed)
+            }
+            (VerifyingKey::P384(key), SignatureAlgorithm::Ecdsa(hash)) => {
+                let signature =
+                    p384::ecdsa::Signature::from_der(signature).map_err(authentication_failed)?;
+                key.verify_prehash(&digest(hash, data)?, &signature)
+                    .map_err(authentication_failed)
+            }
+            (VerifyingKey::P521(key), SignatureAlgorithm::Ecdsa(hash)) => {
+                let signature =
+                    p521::ecdsa::Signature::from_der(signature).map_err(authentication_failed)?;
+                key.verify_prehash(&digest(hash, data)?, &signature)
+                    .map_err(authentication_failed)
+            }
+            (VerifyingKey::Ed25519(key), SignatureAlgorithm::Ed25519) => {
+                let signature = ed25519_dalek::Signature::from_slice(signature)
+                    .map_err(authentication_failed)?;
+                key.verify(data, &signature).map_err(authentication_failed)
+            }
+            (VerifyingKey::Ed448(key), SignatureAlgorithm::Ed448) => {
+                let signature = ed448_goldilocks_plus::Signature::try_from(signature)
+                    .map_err(authentication_failed)?;
+                key.verify(data, &signature).map_err(authentication_failed)
+            }
+            _ => Err(Error::Notify(NotifyType::AUTHENTICATION_FAILED)),
+        }
+    }
+}
+
+fn key_mismatch<E>(_: E) -> Error {
+    Error::KeyMismatch
+}
+
+fn authentication_failed<E>(_: E) -> Error {
+    Error::Notify(NotifyType::AUTHENTICATION_FAILED)
+}
+
+fn pkcs1v15(hash: &HashAlgorithm) -> Result<Pkcs1v15Sign, Error> {
+    Ok(match hash {
+        HashAlgorithm::SHA2_256 => Pkcs1v15Sign::new::<Sha256>(),
+        HashAlgorithm::SHA2_384 => Pkcs1v15Sign::new::<Sha384>(),
+        HashAlgorithm::SHA2_512 => Pkcs1v15Sign::new::<Sha512>(),
+        _ => return Err(Error::InvalidAlgorithmIdentifier),
+    })
+}
+
+fn pss(hash: &HashAlgorithm, salt_length: usize) -> Result<Pss, Error> {
+    Ok(match hash {
+        HashAlgorithm::SHA2_256 => Pss::new_with_salt::<Sha256>(salt_length),
+        HashAlgorithm::SHA2_384 => Pss::new_with_salt::<Sha384>(salt_length),
+        HashAlgorithm::SHA2_512 => Pss::new_with_salt::<Sha512>(salt_length),
+        _ => return Err(Error::InvalidAlgorithmIdentifier),
+    })
+}
+
+impl TryFrom<PrivateKeyInfo<'_>> for SigningKey {
+    type Error = rsa::pkcs8::Error;
+
+    fn try_from(info: PrivateKeyInfo<'_>) -> Result<Self, Self::Error> {
+        let curve = info.algorithm.parameters_oid().ok();
+        Ok(match (info.algorithm.oid, curve) {
+            (RSA_ENCRYPTION, _) => SigningKey::Rsa(info.try_into()?),
+            (ID_EC_PUBLIC_KEY, Some(SECP_256_R_1)) => SigningKey::P256(info.try_into()?),
+            (ID_EC_PUBLIC_KEY, Some(SECP_384_R_1)) => SigningKey::P384(info.try_into()?),
+            (ID_EC_PUBLIC_KEY, Some(SECP_521_R_1)) => SigningKey::P521(info.try_into()?),
+            (ID_ED_25519, _) => SigningKey::Ed25519(info.try_into()?),
+            // the CurvePrivateKey OCTET STRING of RFC 8410 §7
+            (ID_ED_448, _) => {
+                let key = OctetStringRef::from_der(info.private_key)?;
+                SigningKey::Ed448(
+                    key.as_bytes()
+                        .try_into()
+                        .map_err(|_| rsa::pkcs8::Error::KeyMalformed)?,
+                )
+            }
+            _ => return Err(rsa::pkcs8::Error::KeyMalformed),
+        })
+    }
+}
+
+impl TryFrom<SubjectPublicKeyInfoRef<'_>> for VerifyingKey {
+    type Error = rsa::pkcs8::spki::Error;
+
+    fn try_from(info: SubjectPublicKeyInfoRef<'_>) -> Result<Self, Self::Error> {
+        let curve = info.algorithm.parameters_oid().ok();
+        Ok(match (info.algorithm.oid, curve) {
+            (RSA_ENCRYPTION, _) => VerifyingKey::Rsa(info.try_into()?),
+            (ID_EC_PUBLIC_KEY, Some(SECP_256_R_1)) => VerifyingKey::P256(info.try_into()?),
+            (ID_EC_PUBLIC_KEY, Some(SECP_384_R_1)) => VerifyingKey::P384(info.try_into()?),
+            (ID_EC_PUBLIC_KEY, Some(SECP_521_R_1)) => VerifyingKey::P521(info.try_into()?),
+            (ID_ED_25519, _) => VerifyingKey::Ed25519(info.try_into()?),
+            (ID_ED_448, _) => VerifyingKey::Ed448(info.try_into()?),
+            _ => {
+                return Err(rsa::pkcs8::spki::Error::OidUnknown {
+                    oid: info.algorithm.oid,
+                });
+            }
+        })
+    }
+}
+
+impl NotifyPayload {
+    /// A SIGNATURE_HASH_ALGORITHMS notify (RFC 7427 §4) listing `hashes`.
+    pub fn signature_hash_algorithms(hashes: &[HashAlgorithm]) -> Result<Self, Error> {
+        let mut data = vec![];
+        for hash in hashes {
+            data.extend(hash.to_bytes()?);
+        }
+        Ok(NotifyPayload {
+            protocol_id: ProtocolIdentifier::Reserved,
+            spi_size: 0,
+            notify_type: NotifyType::SIGNATURE_HASH_ALGORITHMS,
+            spi: vec![],
+            data,
+        })
+    }
+
+    /// The hash algorithms listed in a SIGNATURE_HASH_ALGORITHMS notify.
+    pub fn hash_algorithms(&self) -> Result<Vec<HashAlgorithm>, Error> {
+        if self.notify_type != NotifyType::SIGNATURE_HASH_ALGORITHMS
+            || !self.data.len().is_multiple_of(2)
+        {
+            return Err(Error::Notify(NotifyType::INVALID_SYNTAX));
+        }
+        self.data
+            .chunks_exact(2)
+            .map(|hash| Ok(HashAlgorithm::from_bytes((hash, 0))?.1))
+            .collect()
+    }
+}
+
+/// The first of our hash algorithms that the peer supports.
+pub fn negotiate_hash(ours: &[HashAlgorithm], theirs: &[HashAlgorithm]) -> Option<HashAlgorithm> {
+    ours.iter().find(|hash| theirs.contains(hash)).cloned()
+}
+
+#[cfg(test)]
+mod test {
+    use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey};
+
+    use super::*;
+    use crate::test_util::hex;
+
+    const MESSAGE: &[u8] = b"IKEv2 signed octets";
+
+    /// Loads the key pair stored as `testdata/keys/<name>.der` and
+    /// `testdata/keys/<name>.pub.der`.
+    macro_rules! keys {
+        ($name:literal) => {
+            keys(
+                $name,
+                include_bytes!(concat!("testdata/keys/", $name, ".der")),
+                include_bytes!(concat!("testdata/keys/", $name, ".pub.der")),
+            )
+        };
+    }
+
+    fn keys(name: &str, private: &[u8], public: &[u8]) -> (SigningKey, VerifyingKey) {
+        let signing_key = SigningKey::from_pkcs8_der(private).expect(name);
+        let verifying_key = VerifyingKey::from_public_key_der(public).unwrap();
+        assert_eq!(signing_key.verifying_key(), verifying_key);
+        (signing_key, verifying_key)
+    }
+
+    #[test]
+    fn test_algorithm_identifier() {
+        // RFC 7427 Appendix A and the encoding produced by OpenSSL
+        let cases = [
+            (
+                SignatureAlgorithm::RsaPkcs1(HashAlgorithm::SHA2_256),
+                "300d06092a864886f70d01010b0500",
+            ),
+            (
+                SignatureAlgorithm::rsa_pss(HashAlgorithm::SHA2_256).unwrap(),
+                "304106092a864886f70d01010a3034a00f300d06096086480165030402010500\
+                 a11c301a06092a864886f70d010108300d06096086480165030402010500a203020120",
+            ),
+            (
+                SignatureAlgorithm::Ecdsa(HashAlgorithm::SHA2_256),
+                "300a06082a8648ce3d040302",
+            ),
+            (SignatureAlgorithm::Ed25519, "300506032b6570"),
+            (SignatureAlgorithm::Ed448, "300506032b6571"),
+        ];
+        for (algorithm, der) in cases {
+            assert_eq!(algorithm.to_der(), Ok(hex(der)));
+            assert_eq!(SignatureAlgorithm::from_der(&hex(der)), Ok(algorithm));
+        }
+        for hash in [
+            HashAlgorithm::SHA2_256,
+            HashAlgorithm::SHA2_384,
+            HashAlgorithm::SHA2_512,
+        ] {
+            for algorithm in [
+                SignatureAlgorithm::RsaPkcs1(hash.clone()),
+                SignatureAlgorithm::RsaPss {
+                    hash: hash.clone(),
+                    salt_length: 222,
+                },
+                SignatureAlgorithm::Ecdsa(hash.clone()),
+            ] {
+                let der = algorithm.to_der().unwrap();
+                assert_eq!(SignatureAlgorithm::from_der(&der), Ok(algorithm));
+            }
+        }
+
+        // PKCS #1 without NULL, PSS with omitted salt length and trailer field
+        assert_eq!(
+            SignatureAlgorithm::from_der(&hex("300b06092a864886f70d01010c")),
+            Ok(SignatureAlgorithm::RsaPkcs1(HashAlgorithm::SHA2_384))
+        );
+        assert_eq!(
+            SignatureAlgorithm::from_der(&hex(
+                "303d06092a864886f70d01010a3030a00d300b0609608648016503040203\
+                 a11a301806092a864886f70d010108300b0609608648016503040203a303020101"
+            )),
+            Ok(SignatureAlgorithm::RsaPss {
+                hash: HashAlgorithm::SHA2_512,
+                salt_length: 20
+            })
+        );
+
+        for der in [
+            // SHA-1 is not supported
+            "300d06092a864886f70d0101050500",
+            // PSS defaults to SHA-1
+            "300f06092a864886f70d01010a3002",
+            // MGF1 hash differs from the hash
+            "304106092a864886f70d01010a3034a00f300d06096086480165030402010500\
+             a11c301a06092a864886f70d010108300d06096086480165030402020500a203020120",
+            // parameters on ECDSA
+            "300c06082a8648ce3d0403020500",
+            // trailing data
+            "300506032b657000",
+            "300606032b6570",
+            "",
+        ] {
+            assert_eq!(
+                SignatureAlgorithm::from_der(&hex(der)),
+                Err(Error::InvalidAlgorithmIdentifier)
+            );
+        }
+        assert_eq!(
+            SignatureAlgorithm::Ecdsa(HashAlgorithm::SHA1).to_der(),
+            Err(Error::InvalidAlgorithmIdentifier)
+        );
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let cases = [
+            (
+                keys!("rsa"),
+                SignatureAlgorithm::RsaPkcs1(HashAlgorithm::SHA2_256),
+            ),
+            (
+                keys!("rsa"),
+                SignatureAlgorithm::rsa_pss(HashAlgorithm::SHA2_512).unwrap(),
+            ),
+            (
+                keys!("p256"),
+                SignatureAlgorithm::Ecdsa(HashAlgorithm::SHA2_256),
+            ),
+            (
+                keys!("p384"),
+                SignatureAlgorithm::Ecdsa(HashAlgorithm::SHA2_384),
+            ),
+            (
+                keys!("p521"),
+                SignatureAlgorithm::Ecdsa(HashAlgorithm::SHA2_512),
+            ),
+            (keys!("ed25519"), SignatureAlgorithm::Ed25519),
+            (keys!("ed448"), SignatureAlgorithm::Ed448),
+        ];
+        let failed = Err(Error::Notify(NotifyType::AUTHENTICATION_FAILED));
+        for ((signing_key, verifying_key), algorithm) in cases {
+            let signature = signing_key.sign(&algorithm, MESSAGE).unwrap();
+            assert_eq!(
+                verifying_key.verify(&algorithm, MESSAGE, &signature),
+                Ok(())
+            );
+            assert_eq!(
+                verifying_key.verify(&algorithm, &MESSAGE[1..], &signature),
+                failed
+            );
+            assert_eq!(
+                verifying_key.verify(&algorithm, MESSAGE, &signature[1..]),
+                failed
+            );
+        }
+
+        let (rsa, _) = keys!("rsa");
+        let (_, ed25519) = keys!("ed25519");
+        assert_eq!(
+            rsa.sign(&SignatureAlgorithm::Ed25519, MESSAGE),
+            Err(Error::KeyMismatch)
+        );
+        assert_eq!(
+            ed25519.verify(&SignatureAlgorithm::Ed448, MESSAGE, &[0; 114]),
+            failed
+        );
+    }
+
+    #[test]
+    fn test_verify_openssl() {
+        // Signatures over MESSAGE made with OpenSSL through Python's cryptography
+        let cases = [
+            (
+                keys!("rsa"),
+                &include_bytes!("testdata/keys/rsa_pkcs1_sha256.sig")[..],
+                SignatureAlgorithm::RsaPkcs1(HashAlgorithm::SHA2_256),
+            ),
+            (
+                keys!("rsa"),
+                &include_bytes!("testdata/keys/rsa_pss_sha384.sig")[..],
+                SignatureAlgorithm::rsa_pss(HashAlgorithm::SHA2_384).unwrap(),
+            ),
+            (
+                keys!("p256"),
+                &include_bytes!("testdata/keys/p256_sha512.sig")[..],
+                SignatureAlgorithm::Ecdsa(HashAlgorithm::SHA2_512),
+            ),
+            (
+                keys!("p384"),
+                &include_bytes!("testdata/keys/p384_sha384.sig")[..],
+                SignatureAlgorithm::Ecdsa(HashAlgorithm::SHA2_384),
+            ),
+            (
+                keys!("ed25519"),
+                &include_bytes!("testdata/keys/ed25519.sig")[..],
+                SignatureAlgorithm::Ed25519,
+            ),
+            (
+                keys!("ed448"),
+                &include_bytes!("testdata/keys/ed448.sig")[..],
+                SignatureAlgorithm::Ed448,
+            ),
+        ];
+        for ((signing_key, verifying_key), signature, algorithm) in cases {
+            assert_eq!(verifying_key.verify(&algorithm, MESSAGE, signature), Ok(()));
+            // deterministic schemes produce the same signature
+            if matches!(
+                algorithm,
+                SignatureAlgorithm::RsaPkcs1(_)
+                    | SignatureAlgorithm::Ed25519
+                    | SignatureAlgorithm::Ed448
+            ) {
+                assert_eq!(
+                    signing_key.sign(&algorithm, MESSAGE),
+                    Ok(signature.to_vec())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_signature_hash_algorithms() {
+        let data = include_bytes!("testdata/ike_sa_init.bin");
+        let notify = NotifyPayload::try_from(&data[952..964]).unwrap();
+        let theirs = notify.hash_algorithms().unwrap();
+        assert_eq!(
+            theirs,
+            [
+                HashAlgorithm::SHA2_256,
+                HashAlgorithm::SHA2_384,
+                HashAlgorithm::SHA2_512,
+                HashAlgorithm::Identity
+            ]
+        );
+        assert_eq!(
+            NotifyPayload::signature_hash_algorithms(&theirs)
+                .unwrap()
+                .to_bytes(),
+            Ok(data[952..964].to_vec())
+        );
+
+        assert_eq!(
+            negotiate_hash(&[HashAlgorithm::SHA1, HashAlgorithm::SHA2_512], &theirs),
+            Some(HashAlgorithm::SHA2_512)
+        );
+        assert_eq!(negotiate_hash(&[HashAlgorithm::SHA1], &theirs), None);
+        assert_eq!(SignatureAlgorithm::Ed448.hash(), HashAlgorithm::Identity);
+
+        let mut odd = notify;
+        odd.data.push(0);
+        assert_eq!(
+            odd.hash_algorithms(),
+            Err(Error::Notify(NotifyType::INVALID_SYNTAX))
+        );
+    }
+}
@@ -0,0 +1,539 @@
+use aes::{Aes128, Aes192, Aes256};
+use aes_gcm::AesGcm;
+use aes_gcm::aead::consts::U12;
+use aes_gcm::aead::{AeadInPlace, KeyInit};
+use cbc::cipher::block_padding::NoPadding;
+use cbc::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit, StreamCipher};
+use deku::prelude::*;
+use hmac::Hmac;
+use sha1::Sha1;
+use sha2::{Sha256, Sha384, Sha512};
+use subtle::ConstantTimeEq;
+
+use crate::consts::*;
+use crate::error::Error;
+use crate::keys::{IkeSaKeys, hmac};
+use crate::message::*;
+use crate::transform;
+use crate::types::*;
+
+/// Runs `$body` with `$aes` bound to the AES variant matching the key size.
+macro_rules! with_aes {
+    ($key:expr, $aes:ident => $body:expr) => {
+        match $key.len() {
+            16 => {
+                type $aes = Aes128;
+                $body
+            }
+            24 => {
+                type $aes = Aes192;
+                $body
+            }
+            32 => {
+                type $aes = Aes256;
+                $body
+            }
+            n => Err(Error::InvalidKeyLength(n)),
+        }
+    };
+}
+
+/// Protects the Encrypted (SK) payload in one direction of an IKE SA: SK_ei
+/// and SK_ai for messages sent by the original initiator, SK_er and SK_ar for
+/// those sent by the responder.
+///
+/// Supported are ENCR_AES_CBC with HMAC-SHA1 or HMAC-SHA2 integrity, and
+/// ENCR_AES_GCM_8/12/16 (RFC 5282) without an integrity transform.
+#[derive(Clone, Debug)]
+pub struct SkCipher {
+    encr: transform::ENCR,
+    integ: transform::INTEG,
+    sk_e: Vec<u8>,
+    sk_a: Vec<u8>,
+}
+
+impl SkCipher {
+    /// The key size is taken from `sk_e`, which for AES-GCM includes the
+    /// four salt bytes.
+    pub fn new(
+        encr: transform::ENCR,
+        integ: transform::INTEG,
+        sk_e: &[u8],
+        sk_a: &[u8],
+    ) -> Result<Self, Error> {
+        let salt = match encr {
+            transform::ENCR::ENCR_AES_CBC => 0,
+            transform::ENCR::ENCR_AES_GCM_8
+            | transform::ENCR::ENCR_AES_GCM_12
+            | transform::ENCR::ENCR_AES_GCM_16 => 4,
+            _ => return Err(Error::UnsupportedTransform(TransformType::ENCR(0, encr))),
+        };
+        if !matches!(sk_e.len().checked_sub(salt), Some(16 | 24 | 32)) {
+            return Err(Error::InvalidKeyLength(sk_e.len()));
+        }
+
+        let aead = encr.icv_length().is_some();
+        let supported = match integ {
+            transform::INTEG::NONE => aead,
+            transform::INTEG::AUTH_HMAC_SHA1_96
+            | transform::INTEG::AUTH_HMAC_SHA2_256_128
+            | transform::INTEG::AUTH_HMAC_SHA2_384_192
+            | transform::INTEG::AUTH_HMAC_SHA2_512_256 => !aead,
+            _ => false,
+        };
+        if !supported {
+            return Err(Error::UnsupportedTransform(TransformType::INTEG(0, integ)));
+        }
+        if integ.key_length() != Some(sk_a.len()) {
+            return Err(Error::InvalidKeyLength(sk_a.len()));
+        }
+
+        Ok(SkCipher {
+            encr,
+            integ,
+            sk_e: sk_e.to_vec(),
+            sk_a: sk_a.to_vec(),
+        })
+    }
+
+    /// Protects messages sent by the initiator (or the responder) of an IKE
+    /// SA with the ENCR and INTEG transforms of its proposal.
+    pub fn from_keys(
+        proposal: &Proposal,
+        keys: &IkeSaKeys,
+        initiator: bool,
+    ) -> Result<Self, Error> {
+        let (encr, _) = proposal.encr().ok_or(Error::MissingTransform("ENCR"))?;
+        let integ = proposal.integ().unwrap_or(&transform::INTEG::NONE);
+        if initiator {
+            SkCipher::new(encr.clone(), integ.clone(), &keys.sk_ei, &keys.sk_ai)
+        } else {
+            SkCipher::new(encr.clone(), integ.clone(), &keys.sk_er, &keys.sk_ar)
+        }
+    }
+
+    pub fn iv_length(&self) -> usize {
+        if self.is_aead() { 8 } else { 16 }
+    }
+
+    pub fn icv_length(&self) -> usize {
+        self.encr
+            .icv_length()
+            .or(self.integ.icv_length())
+            .unwrap_or_default()
+    }
+
+    fn is_aead(&self) -> bool {
+        self.encr.icv_length().is_some()
+    }
+
+    /// Verifies and decrypts the SK payload that ends the message in `data`.
+    /// The returned message holds the unencrypted payloads followed by the
+    /// decrypted inner payloads.
+    pub fn decrypt(&self, data: &[u8]) -> Result<IkeMessage, Error> {
+        let mut message = IkeMessage::parse(data)?;
+        let Some(Payload {
+            payload_type: PayloadType::SK,
+            body:
+                PayloadBody::Encrypted {
+                    next_payload,
+                    data: body,
+                },
+            ..
+        }) = message.payloads.pop()
+        else {
+            return Err(Error::MissingEncryptedPayload);
+        };
+
+        let (iv_length, icv_length) = (self.iv_length(), self.icv_length());
+        if body.len() < iv_length + icv_length {
+            return Err(Error::InvalidEncryptedPayload);
+        }
+        let (authenticated, icv) = data.split_at(data.len() - icv_length);
+        let aad = &data[..data.len() - body.len()];
+        let iv = &body[..iv_length];
+        let mut plaintext = body[iv_length..body.len() - icv_length].to_vec();
+
+        if self.is_aead() {
+            let (key, salt) = self.sk_e.split_at(self.sk_e.len() - 4);
+            gcm_open(key, &[salt, iv].concat(), aad, &mut plaintext, icv)?;
+        } else {
+            let expected = self.mac(authenticated)?;
+            if !bool::from(expected[..icv_length].ct_eq(icv)) {
+                return Err(Error::IntegrityCheckFailed);
+            }
+            if plaintext.len() % 16 != 0 {
+                return Err(Error::InvalidEncryptedPayload);
+            }
+            with_aes!(self.sk_e, A => {
+                cbc::Decryptor::<A>::new_from_slices(&self.sk_e, iv)
+                    .map_err(|_| Error::InvalidKeyLength(self.sk_e.len()))?
+                    .decrypt_padded_mut::<NoPadding>(&mut plaintext)
+                    .map(|_| ())
+                    .map_err(|_| Error::InvalidEncryptedPayload)
+            })?;
+        }
+
+        let pad_length = usize::from(*plaintext.last().ok_or(Error::InvalidEncryptedPayload)?);
+        let length = plaintext
+            .len()
+            .checked_sub(pad_length + 1)
+            .ok_or(Error::InvalidEncryptedPayload)?;
+        plaintext.truncate(length);
+
+        let response = message.header.flags.response;
+        message
+            .payloads
+            .extend(parse_payloads(next_payload, &plaintext, response)?);
+        Ok(message)
+    }
+
+    /// Encrypts all payloads of `message` into a single SK payload and
+    /// returns the protected datagram. `iv` must be `iv_length()` bytes,
+    /// random for AES-CBC and never reused with the same key for AES-GCM.
+    pub fn encrypt(&self, message: &IkeMessage, iv: &[u8]) -> Result<Vec<u8>, Error> {
+        if iv.len() != self.iv_length() {
+            return Err(Error::InvalidIvLength(iv.len()));
+        }
+        let mut payloads = message.payloads.clone();
+        let (first, mut plaintext) = encode_payloads(&mut payloads)?;
+        let block_size = if self.is_aead() { 1 } else { 16 };
+        let pad_length = (block_size - (plaintext.len() + 1) % block_size) % block_size;
+        plaintext.resize(plaintext.len() + pad_length, 0);
+        plaintext.push(pad_length as u8);
+
+        let icv_length = self.icv_length();
+        let payload_length = PAYLOAD_HEADER_LENGTH + iv.len() + plaintext.len() + icv_length;
+        let mut header = message.header.clone();
+        header.next_payload = PayloadType::SK;
+        header.length = u32::try_from(IKE_HEADER_LENGTH + payload_length)
+            .map_err(|_| Error::MessageTooLong(IKE_HEADER_LENGTH + payload_length))?;
+        let payload_header = PayloadHeader {
+            next_payload: first,
+            critical: false,
+            reserved: 0,
+            payload_length: u16::try_from(payload_length)
+                .map_err(|_| Error::PayloadTooLong(PayloadType::SK, payload_length))?,
+        };
+        let mut data = header.to_bytes()?;
+        data.extend(payload_header.to_bytes()?);
+
+        if self.is_aead() {
+            let (key, salt) = self.sk_e.split_at(self.sk_e.len() - 4);
+            let tag = gcm_seal(key, &[salt, iv].concat(), &data, &mut plaintext)?;
+            data.extend(iv);
+            data.extend(plaintext);
+            data.extend(&tag[..icv_length]);
+        } else {
+            let length = plaintext.len();
+            with_aes!(self.sk_e, A => {
+                cbc::Encryptor::<A>::new_from_slices(&self.sk_e, iv)
+                    .map_err(|_| Error::InvalidKeyLength(self.sk_e.len()))?
+                    .encrypt_padded_mut::<NoPadding>(&mut plaintext, length)
+                    .map(|_| ())
+                    .map_err(|_| Error::InvalidEncryptedPayload)
+            })?;
+            data.extend(iv);
+            data.extend(plaintext);
+            let icv = self.mac(&data)?;
+            data.extend(&icv[..icv_length]);
+        }
+        Ok(data)
+    }
+
+    /// Untruncated HMAC of `data` under SK_a.
+    fn mac(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(match self.integ {
+            transform::INTEG::AUTH_HMAC_SHA1_96 => hmac::<Hmac<Sha1>>(&self.sk_a, data),
+            transform::INTEG::AUTH_HMAC_SHA2_256_128 => hmac::<Hmac<Sha256>>(&self.sk_a, data),
+            transform::INTEG::AUTH_HMAC_SHA2_384_192 => hmac::<Hmac<Sha384>>(&self.sk_a, data),
+            transform::INTEG::AUTH_HMAC_SHA2_512_256 => hmac::<Hmac<Sha512>>(&self.sk_a, data),
+            _ => {
+                return Err(Error::UnsupportedTransform(TransformType::INTEG(
+                    0,
+                    self.integ.clone(),
+                )));
+            }
+        })
+    }
+}
+
+/// AES-GCM encryption in place, returning the full 16-byte tag. Shorter
+/// ICVs are prefixes of it.
+fn gcm_seal(key: &[u8], nonce: &[u8], aad: &[u8], buffer: &mut [u8]) -> Result<Vec<u8>, Error> {
+    with_aes!(key, A => {
+        let cipher = AesGcm::<A, U12>::new_from_slice(key)
+            .map_err(|_| Error::InvalidKeyLength(key.len()))?;
+        cipher
+            .encrypt_in_place_detached(nonce.into(), aad, buffer)
+            .map(|tag| tag.to_vec())
+            .map_err(|_| Error::InvalidEncryptedPayload)
+    })
+}
+
+/// AES-GCM decryption in place against a possibly truncated ICV. The
+/// ciphertext is decrypted with the GCM counter stream and the tag is then
+/// recomputed over it, since the AEAD implementation only checks full tags.
+fn gcm_open(
+    key: &[u8],
+    nonce: &[u8],
+    aad: &[u8],
+    buffer: &mut [u8],
+    icv: &[u8],
+) -> Result<(), Error> {
+    let ciphertext = buffer.to_vec();
+    let counter_block = [nonce, &2u32.to_be_bytes()].concat();
+    with_aes!(key, A => {
+        ctr::Ctr32BE::<A>::new_from_slices(key, &counter_block)
+            .map_err(|_| Error::InvalidKeyLength(key.len()))?
+            .apply_keystream(buffer);
+        Ok(())
+    })?;
+    let mut reencrypted = buffer.to_vec();
+    let tag = gcm_seal(key, nonce, aad, &mut reencrypted)?;
+    if !bool::from(tag[..icv.len()].ct_eq(icv)) {
+        buffer.copy_from_slice(&ciphertext);
+        return Err(Error::IntegrityCheckFailed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZero;
+
+    use super::*;
+    use crate::test_util::hex;
+    use crate::ts::{TrafficSelector, TsPayload};
+
+    fn ike_auth() -> IkeMessage {
+        IkeMessage {
+            header: IKEHeader {
+                initiator_spi: NonZero::new(0x0102030405060708).unwrap(),
+                responder_spi: 0x1112131415161718,
+                next_payload: PayloadType::NoNextPayload,
+                major_version: 2,
+                minor_version: 0,
+                exchange_type: ExchangeType::IKE_AUTH,
+                flags: Flags {
+                    unused_0: false,
+                    unused_1: false,
+                    response: false,
+                    version: false,
+                    initiator: true,
+                    unused_2: false,
+                    unused_3: false,
+                    unused_4: false,
+                },
+                message_id: 1,
+                length: 0,
+            },
+            payloads: vec![
+                Payload {
+                    payload_type: PayloadType::IDi,
+                    critical: false,
+                    body: PayloadBody::ID(Identity::Fqdn("client.example".to_string()).into()),
+                },
+                Payload {
+                    payload_type: PayloadType::N,
+                    critical: false,
+                    body: PayloadBody::N(NotifyPayload {
+                        protocol_id: ProtocolIdentifier::Reserved,
+                        spi_size: 0,
+                        notify_type: NotifyType::INITIAL_CONTACT,
+                        spi: vec![],
+                        data: vec![],
+                    }),
+                },
+                Payload {
+                    payload_type: PayloadType::TSi,
+                    critical: false,
+                    body: PayloadBody::TS(TsPayload {
+                        number_of_ts: 1,
+                        reserved: [0; 3],
+                        traffic_selectors: vec![
+                            TrafficSelector::from_prefix("10.0.0.0".parse().unwrap(), 8).unwrap(),
+                        ],
+                    }),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_gcm_vector() {
+        // Test Case 4 of the GCM specification, with the IV split into a
+        // four-byte salt and the eight-byte explicit IV.
+        let key = hex("feffe9928665731c6d6a8f9467308308");
+        let nonce = hex("cafebabefacedbaddecaf888");
+        let aad = hex("feedfacedeadbeeffeedfacedeadbeefabaddad2");
+        let plaintext = hex(
+            "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72\
+             1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b39",
+        );
+        let ciphertext = hex(
+            "42831ec2217774244b7221b784d0d49ce3aa212f2c02a4e035c17e2329aca12e\
+             21d514b25466931c7d8f6a5aac84aa051ba30b396a0aac973d58e091",
+        );
+        let tag = hex("5bc94fbc3221a5db94fae95ae7121a47");
+
+        let mut buffer = plaintext.clone();
+        assert_eq!(gcm_seal(&key, &nonce, &aad, &mut buffer), Ok(tag.clone()));
+        assert_eq!(buffer, ciphertext);
+
+        for icv_length in [8, 12, 16] {
+            let mut buffer = ciphertext.clone();
+            gcm_open(&key, &nonce, &aad, &mut buffer, &tag[..icv_length]).unwrap();
+            assert_eq!(buffer, plaintext);
+        }
+        let mut buffer = ciphertext.clone();
+        assert_eq!(
+            gcm_open(&key, &nonce, &aad[1..], &mut buffer, &tag[..8]),
+            Err(Error::IntegrityCheckFailed)
+        );
+        assert_eq!(buffer, ciphertext);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let cases = [
+            (
+                transform::ENCR::ENCR_AES_CBC,
+                transform::INTEG::AUTH_HMAC_SHA2_256_128,
+                16,
+                32,
+            ),
+            (
+                transform::ENCR::ENCR_AES_CBC,
+                transform::INTEG::AUTH_HMAC_SHA2_512_256,
+                32,
+                64,
+            ),
+            (
+                transform::ENCR::ENCR_AES_CBC,
+                transform::INTEG::AUTH_HMAC_SHA1_96,
+                24,
+                20,
+            ),
+            (
+                transform::ENCR::ENCR_AES_GCM_8,
+                transform::INTEG::NONE,
+                20,
+                0,
+            ),
+            (
+                transform::ENCR::ENCR_AES_GCM_12,
+                transform::INTEG::NONE,
+                28,
+                0,
+            ),
+            (
+                transform::ENCR::ENCR_AES_GCM_16,
+                transform::INTEG::NONE,
+                36,
+                0,
+            ),
+        ];
+        let message = ike_auth();
+        for (encr, integ, sk_e_length, sk_a_length) in cases {
+            let sk_e: Vec<u8> = (0..sk_e_length).collect();
+            let sk_a: Vec<u8> = (0x80..0x80 + sk_a_length).collect();
+            let cipher = SkCipher::new(encr.clone(), integ.clone(), &sk_e, &sk_a).unwrap();
+            let iv = vec![0x5a; cipher.iv_length()];
+            let data = cipher.encrypt(&message, &iv).unwrap();
+
+            let outer = IkeMessage::parse(&data).unwrap();
+            assert_eq!(outer.header.next_payload, PayloadType::SK);
+            let [
+                Payload {
+                    body: PayloadBody::Encrypted { next_payload, .. },
+                    ..
+                },
+            ] = &outer.payloads[..]
+            else {
+                panic!("expected a single SK payload");
+            };
+            assert_eq!(*next_payload, PayloadType::IDi);
+
+            let decrypted = cipher.decrypt(&data).unwrap();
+            assert_eq!(decrypted.payloads, message.payloads);
+            assert_eq!(decrypted.header.length as usize, data.len());
+
+            let mut tampered = data.clone();
+            tampered[IKE_HEADER_LENGTH + PAYLOAD_HEADER_LENGTH + iv.len()] ^= 1;
+            assert_eq!(
+                cipher.decrypt(&tampered).map(|_| ()),
+                Err(Error::IntegrityCheckFailed),
+                "{encr:?}/{integ:?}"
+            );
+            // The header is covered by the ICV too.
+            let mut tampered = data.clone();
+            tampered[19] ^= 1;
+            assert_eq!(
+                cipher.decrypt(&tampered).map(|_| ()),
+                Err(Error::IntegrityCheckFailed)
+            );
+        }
+    }
+
+    #[test]
+    fn test_empty_and_invalid() {
+        let cipher = SkCipher::new(
+            transform::ENCR::ENCR_AES_CBC,
+            transform::INTEG::AUTH_HMAC_SHA2_256_128,
+            &[1; 16],
+            &[2; 32],
+        )
+        .unwrap();
+        let mut message = ike_auth();
+        message.payloads.clear();
+        let data = cipher.encrypt(&message, &[0; 16]).unwrap();
+        assert_eq!(data.len(), IKE_HEADER_LENGTH + 4 + 16 + 16 + 16);
+        assert_eq!(cipher.decrypt(&data).unwrap().payloads, vec![]);
+        assert_eq!(
+            cipher.encrypt(&message, &[0; 8]),
+            Err(Error::InvalidIvLength(8))
+        );
+        assert_eq!(
+            cipher.decrypt(include_bytes!("testdata/ike_sa_init.bin")),
+            Err(Error::MissingEncryptedPayload)
+        );
+
+        assert_eq!(
+            SkCipher::new(
+                transform::ENCR::ENCR_AES_GCM_16,
+                transform::INTEG::AUTH_HMAC_SHA2_256_128,
+                &[0; 20],
+                &[0; 32],
+            )
+            .map(|_| ()),
+            Err(Error::UnsupportedTransform(TransformType::INTEG(
+                0,
+                transform::INTEG::AUTH_HMAC_SHA2_256_128
+            )))
+        );
+        assert_eq!(
+            SkCipher::new(
+                transform::ENCR::ENCR_AES_CBC,
+                transform::INTEG::AUTH_HMAC_SHA2_256_128,
+                &[0; 20],
+                &[0; 32],
+            )
+            .map(|_| ()),
+            Err(Error::InvalidKeyLength(20))
+        );
+        assert_eq!(
+            SkCipher::new(
+                transform::ENCR::ENCR_3DES,
+                transform::INTEG::AUTH_HMAC_SHA2_256_128,
+                &[0; 24],
+                &[0; 32],
+            )
+            .map(|_| ()),
+            Err(Error::UnsupportedTransform(TransformType::ENCR(
+                0,
+                transform::ENCR::ENCR_3DES
+            )))
+        );
+    }
+}
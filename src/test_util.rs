@@ -0,0 +1,36 @@
+use crate::consts::{LastSubstructure, ProtocolIdentifier, TransformType};
+use crate::types::{Attribute, Proposal, Transform};
+
+/// Decodes a string of hex digits, for writing test vectors.
+pub(crate) fn hex(s: &str) -> Vec<u8> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+/// A transform with an optional Key Length attribute.
+pub(crate) fn transform(transform_type: TransformType, key_length: Option<u16>) -> Transform {
+    Transform {
+        last_substructure: LastSubstructure::Transform,
+        reserved_0: 0,
+        transform_length: 0,
+        transform_type,
+        transform_attributes: key_length.map(Attribute::KeyLength).into_iter().collect(),
+    }
+}
+
+/// A proposal numbered 1 without an SPI.
+pub(crate) fn proposal(protocol_id: ProtocolIdentifier, transforms: Vec<Transform>) -> Proposal {
+    Proposal {
+        last_substructure: LastSubstructure::Last,
+        reserved: 0,
+        proposal_length: 0,
+        proposal_num: 1,
+        protocol_id,
+        spi_size: 0,
+        num_transforms: 0,
+        spi: vec![],
+        transforms,
+    }
+}
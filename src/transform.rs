@@ -4,7 +4,12 @@ use deku::prelude::*;
 /// Reference: https://www.iana.org/assignments/ikev2-parameters/ikev2-parameters.xhtml#ikev2-parameters-5
 #[allow(non_camel_case_types)]
 #[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
-#[deku(id_type = "u16", endian = "endian", ctx = "endian: deku::ctx::Endian")]
+#[deku(
+    id_type = "u16",
+    id_endian = "endian",
+    endian = "endian",
+    ctx = "endian: deku::ctx::Endian"
+)]
 pub enum ENCR {
     #[deku(id = 0)]
     Reserved0,
@@ -84,11 +89,68 @@ pub enum ENCR {
     Private(u16),
 }
 
+impl ENCR {
+    /// Length of the keying material taken for this algorithm: the key plus
+    /// any salt or nonce. `key_length` is the Key Length attribute in bits.
+    pub fn key_material_length(&self, key_length: Option<u16>) -> Option<usize> {
+        let aes = || match key_length {
+            Some(bits @ (128 | 192 | 256)) => Some(usize::from(bits / 8)),
+            _ => None,
+        };
+        Some(match self {
+            ENCR::ENCR_NULL => 0,
+            ENCR::ENCR_DES | ENCR::ENCR_DES_IV64 | ENCR::ENCR_DES_IV32 => 8,
+            ENCR::ENCR_3DES => 24,
+            ENCR::ENCR_AES_CBC | ENCR::ENCR_CAMELLIA_CBC => aes()?,
+            ENCR::ENCR_AES_CTR | ENCR::ENCR_CAMELLIA_CTR => aes()? + 4,
+            ENCR::ENCR_AES_CCM_8
+            | ENCR::ENCR_AES_CCM_12
+            | ENCR::ENCR_AES_CCM_16
+            | ENCR::ENCR_AES_CCM_8_IIV
+            | ENCR::ENCR_CAMELLIA_CCM_8
+            | ENCR::ENCR_CAMELLIA_CCM_12
+            | ENCR::ENCR_CAMELLIA_CCM_16 => aes()? + 3,
+            ENCR::ENCR_AES_GCM_8
+            | ENCR::ENCR_AES_GCM_12
+            | ENCR::ENCR_AES_GCM_16
+            | ENCR::ENCR_AES_GCM_16_IIV
+            | ENCR::ENCR_NULL_AUTH_AES_GMAC => aes()? + 4,
+            ENCR::ENCR_CHACHA20_POLY1305 | ENCR::ENCR_CHACHA20_POLY1305_IIV => 32 + 4,
+            _ => return None,
+        })
+    }
+
+    /// Length of the ICV appended by combined-mode algorithms, or `None` for
+    /// algorithms that need a separate integrity transform.
+    pub fn icv_length(&self) -> Option<usize> {
+        match self {
+            ENCR::ENCR_AES_CCM_8
+            | ENCR::ENCR_AES_CCM_8_IIV
+            | ENCR::ENCR_AES_GCM_8
+            | ENCR::ENCR_CAMELLIA_CCM_8 => Some(8),
+            ENCR::ENCR_AES_CCM_12 | ENCR::ENCR_AES_GCM_12 | ENCR::ENCR_CAMELLIA_CCM_12 => Some(12),
+            ENCR::ENCR_AES_CCM_16
+            | ENCR::ENCR_AES_GCM_16
+            | ENCR::ENCR_AES_GCM_16_IIV
+            | ENCR::ENCR_CAMELLIA_CCM_16
+            | ENCR::ENCR_NULL_AUTH_AES_GMAC
+            | ENCR::ENCR_CHACHA20_POLY1305
+            | ENCR::ENCR_CHACHA20_POLY1305_IIV => Some(16),
+            _ => None,
+        }
+    }
+}
+
 /// Transform Type 2 - Pseudorandom Function Transform IDs
 /// Reference: https://www.iana.org/assignments/ikev2-parameters/ikev2-parameters.xhtml#ikev2-parameters-6
 #[allow(non_camel_case_types)]
 #[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
-#[deku(id_type = "u16", endian = "endian", ctx = "endian: deku::ctx::Endian")]
+#[deku(
+    id_type = "u16",
+    id_endian = "endian",
+    endian = "endian",
+    ctx = "endian: deku::ctx::Endian"
+)]
 pub enum PRF {
     #[deku(id = 0)]
     Reserved,
@@ -116,11 +178,31 @@ pub enum PRF {
     Private(u16),
 }
 
+impl PRF {
+    /// Output length, which is also the preferred key length.
+    pub fn output_length(&self) -> Option<usize> {
+        Some(match self {
+            PRF::PRF_HMAC_MD5 | PRF::PRF_AES128_XCBC | PRF::PRF_AES128_CMAC => 16,
+            PRF::PRF_HMAC_SHA1 => 20,
+            PRF::PRF_HMAC_TIGER => 24,
+            PRF::PRF_HMAC_SHA2_256 => 32,
+            PRF::PRF_HMAC_SHA2_384 => 48,
+            PRF::PRF_HMAC_SHA2_512 | PRF::PRF_HMAC_STREEBOG_512 => 64,
+            _ => return None,
+        })
+    }
+}
+
 /// Transform Type 3 - Integrity Algorithm Transform IDs
 /// Reference: https://www.iana.org/assignments/ikev2-parameters/ikev2-parameters.xhtml#ikev2-parameters-7
 #[allow(non_camel_case_types)]
 #[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
-#[deku(id_type = "u16", endian = "endian", ctx = "endian: deku::ctx::Endian")]
+#[deku(
+    id_type = "u16",
+    id_endian = "endian",
+    endian = "endian",
+    ctx = "endian: deku::ctx::Endian"
+)]
 pub enum INTEG {
     #[deku(id = 0)]
     NONE,
@@ -158,11 +240,55 @@ pub enum INTEG {
     Private(u16),
 }
 
+impl INTEG {
+    /// Length of the integrity key, including the salt of the GMAC
+    /// algorithms.
+    pub fn key_length(&self) -> Option<usize> {
+        Some(match self {
+            INTEG::NONE => 0,
+            INTEG::AUTH_HMAC_MD5_96 | INTEG::AUTH_HMAC_MD5_128 => 16,
+            INTEG::AUTH_HMAC_SHA1_96 | INTEG::AUTH_HMAC_SHA1_160 => 20,
+            INTEG::AUTH_AES_XCBC_96 | INTEG::AUTH_AES_CMAC_96 => 16,
+            INTEG::AUTH_AES_128_GMAC => 16 + 4,
+            INTEG::AUTH_AES_192_GMAC => 24 + 4,
+            INTEG::AUTH_AES_256_GMAC => 32 + 4,
+            INTEG::AUTH_HMAC_SHA2_256_128 => 32,
+            INTEG::AUTH_HMAC_SHA2_384_192 => 48,
+            INTEG::AUTH_HMAC_SHA2_512_256 => 64,
+            _ => return None,
+        })
+    }
+
+    /// Length of the truncated ICV.
+    pub fn icv_length(&self) -> Option<usize> {
+        Some(match self {
+            INTEG::NONE => 0,
+            INTEG::AUTH_HMAC_MD5_96
+            | INTEG::AUTH_HMAC_SHA1_96
+            | INTEG::AUTH_AES_XCBC_96
+            | INTEG::AUTH_AES_CMAC_96 => 12,
+            INTEG::AUTH_HMAC_MD5_128 => 16,
+            INTEG::AUTH_HMAC_SHA1_160 => 20,
+            INTEG::AUTH_AES_128_GMAC | INTEG::AUTH_AES_192_GMAC | INTEG::AUTH_AES_256_GMAC => 16,
+            INTEG::AUTH_HMAC_SHA2_256_128 => 16,
+            INTEG::AUTH_HMAC_SHA2_384_192 => 24,
+            INTEG::AUTH_HMAC_SHA2_512_256 => 32,
+            _ => return None,
+        })
+    }
+}
+
 /// Transform Type 4 - Key Exchange Method Transform IDs
 /// Reference: https://www.iana.org/assignments/ikev2-parameters/ikev2-parameters.xhtml#ikev2-parameters-8
 #[allow(non_camel_case_types)]
 #[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
-#[deku(id_type = "u16", endian = "endian", ctx = "endian: deku::ctx::Endian")]
+#[deku(
+    id_type = "u16",
+    id_endian = "endian",
+    endian = "endian",
+    ctx = "endian: deku::ctx::Endian",
+    ctx_default = "deku::ctx::Endian::Big"
+)]
 pub enum KE {
     #[deku(id = 0)]
     NONE,
@@ -230,11 +356,50 @@ pub enum KE {
     Private(u16),
 }
 
+impl KE {
+    /// Length of the Key Exchange Data for this method, or `None` if it is
+    /// not known. ML-KEM carries the encapsulation key in requests and the
+    /// ciphertext in responses.
+    pub fn data_length(&self, response: bool) -> Option<usize> {
+        Some(match self {
+            KE::MODP_768 => 96,
+            KE::MODP_1024 | KE::MODP_1024_PRIME_160 => 128,
+            KE::MODP_1536 => 192,
+            KE::MODP_2048 | KE::MODP_2048_PRIME_224 | KE::MODP_2048_PRIME_256 => 256,
+            KE::MODP_3072 => 384,
+            KE::MODP_4096 => 512,
+            KE::MODP_6144 => 768,
+            KE::MODP_8192 => 1024,
+            KE::ECP_192 => 2 * 24,
+            KE::ECP_224 | KE::brainpoolP224r1 => 2 * 28,
+            KE::ECP_256 | KE::brainpoolP256r1 => 2 * 32,
+            KE::ECP_384 | KE::brainpoolP384r1 => 2 * 48,
+            KE::ECP_512 => 2 * 66,
+            KE::brainpoolP512r1 => 2 * 64,
+            KE::Curve25519 => 32,
+            KE::Curve448 => 56,
+            KE::GOST3410_2012_256 => 64,
+            KE::GOST3410_2012_512 => 128,
+            KE::ML_KEM_512 if response => 768,
+            KE::ML_KEM_512 => 800,
+            KE::ML_KEM_768 if response => 1088,
+            KE::ML_KEM_768 => 1184,
+            KE::ML_KEM_1024 => 1568,
+            _ => return None,
+        })
+    }
+}
+
 /// Transform Type 5 - Sequence Numbers Transform IDs
 /// Reference: https://www.iana.org/assignments/ikev2-parameters/ikev2-parameters.xhtml#ikev2-parameters-9
 #[allow(non_camel_case_types)]
 #[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
-#[deku(id_type = "u16", endian = "endian", ctx = "endian: deku::ctx::Endian")]
+#[deku(
+    id_type = "u16",
+    id_endian = "endian",
+    endian = "endian",
+    ctx = "endian: deku::ctx::Endian"
+)]
 pub enum SN {
     #[deku(id = 0)]
     Sequential_32,
@@ -252,7 +417,12 @@ pub enum SN {
 /// Reference: https://www.iana.org/assignments/ikev2-parameters/ikev2-parameters.xhtml#key-wrap-algorithm-transform-ids
 #[allow(non_camel_case_types)]
 #[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
-#[deku(id_type = "u16", endian = "endian", ctx = "endian: deku::ctx::Endian")]
+#[deku(
+    id_type = "u16",
+    id_endian = "endian",
+    endian = "endian",
+    ctx = "endian: deku::ctx::Endian"
+)]
 pub enum KWA {
     #[deku(id = 0)]
     Reserved,
@@ -274,7 +444,12 @@ pub enum KWA {
 /// Reference: https://www.iana.org/assignments/ikev2-parameters/ikev2-parameters.xhtml#group-controller-authentication-method-transform-ids
 #[allow(non_camel_case_types)]
 #[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
-#[deku(id_type = "u16", endian = "endian", ctx = "endian: deku::ctx::Endian")]
+#[deku(
+    id_type = "u16",
+    id_endian = "endian",
+    endian = "endian",
+    ctx = "endian: deku::ctx::Endian"
+)]
 pub enum GCAUTH {
     #[deku(id = 0)]
     Reserved,
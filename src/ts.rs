@@ -0,0 +1,420 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use deku::prelude::*;
+
+use crate::consts::NotifyType;
+use crate::error::Error;
+
+/// Traffic Selector payload body, shared by TSi and TSr
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(endian = "big")]
+pub struct TsPayload {
+    #[deku(update = "self.traffic_selectors.len()")]
+    pub number_of_ts: u8,
+    pub reserved: [u8; 3],
+    #[deku(count = "number_of_ts")]
+    pub traffic_selectors: Vec<TrafficSelector>,
+}
+
+/// Traffic Selector, keyed by TS Type
+/// Reference: https://www.iana.org/assignments/ikev2-parameters/ikev2-parameters.xhtml#ikev2-parameters-12
+#[deku_derive(DekuRead, DekuWrite)]
+#[derive(Clone, Debug, PartialEq)]
+#[deku(id_type = "u8", endian = "endian", ctx = "endian: deku::ctx::Endian")]
+pub enum TrafficSelector {
+    #[deku(id = 7)]
+    Ipv4AddrRange {
+        ip_protocol_id: u8,
+        #[deku(temp, temp_value = "16", map = "|len| fixed_length(len, 16)")]
+        selector_length: u16,
+        start_port: u16,
+        end_port: u16,
+        starting_address: Ipv4Addr,
+        ending_address: Ipv4Addr,
+    },
+    #[deku(id = 8)]
+    Ipv6AddrRange {
+        ip_protocol_id: u8,
+        #[deku(temp, temp_value = "40", map = "|len| fixed_length(len, 40)")]
+        selector_length: u16,
+        start_port: u16,
+        end_port: u16,
+        starting_address: Ipv6Addr,
+        ending_address: Ipv6Addr,
+    },
+    #[deku(id = 9)]
+    FcAddrRange {
+        reserved_0: u8,
+        #[deku(temp, temp_value = "16", map = "|len| fixed_length(len, 16)")]
+        selector_length: u16,
+        reserved_1: u8,
+        starting_address: [u8; 3],
+        reserved_2: u8,
+        ending_address: [u8; 3],
+        starting_r_ctl: u8,
+        ending_r_ctl: u8,
+        starting_type: u8,
+        ending_type: u8,
+    },
+    #[deku(id = 10)]
+    SecLabel {
+        reserved: u8,
+        #[deku(temp, temp_value = "u16::try_from(security_label.len() + 4)?")]
+        selector_length: u16,
+        #[deku(count = "data_length(*selector_length)?")]
+        security_label: Vec<u8>,
+    },
+    /// Reserved, unassigned and private TS Types, kept verbatim.
+    #[deku(id_pat = "_")]
+    Other {
+        ts_type: u8,
+        reserved: u8,
+        #[deku(temp, temp_value = "u16::try_from(data.len() + 4)?")]
+        selector_length: u16,
+        #[deku(count = "data_length(*selector_length)?")]
+        data: Vec<u8>,
+    },
+}
+
+impl TrafficSelector {
+    /// Selector covering every protocol and port of an address prefix.
+    /// Fails if the prefix is longer than the address.
+    pub fn from_prefix(address: IpAddr, prefix_len: u8) -> Result<Self, Error> {
+        let bits: u32 = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let host_bits = bits
+            .checked_sub(u32::from(prefix_len))
+            .ok_or(Error::InvalidPrefixLength(prefix_len))?;
+        Ok(match address {
+            IpAddr::V4(address) => {
+                let mask = u32::MAX.checked_shl(host_bits).unwrap_or(0);
+                let start = u32::from(address) & mask;
+                TrafficSelector::Ipv4AddrRange {
+                    ip_protocol_id: 0,
+                    start_port: 0,
+                    end_port: u16::MAX,
+                    starting_address: start.into(),
+                    ending_address: (start | !mask).into(),
+                }
+            }
+            IpAddr::V6(address) => {
+                let mask = u128::MAX.checked_shl(host_bits).unwrap_or(0);
+                let start = u128::from(address) & mask;
+                TrafficSelector::Ipv6AddrRange {
+                    ip_protocol_id: 0,
+                    start_port: 0,
+                    end_port: u16::MAX,
+                    starting_address: start.into(),
+                    ending_address: (start | !mask).into(),
+                }
+            }
+        })
+    }
+
+    /// Largest selector contained in both `self` and `other`. Address range
+    /// types intersect field by field; other types only intersect with an
+    /// identical selector.
+    pub fn intersection(&self, other: &Self) -> Option<Self> {
+        match (self, other) {
+            (
+                TrafficSelector::Ipv4AddrRange {
+                    ip_protocol_id: a_protocol,
+                    start_port: a_start_port,
+                    end_port: a_end_port,
+                    starting_address: a_start,
+                    ending_address: a_end,
+                },
+                TrafficSelector::Ipv4AddrRange {
+                    ip_protocol_id: b_protocol,
+                    start_port: b_start_port,
+                    end_port: b_end_port,
+                    starting_address: b_start,
+                    ending_address: b_end,
+                },
+            ) => {
+                let (start_port, end_port) =
+                    intersect_ports((*a_start_port, *a_end_port), (*b_start_port, *b_end_port))?;
+                let (starting_address, ending_address) =
+                    intersect_range((*a_start, *a_end), (*b_start, *b_end))?;
+                Some(TrafficSelector::Ipv4AddrRange {
+                    ip_protocol_id: intersect_protocol(*a_protocol, *b_protocol)?,
+                    start_port,
+                    end_port,
+                    starting_address,
+                    ending_address,
+                })
+            }
+            (
+                TrafficSelector::Ipv6AddrRange {
+                    ip_protocol_id: a_protocol,
+                    start_port: a_start_port,
+                    end_port: a_end_port,
+                    starting_address: a_start,
+                    ending_address: a_end,
+                },
+                TrafficSelector::Ipv6AddrRange {
+                    ip_protocol_id: b_protocol,
+                    start_port: b_start_port,
+                    end_port: b_end_port,
+                    starting_address: b_start,
+                    ending_address: b_end,
+                },
+            ) => {
+                let (start_port, end_port) =
+                    intersect_ports((*a_start_port, *a_end_port), (*b_start_port, *b_end_port))?;
+                let (starting_address, ending_address) =
+                    intersect_range((*a_start, *a_end), (*b_start, *b_end))?;
+                Some(TrafficSelector::Ipv6AddrRange {
+                    ip_protocol_id: intersect_protocol(*a_protocol, *b_protocol)?,
+                    start_port,
+                    end_port,
+                    starting_address,
+                    ending_address,
+                })
+            }
+            _ if self == other => Some(self.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn is_subset_of(&self, other: &Self) -> bool {
+        self.intersection(other).as_ref() == Some(self)
+    }
+}
+
+fn fixed_length(selector_length: u16, expected: u16) -> Result<u16, DekuError> {
+    if selector_length != expected {
+        return Err(DekuError::Assertion("unexpected selector length".into()));
+    }
+    Ok(selector_length)
+}
+
+/// Length of the data following the generic selector header.
+fn data_length(selector_length: u16) -> Result<u16, DekuError> {
+    selector_length
+        .checked_sub(4)
+        .ok_or(DekuError::Assertion("selector length below 4".into()))
+}
+
+/// Protocol 0 matches any protocol.
+fn intersect_protocol(a: u8, b: u8) -> Option<u8> {
+    match (a, b) {
+        (0, p) | (p, 0) => Some(p),
+        _ if a == b => Some(a),
+        _ => None,
+    }
+}
+
+/// A start port above the end port is OPAQUE, which is only covered by
+/// another OPAQUE range or by the full port range.
+fn intersect_ports(a: (u16, u16), b: (u16, u16)) -> Option<(u16, u16)> {
+    const ANY: (u16, u16) = (0, u16::MAX);
+    match (a.0 > a.1, b.0 > b.1) {
+        (true, true) => Some(a),
+        (true, false) if b == ANY => Some(a),
+        (false, true) if a == ANY => Some(b),
+        (false, false) => intersect_range(a, b),
+        _ => None,
+    }
+}
+
+fn intersect_range<T: Ord + Copy>(a: (T, T), b: (T, T)) -> Option<(T, T)> {
+    let start = a.0.max(b.0);
+    let end = a.1.min(b.1);
+    (start <= end).then_some((start, end))
+}
+
+/// Narrows the selectors proposed in one TS payload to those allowed by the
+/// local policy, as a responder does per RFC 7296 §2.9.
+///
+/// When the first proposed selector lies entirely within some policy entries
+/// (typically because it describes the packet that triggered the exchange),
+/// only those entries are used. Results covered by another result are
+/// dropped. Fails with TS_UNACCEPTABLE if nothing is left.
+pub fn narrow(
+    proposed: &[TrafficSelector],
+    policy: &[TrafficSelector],
+) -> Result<Vec<TrafficSelector>, Error> {
+    let mut policy: Vec<_> = policy.iter().collect();
+    if let Some(first) = proposed.first() {
+        let matching: Vec<_> = policy
+            .iter()
+            .copied()
+            .filter(|entry| first.is_subset_of(entry))
+            .collect();
+        if !matching.is_empty() {
+            policy = matching;
+        }
+    }
+
+    let mut narrowed: Vec<TrafficSelector> = vec![];
+    for selector in proposed {
+        for entry in &policy {
+            let Some(selector) = selector.intersection(entry) else {
+                continue;
+            };
+            if narrowed.iter().any(|n| selector.is_subset_of(n)) {
+                continue;
+            }
+            narrowed.retain(|n| !n.is_subset_of(&selector));
+            narrowed.push(selector);
+        }
+    }
+    if narrowed.is_empty() {
+        return Err(Error::Notify(NotifyType::TS_UNACCEPTABLE));
+    }
+    Ok(narrowed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn v4(start: &str, end: &str, ports: (u16, u16)) -> TrafficSelector {
+        TrafficSelector::Ipv4AddrRange {
+            ip_protocol_id: 0,
+            start_port: ports.0,
+            end_port: ports.1,
+            starting_address: start.parse().unwrap(),
+            ending_address: end.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_ts_payload() {
+        let data = [
+            0x3, 0x0, 0x0, 0x0, // 3 selectors
+            0x7, 0x6, 0x0, 0x10, 0x0, 0x0, 0xff, 0xff, 0xc0, 0x0, 0x2, 0x0, 0xc0, 0x0, 0x2, 0xff,
+            0xa, 0x0, 0x0, 0x7, 0x61, 0x62, 0x63, // TS_SECLABEL "abc"
+            0xf0, 0x1, 0x0, 0x5, 0xee,
+        ];
+        let mut payload = TsPayload::try_from(&data[..]).unwrap();
+        assert_eq!(
+            payload.traffic_selectors,
+            vec![
+                TrafficSelector::Ipv4AddrRange {
+                    ip_protocol_id: 6,
+                    start_port: 0,
+                    end_port: 65535,
+                    starting_address: Ipv4Addr::new(192, 0, 2, 0),
+                    ending_address: Ipv4Addr::new(192, 0, 2, 255),
+                },
+                TrafficSelector::SecLabel {
+                    reserved: 0,
+                    security_label: b"abc".to_vec(),
+                },
+                TrafficSelector::Other {
+                    ts_type: 0xf0,
+                    reserved: 1,
+                    data: vec![0xee],
+                },
+            ]
+        );
+        assert_eq!(payload.to_bytes().unwrap(), data);
+
+        payload
+            .traffic_selectors
+            .push(TrafficSelector::from_prefix("2001:db8::".parse().unwrap(), 32).unwrap());
+        payload.update().unwrap();
+        let data = payload.to_bytes().unwrap();
+        assert_eq!(data[0], 4);
+        assert_eq!(TsPayload::try_from(&data[..]).unwrap(), payload);
+
+        // TS_IPV4_ADDR_RANGE must be 16 bytes long.
+        assert!(TsPayload::try_from(&[0x1, 0, 0, 0, 0x7, 0, 0, 0x14][..]).is_err());
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let net = TrafficSelector::from_prefix("10.1.0.0".parse().unwrap(), 16).unwrap();
+        assert_eq!(net, v4("10.1.0.0", "10.1.255.255", (0, 65535)));
+        let host_route = TrafficSelector::from_prefix("10.1.2.3".parse().unwrap(), 32).unwrap();
+        assert_eq!(host_route, v4("10.1.2.3", "10.1.2.3", (0, 65535)));
+        for (address, prefix_len) in [("10.0.0.0", 33), ("10.0.0.0", 255), ("2001:db8::", 129)] {
+            assert_eq!(
+                TrafficSelector::from_prefix(address.parse().unwrap(), prefix_len),
+                Err(Error::InvalidPrefixLength(prefix_len))
+            );
+        }
+        let host = v4("10.1.2.3", "10.1.2.3", (443, 443));
+        let other = v4("10.1.128.0", "10.2.0.255", (1024, 65535));
+
+        assert!(host.is_subset_of(&net));
+        assert!(!net.is_subset_of(&host));
+        assert!(!other.is_subset_of(&net));
+        assert_eq!(
+            net.intersection(&other),
+            Some(v4("10.1.128.0", "10.1.255.255", (1024, 65535)))
+        );
+        assert_eq!(host.intersection(&other), None);
+
+        let tcp = TrafficSelector::Ipv4AddrRange {
+            ip_protocol_id: 6,
+            start_port: 0,
+            end_port: 65535,
+            starting_address: Ipv4Addr::UNSPECIFIED,
+            ending_address: Ipv4Addr::BROADCAST,
+        };
+        let udp = TrafficSelector::Ipv4AddrRange {
+            ip_protocol_id: 17,
+            start_port: 0,
+            end_port: 65535,
+            starting_address: Ipv4Addr::UNSPECIFIED,
+            ending_address: Ipv4Addr::BROADCAST,
+        };
+        assert_eq!(tcp.intersection(&udp), None);
+        assert_eq!(
+            net.intersection(&tcp),
+            Some(TrafficSelector::Ipv4AddrRange {
+                ip_protocol_id: 6,
+                start_port: 0,
+                end_port: 65535,
+                starting_address: Ipv4Addr::new(10, 1, 0, 0),
+                ending_address: Ipv4Addr::new(10, 1, 255, 255),
+            })
+        );
+
+        let opaque = v4("10.1.0.0", "10.1.255.255", (65535, 0));
+        assert!(opaque.is_subset_of(&net));
+        assert!(!net.is_subset_of(&opaque));
+        assert_eq!(opaque.intersection(&other), None);
+
+        let v6 = TrafficSelector::from_prefix("2001:db8::".parse().unwrap(), 32).unwrap();
+        assert_eq!(v6.intersection(&net), None);
+    }
+
+    #[test]
+    fn test_narrow() {
+        let policy = [
+            v4("10.1.0.0", "10.1.255.255", (0, 65535)),
+            v4("10.3.0.0", "10.3.255.255", (0, 65535)),
+        ];
+
+        // Everything is proposed; the responder narrows to its policy.
+        let any = TrafficSelector::from_prefix(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0).unwrap();
+        assert_eq!(
+            narrow(std::slice::from_ref(&any), &policy),
+            Ok(policy.to_vec())
+        );
+
+        // The triggering packet selects the policy entry that contains it.
+        let packet = v4("10.3.4.5", "10.3.4.5", (80, 80));
+        assert_eq!(
+            narrow(&[packet.clone(), any], &policy),
+            Ok(vec![policy[1].clone()])
+        );
+
+        // Proposals already inside the policy are kept as they are.
+        assert_eq!(
+            narrow(std::slice::from_ref(&packet), &policy),
+            Ok(vec![packet])
+        );
+
+        assert_eq!(
+            narrow(&[v4("10.2.0.0", "10.2.255.255", (0, 65535))], &policy),
+            Err(Error::Notify(NotifyType::TS_UNACCEPTABLE))
+        );
+    }
+}
@@ -1,84 +1,549 @@
-use std::{num::NonZeroU64, u8};
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    num::NonZeroU64,
+};
 
 use crate::consts::*;
+use crate::error::Error;
+use crate::message::PAYLOAD_HEADER_LENGTH;
+use crate::transform;
+use deku::ctx::Limit;
+use deku::no_std_io::{Read, Seek, Write};
 use deku::prelude::*;
+use rand_core::{OsRng, RngCore};
 
-#[derive(Debug, PartialEq, DekuRead, DekuWrite)]
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
 #[deku(endian = "big")]
 pub struct IKEHeader {
-    initiator_spi: NonZeroU64,
-    responder_spi: u64,
-    next_payload: PayloadType,
+    pub initiator_spi: NonZeroU64,
+    pub responder_spi: u64,
+    pub next_payload: PayloadType,
     #[deku(bits = 4)]
-    major_version: u8,
+    pub major_version: u8,
     #[deku(bits = 4)]
-    minor_version: u8,
-    exchange_type: ExchangeType,
-    flags: Flags,
-    message_id: u32,
-    length: u32,
+    pub minor_version: u8,
+    pub exchange_type: ExchangeType,
+    pub flags: Flags,
+    pub message_id: u32,
+    pub length: u32,
 }
 
 #[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
 #[deku(endian = "big")]
 pub struct PayloadHeader {
-    next_payload: PayloadType,
+    pub next_payload: PayloadType,
     #[deku(bits = 1)]
-    critical: bool,
+    pub critical: bool,
     #[deku(bits = 7)]
-    reserved: u8,
-    payload_length: u16,
+    pub reserved: u8,
+    pub payload_length: u16,
 }
 
+/// Security Association payload body
 #[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
 #[deku(endian = "big")]
-pub struct Proposal {
-    last_substructure: LastSubstructure,
-    reserved: u8,
-    proposal_length: u16,
-    proposal_num: u8,
-    protocol_id: ProtocolIdentifier,
+pub struct SaPayload {
+    #[deku(read_all, update = "updated_proposals(&self.proposals)?")]
+    pub proposals: Vec<Proposal>,
+}
+
+impl SaPayload {
+    /// Checks proposal numbering, the declared proposal and transform lengths
+    /// and the `LastSubstructure` markers of both lists.
+    pub fn validate(&self) -> Result<(), Error> {
+        for (i, proposal) in self.proposals.iter().enumerate() {
+            let expected = u8::try_from(i + 1).unwrap_or(u8::MAX);
+            if proposal.proposal_num != expected {
+                return Err(Error::ProposalNumber {
+                    expected,
+                    actual: proposal.proposal_num,
+                });
+            }
+            check_marker(
+                &proposal.last_substructure,
+                i + 1 == self.proposals.len(),
+                LastSubstructure::Proposal,
+            )?;
+            let mut length = 8 + proposal.spi.len();
+            for (j, transform) in proposal.transforms.iter().enumerate() {
+                check_marker(
+                    &transform.last_substructure,
+                    j + 1 == proposal.transforms.len(),
+                    LastSubstructure::Transform,
+                )?;
+                length += usize::from(transform.transform_length);
+            }
+            if usize::from(proposal.proposal_length) != length {
+                return Err(Error::SubstructureLength {
+                    declared: proposal.proposal_length,
+                    actual: length,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_marker(
+    actual: &LastSubstructure,
+    last: bool,
+    more: LastSubstructure,
+) -> Result<(), Error> {
+    let expected = if last { LastSubstructure::Last } else { more };
+    if *actual != expected {
+        return Err(Error::SubstructureMarker {
+            expected,
+            actual: actual.clone(),
+        });
+    }
+    Ok(())
+}
+
+impl Proposal {
+    /// The first ENCR transform and its Key Length attribute.
+    pub fn encr(&self) -> Option<(&transform::ENCR, Option<u16>)> {
+        self.transforms
+            .iter()
+            .find_map(|t| match &t.transform_type {
+                TransformType::ENCR(_, encr) => Some((encr, t.key_length())),
+                _ => None,
+            })
+    }
+
+    pub fn prf(&self) -> Option<&transform::PRF> {
+        self.transforms
+            .iter()
+            .find_map(|t| match &t.transform_type {
+                TransformType::PRF(_, prf) => Some(prf),
+                _ => None,
+            })
+    }
+
+    pub fn integ(&self) -> Option<&transform::INTEG> {
+        self.transforms
+            .iter()
+            .find_map(|t| match &t.transform_type {
+                TransformType::INTEG(_, integ) => Some(integ),
+                _ => None,
+            })
+    }
+
+    pub fn ke(&self) -> Option<&transform::KE> {
+        self.transforms
+            .iter()
+            .find_map(|t| match &t.transform_type {
+                TransformType::KE(_, ke) => Some(ke),
+                _ => None,
+            })
+    }
+
+    /// The methods of the additional key exchanges in the order they are
+    /// performed, from ADDKE1 to ADDKE7, leaving out those negotiated as NONE.
+    pub fn additional_key_exchanges(&self) -> Vec<&transform::KE> {
+        (1..=7)
+            .filter_map(|n| {
+                self.transforms.iter().find_map(|t| {
+                    t.transform_type
+                        .additional_key_exchange()
+                        .filter(|(m, _)| *m == n)
+                        .map(|(_, ke)| ke)
+                })
+            })
+            .filter(|ke| **ke != transform::KE::NONE)
+            .collect()
+    }
+}
+
+/// Returns a copy of the proposal list with lengths, counts and
+/// `LastSubstructure` markers recomputed.
+fn updated_proposals(proposals: &[Proposal]) -> Result<Vec<Proposal>, DekuError> {
+    let mut proposals = proposals.to_vec();
+    let num_proposals = proposals.len();
+    for (i, proposal) in proposals.iter_mut().enumerate() {
+        proposal.last_substructure = if i + 1 == num_proposals {
+            LastSubstructure::Last
+        } else {
+            LastSubstructure::Proposal
+        };
+        let num_transforms = proposal.transforms.len();
+        for (j, transform) in proposal.transforms.iter_mut().enumerate() {
+            transform.last_substructure = if j + 1 == num_transforms {
+                LastSubstructure::Last
+            } else {
+                LastSubstructure::Transform
+            };
+            transform.update()?;
+        }
+        proposal.update()?;
+    }
+    Ok(proposals)
+}
+
+/// Key Exchange payload body
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(endian = "big")]
+pub struct KePayload {
+    pub dh_group: transform::KE,
+    pub reserved: u16,
+    #[deku(read_all)]
+    pub data: Vec<u8>,
+}
+
+impl KePayload {
+    /// Checks the Key Exchange Data length against the method. `response`
+    /// selects the ML-KEM ciphertext rather than the encapsulation key.
+    pub fn validate(&self, response: bool) -> Result<(), Error> {
+        match self.dh_group.data_length(response) {
+            Some(expected) if expected != self.data.len() => Err(Error::KeyExchangeLength {
+                group: self.dh_group.clone(),
+                expected,
+                actual: self.data.len(),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Shortest and longest Nonce Data allowed by RFC 7296 §3.9.
+pub const MIN_NONCE_LENGTH: usize = 16;
+pub const MAX_NONCE_LENGTH: usize = 256;
+
+/// Nonce payload body
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(endian = "big")]
+pub struct NoncePayload {
+    #[deku(read_all)]
+    pub nonce: Vec<u8>,
+}
+
+impl NoncePayload {
+    /// A nonce of `length` random bytes.
+    pub fn random(length: usize) -> Result<Self, Error> {
+        let mut nonce = vec![0; length];
+        OsRng.fill_bytes(&mut nonce);
+        let payload = NoncePayload { nonce };
+        payload.validate()?;
+        Ok(payload)
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if !(MIN_NONCE_LENGTH..=MAX_NONCE_LENGTH).contains(&self.nonce.len()) {
+            return Err(Error::InvalidNonceLength(self.nonce.len()));
+        }
+        Ok(())
+    }
+}
+
+/// Identification payload body, shared by IDi and IDr
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(endian = "big")]
+pub struct IdPayload {
+    pub id_type: IdType,
+    pub reserved: [u8; 3],
+    #[deku(read_all)]
+    pub data: Vec<u8>,
+}
+
+/// Decoded Identification Data
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Identity {
+    Ipv4(Ipv4Addr),
+    Fqdn(String),
+    Rfc822(String),
+    Ipv6(Ipv6Addr),
+    /// DER encoding of an ASN.1 X.500 Distinguished Name
+    DerAsn1Dn(Vec<u8>),
+    KeyId(Vec<u8>),
+    Other(IdType, Vec<u8>),
+}
+
+impl IdPayload {
+    pub fn identity(&self) -> Result<Identity, Error> {
+        let invalid = || Error::InvalidIdentity(self.id_type.clone());
+        Ok(match self.id_type {
+            IdType::ID_IPV4_ADDR => Identity::Ipv4(
+                <[u8; 4]>::try_from(&self.data[..])
+                    .map_err(|_| invalid())?
+                    .into(),
+            ),
+            IdType::ID_IPV6_ADDR => Identity::Ipv6(
+                <[u8; 16]>::try_from(&self.data[..])
+                    .map_err(|_| invalid())?
+                    .into(),
+            ),
+            IdType::ID_FQDN => {
+                let name = std::str::from_utf8(&self.data).map_err(|_| invalid())?;
+                if !is_fqdn(name) {
+                    return Err(invalid());
+                }
+                Identity::Fqdn(name.to_string())
+            }
+            IdType::ID_RFC822_ADDR => {
+                let address = std::str::from_utf8(&self.data).map_err(|_| invalid())?;
+                match address.split_once('@') {
+                    Some((local, domain))
+                        if !local.is_empty()
+                            && local.bytes().all(|c| c.is_ascii_graphic() && c != b'@')
+                            && is_fqdn(domain) => {}
+                    _ => return Err(invalid()),
+                }
+                Identity::Rfc822(address.to_string())
+            }
+            IdType::ID_DER_ASN1_DN => Identity::DerAsn1Dn(self.data.clone()),
+            IdType::ID_KEY_ID => Identity::KeyId(self.data.clone()),
+            _ => Identity::Other(self.id_type.clone(), self.data.clone()),
+        })
+    }
+}
+
+impl From<Identity> for IdPayload {
+    fn from(identity: Identity) -> Self {
+        let (id_type, data) = match identity {
+            Identity::Ipv4(addr) => (IdType::ID_IPV4_ADDR, addr.octets().to_vec()),
+            Identity::Fqdn(name) => (IdType::ID_FQDN, name.into_bytes()),
+            Identity::Rfc822(address) => (IdType::ID_RFC822_ADDR, address.into_bytes()),
+            Identity::Ipv6(addr) => (IdType::ID_IPV6_ADDR, addr.octets().to_vec()),
+            Identity::DerAsn1Dn(dn) => (IdType::ID_DER_ASN1_DN, dn),
+            Identity::KeyId(key_id) => (IdType::ID_KEY_ID, key_id),
+            Identity::Other(id_type, data) => (id_type, data),
+        };
+        IdPayload {
+            id_type,
+            reserved: [0; 3],
+            data,
+        }
+    }
+}
+
+/// Accepts dot-separated labels of letters, digits, hyphens and underscores.
+fn is_fqdn(name: &str) -> bool {
+    let name = name.strip_suffix('.').unwrap_or(name);
+    !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_')
+        })
+}
+
+/// Notify payload body
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(endian = "big")]
+pub struct NotifyPayload {
+    pub protocol_id: ProtocolIdentifier,
     #[deku(update = "self.spi.len()")]
+    pub spi_size: u8,
+    pub notify_type: NotifyType,
+    #[deku(count = "spi_size")]
+    pub spi: Vec<u8>,
+    #[deku(read_all)]
+    pub data: Vec<u8>,
+}
+
+/// Delete payload body
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(endian = "big")]
+pub struct DeletePayload {
+    pub protocol_id: ProtocolIdentifier,
+    pub spi_size: u8,
+    #[deku(update = "self.spis.len()")]
+    pub num_of_spis: u16,
+    #[deku(
+        reader = "read_spis(deku::reader, *spi_size, *num_of_spis)",
+        writer = "write_spis(deku::writer, &self.spis)"
+    )]
+    pub spis: Vec<Vec<u8>>,
+}
+
+impl DeletePayload {
+    /// Deletes the IKE SA the payload is sent under.
+    pub fn ike() -> Self {
+        DeletePayload {
+            protocol_id: ProtocolIdentifier::IKE,
+            spi_size: 0,
+            num_of_spis: 0,
+            spis: vec![],
+        }
+    }
+
+    /// Deletes the ESP or AH SAs with the given inbound SPIs, as many as fit
+    /// the 16-bit Payload Length.
+    pub fn child_sas(protocol_id: ProtocolIdentifier, spis: &[[u8; 4]]) -> Result<Self, Error> {
+        let length = PAYLOAD_HEADER_LENGTH + 4 + 4 * spis.len();
+        if length > usize::from(u16::MAX) {
+            return Err(Error::PayloadTooLong(PayloadType::D, length));
+        }
+        Ok(DeletePayload {
+            protocol_id,
+            spi_size: 4,
+            num_of_spis: spis.len() as u16,
+            spis: spis.iter().map(|spi| spi.to_vec()).collect(),
+        })
+    }
+
+    /// Checks the SPI size against the protocol: an IKE SA is deleted
+    /// without SPIs, ESP and AH SAs by their 4-byte SPIs.
+    pub fn validate(&self) -> Result<(), Error> {
+        let expected = match self.protocol_id {
+            ProtocolIdentifier::IKE => 0,
+            ProtocolIdentifier::AH | ProtocolIdentifier::ESP => 4,
+            _ => self.spi_size,
+        };
+        let ike_with_spis = self.protocol_id == ProtocolIdentifier::IKE && !self.spis.is_empty();
+        if self.spi_size != expected
+            || ike_with_spis
+            || self
+                .spis
+                .iter()
+                .any(|spi| spi.len() != usize::from(expected))
+        {
+            return Err(Error::InvalidSpiSize(
+                self.protocol_id.clone(),
+                self.spi_size,
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn read_spis<R: Read + Seek>(
+    reader: &mut Reader<R>,
     spi_size: u8,
+    num_of_spis: u16,
+) -> Result<Vec<Vec<u8>>, DekuError> {
+    (0..num_of_spis)
+        .map(|_| Vec::from_reader_with_ctx(reader, Limit::new_count(usize::from(spi_size))))
+        .collect()
+}
+
+fn write_spis<W: Write + Seek>(writer: &mut Writer<W>, spis: &[Vec<u8>]) -> Result<(), DekuError> {
+    spis.concat().to_writer(writer, ())
+}
+
+/// Vendor ID payload body
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(endian = "big")]
+pub struct VendorIdPayload {
+    #[deku(read_all)]
+    pub vendor_id: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
+#[deku(
+    endian = "endian",
+    ctx = "endian: deku::ctx::Endian",
+    ctx_default = "deku::ctx::Endian::Big"
+)]
+pub struct Proposal {
+    pub last_substructure: LastSubstructure,
+    pub reserved: u8,
+    #[deku(
+        update = "8 + self.spi.len() + self.transforms.iter().map(Transform::length).sum::<usize>()"
+    )]
+    pub proposal_length: u16,
+    pub proposal_num: u8,
+    pub protocol_id: ProtocolIdentifier,
+    #[deku(update = "self.spi.len()")]
+    pub spi_size: u8,
     #[deku(update = "self.transforms.len()")]
-    num_transforms: u8,
+    pub num_transforms: u8,
     #[deku(count = "spi_size")]
-    spi: Vec<u8>,
+    pub spi: Vec<u8>,
     #[deku(count = "num_transforms")]
-    transforms: Vec<Transform>,
+    pub transforms: Vec<Transform>,
 }
 
 #[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
 #[deku(endian = "endian", ctx = "endian: deku::ctx::Endian")]
 pub struct Transform {
-    last_substructure: LastSubstructure,
-    reserved_0: u8,
-    #[deku(update = "self.transform_attributes.len() + 8")]
-    transform_length: u16,
-    transform_type: TransformType,
-    #[deku(count = "transform_length - 8")]
-    transform_attributes: Vec<u8>,
+    pub last_substructure: LastSubstructure,
+    pub reserved_0: u8,
+    #[deku(assert = "*transform_length >= 8", update = "self.length()")]
+    pub transform_length: u16,
+    pub transform_type: TransformType,
+    #[deku(bytes_read = "transform_length - 8")]
+    pub transform_attributes: Vec<Attribute>,
 }
 
-#[derive(Debug, PartialEq, DekuRead, DekuWrite)]
+impl Transform {
+    /// Encoded length of the transform including its attributes.
+    pub fn length(&self) -> usize {
+        8 + self
+            .transform_attributes
+            .iter()
+            .map(Attribute::length)
+            .sum::<usize>()
+    }
+
+    pub fn key_length(&self) -> Option<u16> {
+        self.transform_attributes.iter().find_map(|a| match a {
+            Attribute::KeyLength(bits) => Some(*bits),
+            _ => None,
+        })
+    }
+}
+
+/// Transform Attribute, in either the TV (AF=1) or TLV (AF=0) format
+/// Reference: https://www.iana.org/assignments/ikev2-parameters/ikev2-parameters.xhtml#ikev2-parameters-21
+#[deku_derive(DekuRead, DekuWrite)]
+#[derive(Clone, Debug, PartialEq)]
+#[deku(
+    id_type = "u16",
+    id_endian = "endian",
+    endian = "endian",
+    ctx = "endian: deku::ctx::Endian"
+)]
+pub enum Attribute {
+    /// Key length in bits
+    #[deku(id = 0x800e)]
+    KeyLength(u16),
+    /// Unknown TV attribute; the type keeps the AF bit set.
+    #[deku(id_pat = "0x8000..=0xffff")]
+    TV(u16, u16),
+    /// Unknown TLV attribute
+    #[deku(id_pat = "_")]
+    TLV {
+        attribute_type: u16,
+        #[deku(temp, temp_value = "u16::try_from(value.len())?")]
+        length: u16,
+        #[deku(count = "length")]
+        value: Vec<u8>,
+    },
+}
+
+impl Attribute {
+    /// Encoded length of the attribute.
+    pub fn length(&self) -> usize {
+        match self {
+            Attribute::KeyLength(_) | Attribute::TV(..) => 4,
+            Attribute::TLV { value, .. } => 4 + value.len(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, DekuRead, DekuWrite)]
 #[deku(endian = "endian", ctx = "endian: deku::ctx::Endian")]
 pub struct Flags {
     #[deku(bits = 1)]
-    unused_0: bool,
+    pub unused_0: bool,
     #[deku(bits = 1)]
-    unused_1: bool,
+    pub unused_1: bool,
     #[deku(bits = 1)]
-    response: bool,
+    pub response: bool,
     #[deku(bits = 1)]
-    version: bool,
+    pub version: bool,
     #[deku(bits = 1)]
-    initiator: bool,
+    pub initiator: bool,
     #[deku(bits = 1)]
-    unused_2: bool,
+    pub unused_2: bool,
     #[deku(bits = 1)]
-    unused_3: bool,
+    pub unused_3: bool,
     #[deku(bits = 1)]
-    unused_4: bool,
+    pub unused_4: bool,
 }
 
 #[cfg(test)]
@@ -294,4 +759,305 @@ mod test {
             ]
         )
     }
+
+    #[test]
+    fn test_sa_payload_validate() {
+        let body = &include_bytes!("testdata/ike_sa_init.bin")[32..776];
+        let sa = SaPayload::try_from(body).unwrap();
+        assert_eq!(sa.validate(), Ok(()));
+        assert_eq!(sa.to_bytes().unwrap(), body);
+
+        let mut bad = sa.clone();
+        bad.proposals[1].proposal_num = 3;
+        assert_eq!(
+            bad.validate(),
+            Err(Error::ProposalNumber {
+                expected: 2,
+                actual: 3
+            })
+        );
+
+        let mut bad = sa.clone();
+        bad.proposals[0].last_substructure = LastSubstructure::Last;
+        assert_eq!(
+            bad.validate(),
+            Err(Error::SubstructureMarker {
+                expected: LastSubstructure::Proposal,
+                actual: LastSubstructure::Last
+            })
+        );
+
+        let mut bad = sa.clone();
+        bad.proposals[1].transforms[0].last_substructure = LastSubstructure::Last;
+        assert_eq!(
+            bad.validate(),
+            Err(Error::SubstructureMarker {
+                expected: LastSubstructure::Transform,
+                actual: LastSubstructure::Last
+            })
+        );
+
+        let mut bad = sa.clone();
+        bad.proposals[0].proposal_length += 4;
+        assert_eq!(
+            bad.validate(),
+            Err(Error::SubstructureLength {
+                declared: 364,
+                actual: 360
+            })
+        );
+
+        bad.update().unwrap();
+        assert_eq!(bad, sa);
+    }
+
+    #[test]
+    fn test_transform_attributes() {
+        let body = &include_bytes!("testdata/ike_sa_init.bin")[32..776];
+        let sa = SaPayload::try_from(body).unwrap();
+        let transform = &sa.proposals[0].transforms[1];
+        assert_eq!(
+            transform.transform_type,
+            TransformType::ENCR(0, crate::transform::ENCR::ENCR_AES_CBC)
+        );
+        assert_eq!(transform.transform_attributes, [Attribute::KeyLength(192)]);
+        assert_eq!(transform.key_length(), Some(192));
+        assert_eq!(sa.proposals[0].transforms[12].key_length(), None);
+
+        let data = [
+            0x80, 0x01, 0x00, 0x02, 0x00, 0x11, 0x00, 0x03, 0xaa, 0xbb, 0xcc,
+        ];
+        let mut transform = Transform {
+            last_substructure: LastSubstructure::Last,
+            reserved_0: 0,
+            transform_length: 0,
+            transform_type: TransformType::KE(0, crate::transform::KE::ECP_256),
+            transform_attributes: vec![
+                Attribute::TV(0x8001, 2),
+                Attribute::TLV {
+                    attribute_type: 0x11,
+                    value: vec![0xaa, 0xbb, 0xcc],
+                },
+            ],
+        };
+        transform.update().unwrap();
+        assert_eq!(transform.transform_length, 19);
+        let mut proposal = Proposal {
+            last_substructure: LastSubstructure::Last,
+            reserved: 0,
+            proposal_length: 0,
+            proposal_num: 1,
+            protocol_id: ProtocolIdentifier::IKE,
+            spi_size: 0,
+            num_transforms: 1,
+            spi: vec![],
+            transforms: vec![transform],
+        };
+        proposal.update().unwrap();
+        let encoded = proposal.to_bytes().unwrap();
+        assert_eq!(encoded[16..], data);
+        assert_eq!(Proposal::try_from(&encoded[..]).unwrap(), proposal);
+
+        // Additional Key Exchange 2 with ML_KEM_768 (RFC 9370)
+        let data = [0, 0, 0, 16, 1, 1, 0, 1, 0, 0, 0, 8, 7, 0, 0, 36];
+        let proposal = Proposal::try_from(&data[..]).unwrap();
+        let transform = &proposal.transforms[0];
+        assert_eq!(
+            transform.transform_type,
+            TransformType::ADDKE2(0, crate::transform::KE::ML_KEM_768)
+        );
+        assert_eq!(
+            transform.transform_type.additional_key_exchange(),
+            Some((2, &crate::transform::KE::ML_KEM_768))
+        );
+    }
+
+    #[test]
+    fn test_ke_payload() {
+        let body = &include_bytes!("testdata/ike_sa_init.bin")[780..848];
+        let mut ke = KePayload::try_from(body).unwrap();
+        assert_eq!(ke.dh_group, transform::KE::ECP_256);
+        assert_eq!(ke.data.len(), 64);
+        assert_eq!(ke.validate(false), Ok(()));
+        assert_eq!(ke.to_bytes().unwrap(), body);
+
+        ke.data.pop();
+        assert_eq!(
+            ke.validate(false),
+            Err(Error::KeyExchangeLength {
+                group: transform::KE::ECP_256,
+                expected: 64,
+                actual: 63
+            })
+        );
+
+        ke.dh_group = transform::KE::ML_KEM_768;
+        ke.data = vec![0; 1088];
+        assert_eq!(ke.validate(true), Ok(()));
+        assert!(ke.validate(false).is_err());
+
+        ke.dh_group = transform::KE::Private(1024);
+        assert_eq!(ke.validate(false), Ok(()));
+    }
+
+    #[test]
+    fn test_nonce_payload() {
+        let body = &include_bytes!("testdata/ike_sa_init.bin")[852..884];
+        let nonce = NoncePayload::try_from(body).unwrap();
+        assert_eq!(nonce.nonce, body);
+        assert_eq!(nonce.validate(), Ok(()));
+        assert_eq!(nonce.to_bytes().unwrap(), body);
+
+        for length in [MIN_NONCE_LENGTH, MAX_NONCE_LENGTH] {
+            let nonce = NoncePayload::random(length).unwrap();
+            assert_eq!(nonce.nonce.len(), length);
+        }
+        assert_ne!(NoncePayload::random(32), NoncePayload::random(32));
+        assert_eq!(NoncePayload::random(15), Err(Error::InvalidNonceLength(15)));
+        let nonce = NoncePayload {
+            nonce: vec![0; 257],
+        };
+        assert_eq!(nonce.validate(), Err(Error::InvalidNonceLength(257)));
+    }
+
+    #[test]
+    fn test_delete_payload() {
+        let ike = DeletePayload::try_from(&[1, 0, 0, 0][..]).unwrap();
+        assert_eq!(ike, DeletePayload::ike());
+        assert_eq!(ike.validate(), Ok(()));
+
+        let data = [3, 4, 0, 2, 0xc0, 0x01, 0xd0, 0x0d, 0x12, 0x34, 0x56, 0x78];
+        let mut esp = DeletePayload::try_from(&data[..]).unwrap();
+        assert_eq!(
+            esp,
+            DeletePayload::child_sas(
+                ProtocolIdentifier::ESP,
+                &[[0xc0, 0x01, 0xd0, 0x0d], [0x12, 0x34, 0x56, 0x78]]
+            )
+            .unwrap()
+        );
+        assert_eq!(esp.validate(), Ok(()));
+        assert_eq!(esp.to_bytes().unwrap(), data);
+
+        esp.spis.pop();
+        esp.update().unwrap();
+        assert_eq!(
+            esp.to_bytes().unwrap(),
+            [3, 4, 0, 1, 0xc0, 0x01, 0xd0, 0x0d]
+        );
+        assert!(DeletePayload::try_from(&data[..10]).is_err());
+
+        let invalid = [
+            (&[1, 4, 0, 1, 0, 0, 0, 1][..], ProtocolIdentifier::IKE, 4),
+            (&[3, 0, 0, 0], ProtocolIdentifier::ESP, 0),
+            (
+                &[2, 8, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1],
+                ProtocolIdentifier::AH,
+                8,
+            ),
+        ];
+        for (data, protocol_id, spi_size) in invalid {
+            let delete = DeletePayload::try_from(data).unwrap();
+            assert_eq!(
+                delete.validate(),
+                Err(Error::InvalidSpiSize(protocol_id, spi_size))
+            );
+        }
+        let ike_with_spis = DeletePayload::try_from(&[1, 0, 0, 1][..]).unwrap();
+        assert_eq!(ike_with_spis.spis, [Vec::<u8>::new()]);
+        assert_eq!(
+            ike_with_spis.validate(),
+            Err(Error::InvalidSpiSize(ProtocolIdentifier::IKE, 0))
+        );
+
+        // (65535 - 8) / 4 SPIs fit the Payload Length
+        let spis = vec![[0; 4]; 16382];
+        assert_eq!(
+            DeletePayload::child_sas(ProtocolIdentifier::ESP, &spis),
+            Err(Error::PayloadTooLong(PayloadType::D, 65536))
+        );
+        let delete = DeletePayload::child_sas(ProtocolIdentifier::ESP, &spis[1..]).unwrap();
+        assert_eq!(delete.num_of_spis, 16381);
+        assert_eq!(delete.to_bytes().unwrap().len(), 65528);
+    }
+
+    #[test]
+    fn test_vendor_id_payload() {
+        let vendor_id = VendorIdPayload::try_from(&b"example vendor"[..]).unwrap();
+        assert_eq!(vendor_id.vendor_id, b"example vendor");
+        assert_eq!(vendor_id.to_bytes().unwrap(), b"example vendor");
+    }
+
+    #[test]
+    fn test_notify_payload() {
+        let body = &include_bytes!("testdata/ike_sa_init.bin")[952..964];
+        let mut notify = NotifyPayload::try_from(body).unwrap();
+        assert_eq!(
+            notify,
+            NotifyPayload {
+                protocol_id: ProtocolIdentifier::Reserved,
+                spi_size: 0,
+                notify_type: NotifyType::SIGNATURE_HASH_ALGORITHMS,
+                spi: vec![],
+                data: vec![0x0, 0x2, 0x0, 0x3, 0x0, 0x4, 0x0, 0x5],
+            }
+        );
+        assert!(!notify.notify_type.is_error());
+
+        notify.protocol_id = ProtocolIdentifier::ESP;
+        notify.notify_type = NotifyType::PrivateError(0x2000);
+        notify.spi = vec![0xde, 0xad, 0xbe, 0xef];
+        notify.data = vec![];
+        notify.update().unwrap();
+        let data = notify.to_bytes().unwrap();
+        assert_eq!(data, [0x3, 0x4, 0x20, 0x0, 0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(NotifyPayload::try_from(&data[..]).unwrap(), notify);
+        assert!(notify.notify_type.is_error());
+        assert!(NotifyType::NO_PROPOSAL_CHOSEN.is_error());
+        assert!(!NotifyType::PrivateStatus(40960).is_error());
+    }
+
+    #[test]
+    fn test_id_payload() {
+        let id = IdPayload::try_from(&[0x1, 0x0, 0x0, 0x0, 0xc0, 0x0, 0x2, 0x1][..]).unwrap();
+        assert_eq!(
+            id.identity(),
+            Ok(Identity::Ipv4(Ipv4Addr::new(192, 0, 2, 1)))
+        );
+
+        let cases = [
+            Identity::Fqdn("vpn.example.com".to_string()),
+            Identity::Rfc822("alice@example.com".to_string()),
+            Identity::Ipv6("2001:db8::1".parse().unwrap()),
+            Identity::DerAsn1Dn(vec![0x30, 0x0]),
+            Identity::KeyId(vec![0xff, 0x0]),
+            Identity::Other(IdType::ID_NULL, vec![]),
+        ];
+        for identity in cases {
+            let payload = IdPayload::from(identity.clone());
+            let data = payload.to_bytes().unwrap();
+            assert_eq!(IdPayload::try_from(&data[..]).unwrap(), payload);
+            assert_eq!(payload.identity(), Ok(identity));
+        }
+
+        let invalid = [
+            (IdType::ID_IPV4_ADDR, &b"\x01\x02\x03"[..]),
+            (IdType::ID_IPV6_ADDR, &[0; 4][..]),
+            (IdType::ID_FQDN, &b""[..]),
+            (IdType::ID_FQDN, &b"bad..example"[..]),
+            (IdType::ID_FQDN, &b"-bad.example"[..]),
+            (IdType::ID_FQDN, &b"bad\0example"[..]),
+            (IdType::ID_RFC822_ADDR, &b"example.com"[..]),
+            (IdType::ID_RFC822_ADDR, &b"@example.com"[..]),
+            (IdType::ID_RFC822_ADDR, &b"a b@example.com"[..]),
+        ];
+        for (id_type, data) in invalid {
+            let payload = IdPayload {
+                id_type: id_type.clone(),
+                reserved: [0; 3],
+                data: data.to_vec(),
+            };
+            assert_eq!(payload.identity(), Err(Error::InvalidIdentity(id_type)));
+        }
+    }
 }
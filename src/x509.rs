@@ -0,0 +1,416 @@
+use std::time::SystemTime;
+
+use rsa::pkcs8::DecodePublicKey;
+use x509_cert::Certificate;
+use x509_cert::der::Encode;
+use x509_cert::der::oid::db::rfc5280::{
+    ANY_EXTENDED_KEY_USAGE, ID_CE_AUTHORITY_KEY_IDENTIFIER, ID_CE_BASIC_CONSTRAINTS,
+    ID_CE_EXT_KEY_USAGE, ID_CE_KEY_USAGE, ID_CE_SUBJECT_ALT_NAME, ID_CE_SUBJECT_KEY_IDENTIFIER,
+    ID_KP_CLIENT_AUTH, ID_KP_SERVER_AUTH,
+};
+use x509_cert::der::oid::{AssociatedOid, ObjectIdentifier};
+use x509_cert::ext::pkix::name::GeneralName;
+use x509_cert::ext::pkix::{BasicConstraints, ExtendedKeyUsage, KeyUsage, SubjectAltName};
+
+use crate::cert::CertPayload;
+use crate::consts::*;
+use crate::error::Error;
+use crate::sig::{SignatureAlgorithm, VerifyingKey};
+use crate::types::{IdPayload, Identity};
+
+/// id-kp-ipsecIKE (RFC 4945 §5.1.3.12)
+const ID_KP_IPSEC_IKE: ObjectIdentifier = ObjectIdentifier::new_unwrap("1.3.6.1.5.5.7.3.17");
+
+/// Extensions that are understood, and may therefore be critical.
+const KNOWN_EXTENSIONS: [ObjectIdentifier; 6] = [
+    ID_CE_BASIC_CONSTRAINTS,
+    ID_CE_KEY_USAGE,
+    ID_CE_EXT_KEY_USAGE,
+    ID_CE_SUBJECT_ALT_NAME,
+    ID_CE_SUBJECT_KEY_IDENTIFIER,
+    ID_CE_AUTHORITY_KEY_IDENTIFIER,
+];
+
+/// Longest chain of intermediate CAs that is followed.
+const MAX_INTERMEDIATES: usize = 8;
+
+/// Source of the current time for validity checks.
+pub trait Clock {
+    fn now(&self) -> SystemTime;
+}
+
+/// The system's wall clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A fixed point in time.
+impl Clock for SystemTime {
+    fn now(&self) -> SystemTime {
+        *self
+    }
+}
+
+/// Verifies the certificates a peer sends in its CERT payloads against a
+/// store of trusted CA certificates (RFC 4945 §5, RFC 5280 §6).
+pub struct CertVerifier<C = SystemClock> {
+    trust_anchors: Vec<Certificate>,
+    clock: C,
+}
+
+impl CertVerifier {
+    pub fn new(trust_anchors: Vec<Certificate>) -> Self {
+        Self::with_clock(trust_anchors, SystemClock)
+    }
+}
+
+impl<C: Clock> CertVerifier<C> {
+    pub fn with_clock(trust_anchors: Vec<Certificate>, clock: C) -> Self {
+        CertVerifier {
+            trust_anchors,
+            clock,
+        }
+    }
+
+    /// Builds a chain from the end-entity certificate in the first CERT
+    /// payload, through the other X.509 certificates in any order, to a
+    /// trust anchor, and checks that the end entity may authenticate `id`.
+    /// Returns the key to verify the peer's AUTH payload with.
+    ///
+    /// Every failure is reported as AUTHENTICATION_FAILED.
+    pub fn verify(&self, certs: &[CertPayload], id: &IdPayload) -> Result<VerifyingKey, Error> {
+        let certs = certs
+            .iter()
+            .filter(|c| c.cert_encoding == CertificateEncoding::X509_CERTIFICATE_SIGNATURE)
+            .map(CertPayload::certificate)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(authentication_failed)?;
+        let (end_entity, intermediates) = certs.split_first().ok_or_else(failed)?;
+        let now = self.clock.now();
+
+        check_validity(end_entity, now)?;
+        check_end_entity(end_entity)?;
+        check_id(end_entity, id)?;
+
+        let mut current = end_entity;
+        for depth in 0..=MAX_INTERMEDIATES {
+            if let Some(anchor) = self.trust_anchors.iter().find(|a| issued_by(current, a)) {
+                check_validity(anchor, now)?;
+                return public_key(end_entity);
+            }
+            let Some(issuer) = intermediates.iter().find(|c| issued_by(current, c)) else {
+                break;
+            };
+            check_validity(issuer, now)?;
+            check_ca(issuer, depth)?;
+            current = issuer;
+        }
+        Err(failed())
+    }
+}
+
+fn failed() -> Error {
+    Error::Notify(NotifyType::AUTHENTICATION_FAILED)
+}
+
+fn authentication_failed<E>(_: E) -> Error {
+    failed()
+}
+
+fn public_key(certificate: &Certificate) -> Result<VerifyingKey, Error> {
+    let spki = certificate
+        .tbs_certificate
+        .subject_public_key_info
+        .to_der()
+        .map_err(authentication_failed)?;
+    VerifyingKey::from_public_key_der(&spki).map_err(authentication_failed)
+}
+
+/// Whether `issuer` names and signed `certificate`.
+fn issued_by(certificate: &Certificate, issuer: &Certificate) -> bool {
+    certificate.tbs_certificate.issuer == issuer.tbs_certificate.subject
+        && verify_signature(certificate, issuer).is_ok()
+}
+
+fn verify_signature(certificate: &Certificate, issuer: &Certificate) -> Result<(), Error> {
+    if certificate.signature_algorithm != certificate.tbs_certificate.signature {
+        return Err(failed());
+    }
+    let algorithm = certificate
+        .signature_algorithm
+        .to_der()
+        .map_err(authentication_failed)?;
+    let algorithm = SignatureAlgorithm::from_der(&algorithm).map_err(authentication_failed)?;
+    let tbs = certificate
+        .tbs_certificate
+        .to_der()
+        .map_err(authentication_failed)?;
+    let signature = certificate.signature.as_bytes().ok_or_else(failed)?;
+    public_key(issuer)?.verify(&algorithm, &tbs, signature)
+}
+
+fn check_validity(certificate: &Certificate, now: SystemTime) -> Result<(), Error> {
+    let validity = &certificate.tbs_certificate.validity;
+    if now < validity.not_before.to_system_time() || now > validity.not_after.to_system_time() {
+        return Err(failed());
+    }
+    Ok(())
+}
+
+/// The decoded extension of type `T`, rejecting any critical extension
+/// that is not understood.
+fn extension<'a, T>(certificate: &'a Certificate) -> Result<Option<T>, Error>
+where
+    T: x509_cert::der::Decode<'a> + AssociatedOid,
+{
+    let extensions = certificate.tbs_certificate.extensions.as_deref();
+    if extensions
+        .unwrap_or_default()
+        .iter()
+        .any(|e| e.critical && !KNOWN_EXTENSIONS.contains(&e.extn_id))
+    {
+        return Err(failed());
+    }
+    Ok(certificate
+        .tbs_certificate
+        .get::<T>()
+        .map_err(authentication_failed)?
+        .map(|(_, extension)| extension))
+}
+
+/// An intermediate CA with `depth` intermediates below it must be allowed
+/// to sign certificates by its basic constraints and key usage.
+fn check_ca(certificate: &Certificate, depth: usize) -> Result<(), Error> {
+    let constraints = extension::<BasicConstraints>(certificate)?.ok_or_else(failed)?;
+    if !constraints.ca
+        || constraints
+            .path_len_constraint
+            .is_some_and(|length| usize::from(length) < depth)
+    {
+        return Err(failed());
+    }
+    if extension::<KeyUsage>(certificate)?.is_some_and(|usage| !usage.key_cert_sign()) {
+        return Err(failed());
+    }
+    Ok(())
+}
+
+/// The end entity must not be a CA, must allow signatures if it restricts
+/// its key usage, and must be usable for IKE if it restricts its extended
+/// key usage: with id-kp-ipsecIKE or anyExtendedKeyUsage, or the TLS
+/// purposes many deployments issue instead.
+fn check_end_entity(certificate: &Certificate) -> Result<(), Error> {
+    if extension::<BasicConstraints>(certificate)?.is_some_and(|constraints| constraints.ca) {
+        return Err(failed());
+    }
+    if extension::<KeyUsage>(certificate)?
+        .is_some_and(|usage| !usage.digital_signature() && !usage.non_repudiation())
+    {
+        return Err(failed());
+    }
+    if let Some(ExtendedKeyUsage(purposes)) = extension(certificate)?
+        && !purposes.iter().any(|purpose| {
+            [
+                ID_KP_IPSEC_IKE,
+                ANY_EXTENDED_KEY_USAGE,
+                ID_KP_SERVER_AUTH,
+                ID_KP_CLIENT_AUTH,
+            ]
+            .contains(purpose)
+        })
+    {
+        return Err(failed());
+    }
+    Ok(())
+}
+
+/// Matches the identity against the subject alternative names or, for a
+/// Distinguished Name, the subject of the certificate.
+fn check_id(certificate: &Certificate, id: &IdPayload) -> Result<(), Error> {
+    let identity = id.identity().map_err(authentication_failed)?;
+    if let Identity::DerAsn1Dn(dn) = &identity {
+        let subject = certificate
+            .tbs_certificate
+            .subject
+            .to_der()
+            .map_err(authentication_failed)?;
+        return if *dn == subject {
+            Ok(())
+        } else {
+            Err(failed())
+        };
+    }
+    let names = extension::<SubjectAltName>(certificate)?.map_or(vec![], |san| san.0);
+    let matches = |name: &GeneralName| match (name, &identity) {
+        (GeneralName::DnsName(dns), Identity::Fqdn(fqdn)) => {
+            dns.as_str().eq_ignore_ascii_case(fqdn)
+        }
+        (GeneralName::Rfc822Name(email), Identity::Rfc822(address)) => {
+            email.as_str().eq_ignore_ascii_case(address)
+        }
+        (GeneralName::IpAddress(ip), Identity::Ipv4(addr)) => ip.as_bytes() == addr.octets(),
+        (GeneralName::IpAddress(ip), Identity::Ipv6(addr)) => ip.as_bytes() == addr.octets(),
+        _ => false,
+    };
+    if !names.iter().any(matches) {
+        return Err(failed());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use rsa::pkcs8::DecodePrivateKey;
+    use x509_cert::der::Decode;
+
+    use super::*;
+    use crate::auth::AuthPayload;
+    use crate::sig::SigningKey;
+
+    // The root CA signed the intermediate CA, which signed the end-entity
+    // certificates for vpn.example.com, all generated with OpenSSL.
+    const ROOT_CA: &[u8] = include_bytes!("testdata/certs/root_ca.der");
+    const OTHER_CA: &[u8] = include_bytes!("testdata/certs/other_ca.der");
+    const INTERMEDIATE_CA: &[u8] = include_bytes!("testdata/certs/intermediate_ca.der");
+    const VPN: &[u8] = include_bytes!("testdata/certs/vpn.der");
+
+    /// 2026-05-28, while all certificates are valid
+    fn now() -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_780_000_000)
+    }
+
+    fn cert(der: &[u8]) -> CertPayload {
+        CertPayload {
+            cert_encoding: CertificateEncoding::X509_CERTIFICATE_SIGNATURE,
+            data: der.to_vec(),
+        }
+    }
+
+    fn verifier(anchors: &[&[u8]], now: SystemTime) -> CertVerifier<SystemTime> {
+        let anchors = anchors
+            .iter()
+            .map(|der| Certificate::from_der(der).unwrap())
+            .collect();
+        CertVerifier::with_clock(anchors, now)
+    }
+
+    fn fqdn(name: &str) -> IdPayload {
+        IdPayload::from(Identity::Fqdn(name.to_string()))
+    }
+
+    #[test]
+    fn test_verify() {
+        let verifier = verifier(&[OTHER_CA, ROOT_CA], now());
+        let chain = [cert(VPN), cert(INTERMEDIATE_CA)];
+        let subject = Certificate::from_der(VPN)
+            .unwrap()
+            .tbs_certificate
+            .subject
+            .to_der()
+            .unwrap();
+        for id in [
+            fqdn("vpn.example.com"),
+            fqdn("VPN.Example.COM"),
+            IdPayload::from(Identity::Rfc822("admin@example.com".to_string())),
+            IdPayload::from(Identity::Ipv4("192.0.2.1".parse().unwrap())),
+            IdPayload::from(Identity::Ipv6("2001:db8::1".parse().unwrap())),
+            IdPayload::from(Identity::DerAsn1Dn(subject)),
+        ] {
+            assert!(verifier.verify(&chain, &id).is_ok(), "{id:?}");
+        }
+
+        // the returned key verifies AUTH payloads made with the end entity's key
+        let key = verifier.verify(&chain, &fqdn("vpn.example.com")).unwrap();
+        let signing_key = SigningKey::from_pkcs8_der(include_bytes!("testdata/keys/p256.der"));
+        assert_eq!(key, signing_key.as_ref().unwrap().verifying_key());
+        let auth = AuthPayload::sign(
+            signing_key.as_ref().unwrap(),
+            &SignatureAlgorithm::Ecdsa(HashAlgorithm::SHA2_256),
+            b"signed octets",
+        )
+        .unwrap();
+        assert_eq!(auth.verify(&key, b"signed octets"), Ok(()));
+
+        // intermediates in any order, among unrelated certificates
+        let unordered = [cert(VPN), cert(OTHER_CA), cert(INTERMEDIATE_CA)];
+        assert!(
+            verifier
+                .verify(&unordered, &fqdn("vpn.example.com"))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_verify_failures() {
+        let failed = || Some(Error::Notify(NotifyType::AUTHENTICATION_FAILED));
+        let id = fqdn("vpn.example.com");
+        let chain = [cert(VPN), cert(INTERMEDIATE_CA)];
+        let verifier = verifier(&[ROOT_CA], now());
+
+        // no chain to a trust anchor
+        assert_eq!(verifier.verify(&[], &id).err(), failed());
+        assert_eq!(verifier.verify(&chain[..1], &id).err(), failed());
+        let untrusted = self::verifier(&[OTHER_CA], now());
+        assert_eq!(untrusted.verify(&chain, &id).err(), failed());
+
+        // outside the validity period
+        for secs in [1_760_000_000, 1_840_000_000] {
+            let at = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+            let verifier = self::verifier(&[ROOT_CA], at);
+            assert_eq!(verifier.verify(&chain, &id).err(), failed());
+        }
+
+        // identities the certificate does not name
+        for id in [
+            fqdn("other.example.com"),
+            IdPayload::from(Identity::Ipv4("192.0.2.2".parse().unwrap())),
+            IdPayload::from(Identity::DerAsn1Dn(vec![0x30, 0])),
+            IdPayload::from(Identity::KeyId(b"vpn.example.com".to_vec())),
+        ] {
+            assert_eq!(verifier.verify(&chain, &id).err(), failed());
+        }
+
+        // key usage without digitalSignature, extended key usage without IKE
+        for der in [
+            &include_bytes!("testdata/certs/vpn_key_encipherment.der")[..],
+            include_bytes!("testdata/certs/vpn_email_protection.der"),
+        ] {
+            let chain = [cert(der), cert(INTERMEDIATE_CA)];
+            assert_eq!(verifier.verify(&chain, &id).err(), failed());
+        }
+
+        // a CA certificate as end entity
+        let intermediate = Certificate::from_der(INTERMEDIATE_CA).unwrap();
+        let dn = IdPayload::from(Identity::DerAsn1Dn(
+            intermediate.tbs_certificate.subject.to_der().unwrap(),
+        ));
+        assert_eq!(
+            verifier.verify(&[cert(INTERMEDIATE_CA)], &dn).err(),
+            failed()
+        );
+
+        // a corrupted signature
+        let mut tampered = VPN.to_vec();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert_eq!(
+            verifier
+                .verify(&[cert(&tampered), cert(INTERMEDIATE_CA)], &id)
+                .err(),
+            failed()
+        );
+        assert_eq!(
+            verifier
+                .verify(&[cert(b"not DER"), cert(INTERMEDIATE_CA)], &id)
+                .err(),
+            failed()
+        );
+    }
+}